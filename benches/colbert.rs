@@ -0,0 +1,153 @@
+//! Throughput benchmarks of the encode, scoring and pooling paths.
+//!
+//! The models are randomly initialized and generated on the fly with the test fixtures,
+//! so the benchmarks run without network access. Run them with `cargo bench`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use candle_core::{Device, Tensor, D};
+use common::{Architecture, WORDS};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pylate_rs::{hierarchical_pooling, ColBERT};
+use std::{fs, path::PathBuf};
+
+/// The hidden size of the benchmark backbones, closer to real models than the test fixtures.
+const HIDDEN_SIZE: usize = 128;
+/// The dimension of the token embeddings.
+const EMBEDDING_DIM: usize = 128;
+/// The number of documents encoded per iteration.
+const NUM_DOCUMENTS: usize = 64;
+
+/// Writes a fixture model with a wider backbone that accepts sequences of up to 512 tokens.
+fn write_model(name: &str, architecture: Architecture) -> PathBuf {
+    let dir = common::write_colbert(name, architecture, false).expect("failed to write fixture");
+    let mut config: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("config.json")).unwrap()).unwrap();
+    config["hidden_size"] = HIDDEN_SIZE.into();
+    config["num_attention_heads"] = 4.into();
+    config["intermediate_size"] = (4 * HIDDEN_SIZE).into();
+    config["max_position_embeddings"] = 512.into();
+    fs::write(
+        dir.join("config.json"),
+        serde_json::to_vec(&config).unwrap(),
+    )
+    .unwrap();
+    common::write_weights(&dir, &config).unwrap();
+    common::write_dense(&dir.join("1_Dense"), HIDDEN_SIZE, EMBEDDING_DIM).unwrap();
+    dir
+}
+
+/// Returns `count` texts of `num_words` words each.
+///
+/// Punctuation is left out: the skiplist would drop it from the embeddings, making
+/// documents shorter than their length label.
+fn texts(count: usize, num_words: usize) -> Vec<String> {
+    let words: Vec<&str> = WORDS
+        .iter()
+        .copied()
+        .filter(|word| word.chars().all(char::is_alphabetic))
+        .collect();
+    (0..count)
+        .map(|i| {
+            (0..num_words)
+                .map(|j| words[(i + j) % words.len()])
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+/// Returns random L2-normalized embeddings of shape `[count, num_tokens, EMBEDDING_DIM]`.
+fn random_embeddings(count: usize, num_tokens: usize) -> Tensor {
+    let embeddings =
+        Tensor::randn(0f32, 1f32, (count, num_tokens, EMBEDDING_DIM), &Device::Cpu).unwrap();
+    embeddings
+        .broadcast_div(
+            &embeddings
+                .sqr()
+                .unwrap()
+                .sum_keepdim(D::Minus1)
+                .unwrap()
+                .sqrt()
+                .unwrap(),
+        )
+        .unwrap()
+}
+
+/// Documents encoded per second for each backbone, batch size and document length.
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(NUM_DOCUMENTS as u64));
+
+    for (name, architecture) in [
+        ("bert", Architecture::Bert),
+        ("modernbert", Architecture::ModernBert),
+    ] {
+        let path = write_model(&format!("bench-{name}"), architecture);
+        for document_length in [32, 128, 512] {
+            // Leave room for the [CLS], [D] and [SEP] tokens.
+            let documents = texts(NUM_DOCUMENTS, document_length - 3);
+            for batch_size in [1, 16, 64] {
+                let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+                    .with_document_length(document_length)
+                    .with_batch_size(batch_size)
+                    .try_into()
+                    .unwrap();
+                group.bench_with_input(
+                    BenchmarkId::new(
+                        format!("{name}/length-{document_length}"),
+                        format!("batch-{batch_size}"),
+                    ),
+                    &documents,
+                    |b, documents| b.iter(|| model.encode(documents, false).unwrap()),
+                );
+            }
+        }
+    }
+    group.finish();
+}
+
+/// Documents scored per second with MaxSim for growing collection sizes.
+fn similarity(c: &mut Criterion) {
+    let path = write_model("bench-similarity", Architecture::Bert);
+    let model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into().unwrap();
+    let queries = random_embeddings(1, 32);
+
+    let mut group = c.benchmark_group("similarity");
+    group.sample_size(10);
+    for num_documents in [100, 1_000, 5_000] {
+        let documents = random_embeddings(num_documents, 64);
+        group.throughput(Throughput::Elements(num_documents as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_documents),
+            &documents,
+            |b, documents| b.iter(|| model.similarity(&queries, documents).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+/// Time to pool a single document for several lengths and pool factors.
+fn pooling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hierarchical_pooling");
+    group.throughput(Throughput::Elements(1));
+    for num_tokens in [64, 256] {
+        let document = random_embeddings(1, num_tokens);
+        for pool_factor in [2, 4] {
+            group.bench_with_input(
+                BenchmarkId::new(
+                    format!("tokens-{num_tokens}"),
+                    format!("factor-{pool_factor}"),
+                ),
+                &document,
+                |b, document| b.iter(|| hierarchical_pooling(document, pool_factor).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, similarity, pooling);
+criterion_main!(benches);
@@ -1,40 +1,153 @@
-use anyhow::Result;
-use candle_core::Device;
-use pylate_rs::{hierarchical_pooling, ColBERT};
+#[path = "../cli/args.rs"]
+mod args;
+#[path = "../cli/corpus.rs"]
+mod corpus;
+
+use anyhow::{bail, Result};
+use args::ModelArgs;
+use clap::Parser;
+use corpus::read_records;
+use pylate_rs::{evaluate, read_qrels, unpad_embeddings, write_trec_run, EmbeddingCollection, Run};
+use serde_json::json;
+use std::{path::PathBuf, time::Instant};
+
+/// Evaluate a ColBERT model on a BEIR-style dataset stored on disk.
+///
+/// The dataset directory holds `corpus.jsonl` and `queries.jsonl` files with `_id`,
+/// `text` and optional `title` fields, and `qrels/<split>.tsv` relevance judgments.
+#[derive(Parser)]
+#[command(name = "benchmark", about)]
+struct Cli {
+    #[command(flatten)]
+    model: ModelArgs,
+    /// The BEIR dataset directory.
+    #[arg(long)]
+    data: PathBuf,
+    /// The qrels split to evaluate on.
+    #[arg(long, default_value = "test")]
+    split: String,
+    /// A qrels file in BEIR TSV or TREC format, instead of `qrels/<split>.tsv`.
+    #[arg(long)]
+    qrels: Option<PathBuf>,
+    /// The number of documents retrieved per query.
+    #[arg(long, default_value_t = 100)]
+    top_k: usize,
+    /// The cutoffs at which nDCG, MRR and Recall are reported.
+    #[arg(long, value_delimiter = ',', default_value = "1,10,100")]
+    cutoffs: Vec<usize>,
+    /// The number of documents encoded at once.
+    #[arg(long, default_value_t = 1024)]
+    chunk_size: usize,
+    /// Write the retrieved documents to this file in TREC run format.
+    #[arg(long)]
+    run_output: Option<PathBuf>,
+}
 
 fn main() -> Result<()> {
-    let device = Device::Cpu;
-    // let device = Device::new_cuda(0)?; // Uncomment this line to use GPU if available
-    // let device = Device::new_metal(0)?; // Uncomment this line to use Apple Silicon GPU if available
+    let cli = Cli::parse();
+
+    let qrels_path = cli
+        .qrels
+        .clone()
+        .unwrap_or_else(|| cli.data.join("qrels").join(format!("{}.tsv", cli.split)));
+    let qrels = read_qrels(&qrels_path)?;
 
-    let mut model: ColBERT = ColBERT::from("lightonai/colbertv2.0")
-        .with_device(device)
-        .try_into()?;
+    // BEIR documents are encoded as their title followed by their text.
+    let documents = read_records(&cli.data.join("corpus.jsonl"), "_id", "text")?;
+    let document_texts: Vec<String> = documents
+        .iter()
+        .map(|document| match document.metadata["title"].as_str() {
+            Some(title) if !title.is_empty() => format!("{} {}", title, document.text),
+            _ => document.text.clone(),
+        })
+        .collect();
 
-    let query_sentences = vec!["Query 1.".to_string(), "Query 2.".to_string()];
-    let document_sentences = vec!["Document 1.".to_string(), "Document 2.".to_string()];
+    // Only the judged queries are evaluated.
+    let queries: Vec<_> = read_records(&cli.data.join("queries.jsonl"), "_id", "text")?
+        .into_iter()
+        .filter(|query| qrels.contains_key(&query.id))
+        .collect();
+    if documents.is_empty() || queries.is_empty() {
+        bail!(
+            "The corpus or the judged queries of {} are empty",
+            cli.data.display()
+        );
+    }
+    eprintln!(
+        "Loaded {} documents and {} judged queries from {}",
+        documents.len(),
+        queries.len(),
+        cli.data.display()
+    );
 
-    let query_embeddings = model.encode(&query_sentences, true)?;
-    let document_embeddings = model.encode(&document_sentences, false)?;
+    let start = Instant::now();
+    let mut model = cli.model.load()?;
+    let loading_time = start.elapsed().as_secs_f64();
 
-    let similarities = model.similarity(&query_embeddings, &document_embeddings)?;
-    let score = similarities.data[0][0];
+    let start = Instant::now();
+    let mut collection = EmbeddingCollection::new();
+    for (chunk, texts) in documents
+        .chunks(cli.chunk_size.max(1))
+        .zip(document_texts.chunks(cli.chunk_size.max(1)))
+    {
+        let embeddings = unpad_embeddings(&model.encode(texts, false)?)?;
+        for (document, embeddings) in chunk.iter().zip(embeddings) {
+            collection.push(document.id.clone(), embeddings, serde_json::Value::Null)?;
+        }
+        eprintln!("Encoded {}/{} documents", collection.len(), documents.len());
+    }
+    let document_time = start.elapsed().as_secs_f64();
 
-    println!("Similarity score: {}", score);
+    let start = Instant::now();
+    let query_texts: Vec<String> = queries.iter().map(|query| query.text.clone()).collect();
+    let queries_embeddings = model.encode(&query_texts, true)?;
+    let query_time = start.elapsed().as_secs_f64();
 
-    // Hierarchical pooling reduces the dimensionality of document embeddings.
-    // By setting the `pooling_factor` to 2, the number of token embeddings
-    // is divided by 2. This can improve computational efficiency for downstream
-    // tasks like similarity calculations by reducing the data size. You can
-    // experiment with different `pooling_factor` values (e.g., 3, 4) based
-    // on your specific requirements and the desired trade-off between
-    // dimensionality reduction and information retention.
-    let document_embeddings = hierarchical_pooling(&document_embeddings, 2)?;
+    let start = Instant::now();
+    let results = collection.search(&queries_embeddings, cli.top_k)?;
+    let search_time = start.elapsed().as_secs_f64();
 
-    let similarities = model.similarity(&query_embeddings, &document_embeddings)?;
-    let score = similarities.data[0][0];
+    let run: Run = queries
+        .iter()
+        .zip(results)
+        .map(|(query, ranked)| {
+            let ranking = ranked
+                .into_iter()
+                .map(|document| (collection.ids()[document.index].clone(), document.score))
+                .collect();
+            (query.id.clone(), ranking)
+        })
+        .collect();
+    if let Some(path) = &cli.run_output {
+        write_trec_run(&run, path, "pylate-rs")?;
+    }
 
-    println!("Similarity score after hierarchical pooling: {}", score);
+    let report = evaluate(&run, &qrels, &cli.cutoffs);
+    for &k in &cli.cutoffs {
+        eprintln!(
+            "nDCG@{k}: {:.4}  MRR@{k}: {:.4}  Recall@{k}: {:.4}",
+            report.ndcg[&k], report.mrr[&k], report.recall[&k]
+        );
+    }
+    eprintln!("MAP: {:.4}", report.map);
+    eprintln!(
+        "Documents per second: {:.2}  Queries per second: {:.2}  Search time: {:.2}s",
+        documents.len() as f64 / document_time,
+        queries.len() as f64 / query_time,
+        search_time
+    );
 
+    let output = json!({
+        "model": cli.model.model,
+        "pool_factor": model.info().pool_factor,
+        "metrics": report,
+        "throughput": {
+            "loading_time": loading_time,
+            "documents_per_second": documents.len() as f64 / document_time,
+            "queries_per_second": queries.len() as f64 / query_time,
+            "search_time": search_time,
+        },
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
     Ok(())
 }
@@ -0,0 +1,16 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Generates the C header of the `ffi` module into `OUT_DIR`, leaving the source tree
+    // untouched. The `ffi` tests check that `include/pylate_rs.h` matches it.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("Unable to generate the C header")
+            .write_to_file(std::path::Path::new(&out_dir).join("pylate_rs.h"));
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{bail, Result};
+use candle_core::Device;
+use clap::Args;
+use pylate_rs::{ColBERT, EmbeddingCache};
+use std::path::PathBuf;
+
+/// Options forwarded to the `ColbertBuilder`.
+#[derive(Args)]
+pub struct ModelArgs {
+    /// The Hugging Face repository or local directory of the model.
+    #[arg(long, short, default_value = "lightonai/GTE-ModernColBERT-v1")]
+    pub model: String,
+    /// The device to run on: `cpu`, `cuda`, `cuda:<index>` or `mps`.
+    #[arg(long, default_value = "cpu")]
+    device: String,
+    /// The maximum number of tokens of a query.
+    #[arg(long)]
+    query_length: Option<usize>,
+    /// The maximum number of tokens of a document.
+    #[arg(long)]
+    document_length: Option<usize>,
+    /// The number of sentences encoded per forward pass.
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// The prefix prepended to queries.
+    #[arg(long)]
+    query_prefix: Option<String>,
+    /// The prefix prepended to documents.
+    #[arg(long)]
+    document_prefix: Option<String>,
+    /// Divide the number of document token embeddings by this factor with hierarchical pooling.
+    #[arg(long)]
+    pool_factor: Option<usize>,
+    /// Keep the embeddings of this many sentences in memory and skip encoding them again.
+    #[arg(long)]
+    cache_size: Option<usize>,
+    /// Also cache embeddings in this directory, across runs.
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+}
+
+impl ModelArgs {
+    pub fn load(&self) -> Result<ColBERT> {
+        let mut builder = ColBERT::from(&self.model).with_device(parse_device(&self.device)?);
+        if let Some(query_length) = self.query_length {
+            builder = builder.with_query_length(query_length);
+        }
+        if let Some(document_length) = self.document_length {
+            builder = builder.with_document_length(document_length);
+        }
+        if let Some(batch_size) = self.batch_size {
+            builder = builder.with_batch_size(batch_size);
+        }
+        if let Some(query_prefix) = &self.query_prefix {
+            builder = builder.with_query_prefix(query_prefix.clone());
+        }
+        if let Some(document_prefix) = &self.document_prefix {
+            builder = builder.with_document_prefix(document_prefix.clone());
+        }
+        if let Some(pool_factor) = self.pool_factor {
+            builder = builder.with_pool_factor(pool_factor);
+        }
+        if self.cache_size.is_some() || self.cache_dir.is_some() {
+            let mut cache = EmbeddingCache::new(self.cache_size.unwrap_or(0));
+            if let Some(cache_dir) = &self.cache_dir {
+                cache = cache.with_directory(cache_dir)?;
+            }
+            builder = builder.with_cache(cache);
+        }
+        Ok(builder.try_into()?)
+    }
+}
+
+/// Parses `cpu`, `cuda`, `cuda:<index>` or `mps` into a device.
+pub fn parse_device(device: &str) -> Result<Device> {
+    Ok(match device {
+        "cpu" => Device::Cpu,
+        "mps" => Device::new_metal(0)?,
+        "cuda" => Device::new_cuda(0)?,
+        _ => match device.strip_prefix("cuda:") {
+            Some(index) => Device::new_cuda(index.parse()?)?,
+            None => bail!("Unknown device `{}`", device),
+        },
+    })
+}
@@ -0,0 +1,112 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// A single entry of a corpus or query file.
+pub struct Record {
+    pub id: String,
+    pub text: String,
+    pub metadata: Value,
+}
+
+/// Reads a JSONL, JSON or TSV file, depending on its extension.
+///
+/// JSONL lines, and the elements of the array held by a JSON file, are objects holding
+/// an id and a text field, every other field is kept as metadata. TSV lines are
+/// `id<TAB>text` and extra columns are ignored. Blank lines are skipped. Objects without
+/// the id field and TSV lines with a single column use their 0-based position among the
+/// records as id.
+pub fn read_records(path: &Path, id_field: &str, text_field: &str) -> Result<Vec<Record>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut records = Vec::new();
+    match extension {
+        "json" => {
+            let objects: Vec<Map<String, Value>> = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("{} is not a JSON array of objects", path.display()))?;
+            for (index, fields) in objects.into_iter().enumerate() {
+                let record =
+                    parse_json_object(fields, index, id_field, text_field).with_context(|| {
+                        format!("Invalid record {} of {}", index + 1, path.display())
+                    })?;
+                records.push(record);
+            }
+        },
+        "jsonl" | "tsv" => {
+            for (line_number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = if extension == "jsonl" {
+                    parse_json_line(&line, records.len(), id_field, text_field)
+                } else {
+                    Ok(parse_tsv_line(&line, records.len()))
+                };
+                records.push(record.with_context(|| {
+                    format!(
+                        "Invalid record on line {} of {}",
+                        line_number + 1,
+                        path.display()
+                    )
+                })?);
+            }
+        },
+        _ => bail!(
+            "Unsupported input format for {}, expected a .jsonl, .json or .tsv file",
+            path.display()
+        ),
+    }
+    Ok(records)
+}
+
+/// Parses a JSONL line, `index` being the fallback id.
+fn parse_json_line(line: &str, index: usize, id_field: &str, text_field: &str) -> Result<Record> {
+    parse_json_object(serde_json::from_str(line)?, index, id_field, text_field)
+}
+
+/// Builds a record from a JSON object, `index` being the fallback id.
+fn parse_json_object(
+    mut fields: Map<String, Value>,
+    index: usize,
+    id_field: &str,
+    text_field: &str,
+) -> Result<Record> {
+    let text = match fields.remove(text_field) {
+        Some(Value::String(text)) => text,
+        _ => return Err(anyhow!("Missing string field `{}`", text_field)),
+    };
+    let id = match fields.remove(id_field) {
+        Some(Value::String(id)) => id,
+        Some(Value::Number(id)) => id.to_string(),
+        _ => index.to_string(),
+    };
+    let metadata = if fields.is_empty() {
+        Value::Null
+    } else {
+        Value::Object(fields)
+    };
+    Ok(Record { id, text, metadata })
+}
+
+/// Parses a TSV line, `index` being the fallback id.
+fn parse_tsv_line(line: &str, index: usize) -> Record {
+    let mut columns = line.split('\t');
+    let (id, text) = match (columns.next(), columns.next()) {
+        (Some(id), Some(text)) => (id.to_string(), text),
+        _ => (index.to_string(), line),
+    };
+    Record {
+        id,
+        text: text.to_string(),
+        metadata: Value::Null,
+    }
+}
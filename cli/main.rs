@@ -0,0 +1,213 @@
+mod args;
+mod corpus;
+
+use anyhow::{bail, Result};
+use args::ModelArgs;
+use candle_core::Tensor;
+use clap::{Args, Parser, Subcommand};
+use corpus::{read_records, Record};
+use pylate_rs::{ColBERT, EmbeddingCollection, EmbeddingStore};
+#[cfg(feature = "server")]
+use pylate_rs::{EmbeddingServer, ServerConfig};
+use serde_json::json;
+use std::{
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Encode, index and search text collections with ColBERT models.
+#[derive(Parser)]
+#[command(name = "pylate-rs", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode a file and print the token embeddings of each entry as JSONL.
+    Encode {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        /// Encode the entries as queries instead of documents.
+        #[arg(long)]
+        is_query: bool,
+    },
+    /// Encode a corpus into an embeddings file that can be searched.
+    Index {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        /// The embeddings file to write.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// The number of documents encoded before their embeddings are moved to the collection.
+        #[arg(long, default_value_t = 1024)]
+        chunk_size: usize,
+    },
+    /// Search an embeddings file with queries and print the top-k results as JSONL.
+    Search {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        /// The embeddings file written by the `index` command.
+        #[arg(long)]
+        index: PathBuf,
+        /// The number of documents returned per query.
+        #[arg(long, short = 'k', default_value_t = 10)]
+        top_k: usize,
+    },
+    /// Serve the model over HTTP on a local address.
+    #[cfg(feature = "server")]
+    Serve {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+        /// The number of threads parsing HTTP requests.
+        #[arg(long, default_value_t = 4)]
+        threads: usize,
+        /// The maximum number of sentences encoded together across concurrent requests.
+        #[arg(long, default_value_t = 64)]
+        max_batch_size: usize,
+        /// How long, in milliseconds, a request waits for others to join its batch.
+        #[arg(long, default_value_t = 5)]
+        batch_timeout_ms: u64,
+    },
+}
+
+/// The input file and the fields to read from JSON records.
+#[derive(Args)]
+struct InputArgs {
+    /// A `.jsonl` or `.tsv` file with one entry per line, or a `.json` array of entries.
+    #[arg(long, short)]
+    input: PathBuf,
+    /// The JSON field holding the id of an entry.
+    #[arg(long, default_value = "id")]
+    id_field: String,
+    /// The JSON field holding the text of an entry.
+    #[arg(long, default_value = "text")]
+    text_field: String,
+}
+
+impl InputArgs {
+    fn read(&self) -> Result<Vec<Record>> {
+        let records = read_records(&self.input, &self.id_field, &self.text_field)?;
+        if records.is_empty() {
+            bail!("{} does not contain any entry", self.input.display());
+        }
+        Ok(records)
+    }
+}
+
+/// Encodes texts and returns one unpadded `[n_tokens, embedding_dim]` tensor per text,
+/// documents being pooled with the pool factor of the model.
+fn encode(model: &mut ColBERT, records: &[Record], is_query: bool) -> Result<Vec<Tensor>> {
+    let texts: Vec<String> = records.iter().map(|record| record.text.clone()).collect();
+    let embeddings = model.encode(&texts, is_query)?;
+    Ok(model.split_embeddings(&embeddings, is_query)?)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    match cli.command {
+        Command::Encode {
+            model,
+            input,
+            is_query,
+        } => {
+            let records = input.read()?;
+            let mut model = model.load()?;
+            for (record, embeddings) in records.iter().zip(encode(&mut model, &records, is_query)?)
+            {
+                let line = json!({
+                    "id": record.id,
+                    "embeddings": embeddings.to_vec2::<f32>()?,
+                });
+                writeln!(stdout, "{}", line)?;
+            }
+        },
+        Command::Index {
+            model,
+            input,
+            output,
+            chunk_size,
+        } => {
+            let records = input.read()?;
+            let mut model = model.load()?;
+            let mut collection = EmbeddingCollection::new();
+            for chunk in records.chunks(chunk_size.max(1)) {
+                for (record, embeddings) in chunk.iter().zip(encode(&mut model, chunk, false)?) {
+                    collection.push(record.id.clone(), embeddings, record.metadata.clone())?;
+                }
+                eprintln!("Encoded {}/{} documents", collection.len(), records.len());
+            }
+            collection.save(&output)?;
+            eprintln!(
+                "Wrote {} documents to {}",
+                collection.len(),
+                output.display()
+            );
+        },
+        Command::Search {
+            model,
+            input,
+            index,
+            top_k,
+        } => {
+            let store = EmbeddingStore::open(&index)?;
+            let records = input.read()?;
+            let mut model = model.load()?;
+            let texts: Vec<String> = records.iter().map(|record| record.text.clone()).collect();
+            let queries_embeddings = model.encode(&texts, true)?;
+
+            let results = store.search(&queries_embeddings, top_k)?;
+            for (record, ranked) in records.iter().zip(results) {
+                let results: Vec<_> = ranked
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, document)| {
+                        json!({
+                            "id": store.ids()[document.index],
+                            "score": document.score,
+                            "rank": rank + 1,
+                            "metadata": store.metadata(document.index),
+                        })
+                    })
+                    .collect();
+                let line = json!({ "query_id": record.id, "results": results });
+                writeln!(stdout, "{}", line)?;
+            }
+        },
+        #[cfg(feature = "server")]
+        Command::Serve {
+            model,
+            addr,
+            threads,
+            max_batch_size,
+            batch_timeout_ms,
+        } => {
+            let config = ServerConfig {
+                model_id: Some(model.model.clone()),
+                num_threads: threads,
+                max_batch_size,
+                batch_timeout: std::time::Duration::from_millis(batch_timeout_ms),
+            };
+            let server = EmbeddingServer::start(model.load()?, addr.as_str(), config)?;
+            if let Some(addr) = server.addr() {
+                eprintln!("Listening on http://{}", addr);
+            }
+            server.join();
+        },
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,14 @@
+//! Writes the fixture ColBERT model of the integration tests and prints its path.
+//!
+//! The Python tests load this model so that they run without network access.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::Architecture;
+
+fn main() -> anyhow::Result<()> {
+    let path = common::write_colbert("python", Architecture::ModernBert, false)?;
+    println!("{}", path.display());
+    Ok(())
+}
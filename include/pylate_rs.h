@@ -0,0 +1,193 @@
+/* Generated by cbindgen from src/ffi.rs with `make ffi-header`. Do not edit. */
+
+#ifndef PYLATE_RS_H
+#define PYLATE_RS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The call succeeded.
+ */
+#define COLBERT_OK 0
+
+/**
+ * The call failed, see `colbert_last_error`.
+ */
+#define COLBERT_ERROR -1
+
+/**
+ * A caller-provided buffer cannot hold the output, see `colbert_encode_into`.
+ */
+#define COLBERT_BUFFER_TOO_SMALL -2
+
+/**
+ * A loaded ColBERT model. Calls on the same model from several threads are serialized.
+ */
+typedef struct ColbertModel ColbertModel;
+
+/**
+ * Ragged embeddings allocated by the library, released with `colbert_embeddings_free`.
+ */
+typedef struct ColbertEmbeddings {
+  /**
+   * The `[sum(lengths), dim]` rows of all the sentences, row-major.
+   */
+  float *data;
+  /**
+   * The number of rows of each sentence.
+   */
+  size_t *lengths;
+  /**
+   * The number of sentences.
+   */
+  size_t num_sentences;
+  /**
+   * The dimension of the embeddings.
+   */
+  size_t dim;
+} ColbertEmbeddings;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns the message of the last error raised on the calling thread, or null if no
+ * call has failed yet. The string is owned by the library and remains valid until the
+ * next failing call on the same thread.
+ */
+const char *colbert_last_error(void);
+
+/**
+ * Loads a ColBERT model from a local directory on the CPU.
+ *
+ * `options` is either null or a JSON object overriding the settings of the model, with
+ * the same fields as the options of the WASM constructor, e.g.
+ * `{"query_length": 32, "pool_factor": 2}`. Returns null on failure.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string and `options` null or a NUL-terminated string.
+ */
+struct ColbertModel *colbert_model_load(const char *path,
+                                        const char *options);
+
+/**
+ * Releases a model returned by `colbert_model_load`. Does nothing if `model` is null.
+ *
+ * # Safety
+ *
+ * `model` must be null or a model that has not been released yet.
+ */
+void colbert_model_free(struct ColbertModel *model);
+
+/**
+ * Returns the dimension of the token embeddings of a model, or 0 if `model` is null.
+ *
+ * # Safety
+ *
+ * `model` must be null or a model returned by `colbert_model_load`.
+ */
+size_t colbert_model_embedding_dim(const struct ColbertModel *model);
+
+/**
+ * Encodes sentences into buffers allocated by the library.
+ *
+ * Documents are returned without padding rows and pooled with the pool factor of the
+ * model. Queries keep their expansion tokens, and so have the same number of rows
+ * each, unless query expansion is disabled. On success, `out` must be
+ * released with `colbert_embeddings_free`.
+ *
+ * # Safety
+ *
+ * `model` must be a model returned by `colbert_model_load`, `sentences` must point to
+ * `num_sentences` NUL-terminated strings and `out` must be valid for writes.
+ */
+int32_t colbert_encode(struct ColbertModel *model,
+                       const char *const *sentences,
+                       size_t num_sentences,
+                       bool is_query,
+                       struct ColbertEmbeddings *out);
+
+/**
+ * Encodes sentences into buffers provided by the caller.
+ *
+ * The number of rows of each sentence is written to `lengths`, which holds
+ * `num_sentences` elements, and the rows to `data`, which holds `capacity` floats.
+ * When `data` is too small, nothing is written to it and `COLBERT_BUFFER_TOO_SMALL` is
+ * returned. In every case where encoding succeeds, the number of floats of the output
+ * is written to `required` unless it is null.
+ *
+ * # Safety
+ *
+ * `model` must be a model returned by `colbert_model_load`, `sentences` must point to
+ * `num_sentences` NUL-terminated strings, `data` to `capacity` floats, `lengths` to
+ * `num_sentences` elements and `required` must be null or valid for writes.
+ */
+int32_t colbert_encode_into(struct ColbertModel *model,
+                            const char *const *sentences,
+                            size_t num_sentences,
+                            bool is_query,
+                            float *data,
+                            size_t capacity,
+                            size_t *lengths,
+                            size_t *required);
+
+/**
+ * Releases the buffers of embeddings returned by the library and resets `embeddings`.
+ * Does nothing if `embeddings` is null.
+ *
+ * # Safety
+ *
+ * `embeddings` must be null or filled by `colbert_encode` or
+ * `colbert_hierarchical_pooling` and not released yet.
+ */
+void colbert_embeddings_free(struct ColbertEmbeddings *embeddings);
+
+/**
+ * Computes the MaxSim score of every query against every document.
+ *
+ * Queries and documents are ragged embeddings of dimension `dim`, such as the output
+ * of `colbert_encode`. The `[num_queries, num_documents]` scores are written row-major
+ * to `scores`.
+ *
+ * # Safety
+ *
+ * `queries` and `documents` must hold the rows given by their lengths, the lengths
+ * arrays must hold `num_queries` and `num_documents` elements and `scores` must hold
+ * `num_queries * num_documents` floats.
+ */
+int32_t colbert_maxsim(const float *queries,
+                       const size_t *query_lengths,
+                       size_t num_queries,
+                       const float *documents,
+                       const size_t *document_lengths,
+                       size_t num_documents,
+                       size_t dim,
+                       float *scores);
+
+/**
+ * Reduces the number of rows of each document by `pool_factor` with hierarchical
+ * pooling, writing the pooled embeddings to buffers allocated by the library.
+ *
+ * On success, `out` must be released with `colbert_embeddings_free`.
+ *
+ * # Safety
+ *
+ * `embeddings` must hold the rows given by `lengths`, which holds `num_documents`
+ * elements, and `out` must be valid for writes.
+ */
+int32_t colbert_hierarchical_pooling(const float *embeddings,
+                                     const size_t *lengths,
+                                     size_t num_documents,
+                                     size_t dim,
+                                     size_t pool_factor,
+                                     struct ColbertEmbeddings *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PYLATE_RS_H */
@@ -1,3 +1,5 @@
+from pylate_rs.pylate_rs import CancellationToken, hierarchical_pooling
+
 from .colbert import ColBERT
 
-__all__ = ["ColBERT"]
+__all__ = ["CancellationToken", "ColBERT", "hierarchical_pooling"]
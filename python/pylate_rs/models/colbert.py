@@ -1,7 +1,10 @@
 from __future__ import annotations
 
+from typing import Callable
+
 import numpy as np
 from pylate_rs.pylate_rs import (
+    CancellationToken,
     PyColBERT,
 )
 
@@ -31,6 +34,12 @@ class ColBERT:
             model config.
         mask_token:
             The mask token used for padding queries. Defaults to "[MASK]".
+        skiplist_words:
+            The words whose token embeddings are removed from documents.
+            Defaults to skiplist_words in the model config, or punctuation.
+        pool_factor:
+            The factor by which `encode` pools document embeddings when no
+            pool_factor is passed to it. Defaults to 1, which disables pooling.
 
     """
 
@@ -46,6 +55,8 @@ class ColBERT:
         query_prefix: str | None = None,
         document_prefix: str | None = None,
         mask_token: str = "[MASK]",  # noqa: S107
+        skiplist_words: list[str] | None = None,
+        pool_factor: int = 1,
     ) -> None:
         """Initialize and configures the ColBERT model."""
         self.model = PyColBERT.from_pretrained(
@@ -59,16 +70,25 @@ class ColBERT:
             query_prefix=query_prefix,
             document_prefix=document_prefix,
             mask_token=mask_token,
+            skiplist_words=skiplist_words,
+            pool_factor=pool_factor,
         )
 
     def encode(
         self,
         sentences: list[str],
         is_query: bool,
-        pool_factor: int = 1,
-    ) -> np.ndarray:
+        pool_factor: int | None = None,
+        batch_size: int | None = None,
+        ragged: bool = False,
+        progress: Callable[[dict[str, int]], None] | None = None,
+        cancel: CancellationToken | None = None,
+    ) -> np.ndarray | list[np.ndarray]:
         """Encode a list of sentences into embeddings.
 
+        The GIL is released while encoding, so other Python threads keep running.
+        Concurrent calls on the same model are encoded one after the other.
+
         Args:
         ----
             sentences:
@@ -76,27 +96,48 @@ class ColBERT:
             is_query:
                 A flag indicating if the sentences are queries or documents.
             pool_factor:
-                The factor by which to pool the embeddings. Defaults to 1.
-                When set to 2, it will divide the number of tokens embedded by 2,
-                effectively pooling the embeddings. You can set it to any integer
-                value to control the pooling behavior. If set to 1, no pooling is
-                applied.
+                The factor by which to pool the document embeddings. Defaults to
+                the pool_factor of the model. When set to 2, it will divide the
+                number of tokens embedded by 2, effectively pooling the embeddings.
+                You can set it to any integer value to control the pooling
+                behavior. If set to 1, no pooling is applied. Query embeddings are
+                never pooled.
+            batch_size:
+                The batch size for this call. Defaults to the batch size of the
+                model.
+            ragged:
+                Whether to return a list with one array per sentence, without the
+                padding rows, like PyLate. Defaults to False.
+            progress:
+                A function called after each batch with a dict of batches_done,
+                total_batches, sentences_done, total_sentences and
+                tokens_processed. An exception raised by the function stops
+                encoding and is raised by encode. It must not use the same model.
+            cancel:
+                A CancellationToken which stops encoding before its next batch
+                once cancelled, raising a ColbertException.
 
         Returns:
         -------
-            The resulting embeddings as a NumPy array.
+            The resulting embeddings as a NumPy array of shape (batch_size,
+            max_length, embedding_dim) padded with zero rows, or with `ragged` a
+            list of arrays of shape (length, embedding_dim).
 
         """
         return self.model.encode(
             sentences=sentences,
             is_query=is_query,
             pool_factor=pool_factor,
+            batch_size=batch_size,
+            ragged=ragged,
+            progress=progress,
+            cancel=cancel,
         )
 
     def similarity(
         self,
-        query_embeddings: np.ndarray,
-        doc_embeddings: np.ndarray,
+        query_embeddings: np.ndarray | list[np.ndarray],
+        doc_embeddings: np.ndarray | list[np.ndarray],
     ) -> list[list[float]]:
         """Calculate similarity scores between query and document embeddings.
 
@@ -106,9 +147,11 @@ class ColBERT:
         Args:
         ----
             query_embeddings:
-                A NumPy array of query embeddings.
+                A NumPy array of query embeddings, or a list of arrays as returned
+                by `encode` with `ragged`.
             doc_embeddings:
-                A NumPy array of document embeddings.
+                A NumPy array of document embeddings, or a list of arrays as
+                returned by `encode` with `ragged`.
 
         Returns:
         -------
@@ -116,3 +159,61 @@ class ColBERT:
 
         """
         return self.model.similarity(query_embeddings, doc_embeddings)
+
+    def raw_similarity(
+        self,
+        queries: list[str],
+        documents: list[str],
+    ) -> dict:
+        """Compute the token-level similarity matrix between queries and documents.
+
+        Args:
+        ----
+            queries:
+                A list of queries.
+            documents:
+                A list of documents.
+
+        Returns:
+        -------
+            A dictionary with the `similarity_matrix` NumPy array of shape
+            (num_queries, num_documents, query_length, document_length), and the
+            `query_tokens` and `document_tokens` matching its last two dimensions.
+
+        """
+        return self.model.raw_similarity(queries, documents)
+
+    def tokenize(
+        self,
+        sentences: list[str],
+        is_query: bool,
+    ) -> list[list[str]]:
+        """Tokenize sentences with the prefixes and lengths used by `encode`.
+
+        Args:
+        ----
+            sentences:
+                A list of strings to tokenize.
+            is_query:
+                A flag indicating if the sentences are queries or documents.
+
+        Returns:
+        -------
+            The tokens of each sentence, aligned with the rows of its embeddings.
+            Padding is dropped, as are the skiplist tokens of documents.
+
+        """
+        return self.model.tokenize(sentences, is_query)
+
+    def info(self) -> dict:
+        """Describe the loaded model.
+
+        Returns:
+        -------
+            A dictionary with the model id, architecture, embedding dimension,
+            vocabulary size, prefixes, query and document lengths, query expansion
+            flags and device. Use the embedding dimension to check that stored
+            embeddings match the output of the model.
+
+        """
+        return self.model.info()
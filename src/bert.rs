@@ -0,0 +1,233 @@
+use candle_core::{DType, Device, Result, Tensor, D};
+use candle_nn::{embedding, linear, ops::softmax, Embedding, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{Config, HiddenAct};
+
+// This module has been adapted from the `candle` library so that the BERT backbone can be
+// fine-tuned: the fused layer normalization of `candle_nn` has no backward pass.
+
+#[derive(Clone)]
+struct LayerNorm {
+    inner: candle_nn::LayerNorm,
+    eps: f64,
+}
+
+impl LayerNorm {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let inner = candle_nn::layer_norm(config.hidden_size, config.layer_norm_eps, vb)?;
+        Ok(Self {
+            inner,
+            eps: config.layer_norm_eps,
+        })
+    }
+}
+
+impl Module for LayerNorm {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        // The fused kernel has no backward pass, so gradients would stop at every layer
+        // normalization when fine-tuning.
+        if !xs.track_op() {
+            return self.inner.forward(xs);
+        }
+        let hidden_size = xs.dim(D::Minus1)? as f64;
+        let mean = (xs.sum_keepdim(D::Minus1)? / hidden_size)?;
+        let xs = xs.broadcast_sub(&mean)?;
+        let variance = (xs.sqr()?.sum_keepdim(D::Minus1)? / hidden_size)?;
+        let xs = xs.broadcast_div(&(variance + self.eps)?.sqrt()?)?;
+        let xs = xs.broadcast_mul(self.inner.weight())?;
+        match self.inner.bias() {
+            Some(bias) => xs.broadcast_add(bias),
+            None => Ok(xs),
+        }
+    }
+}
+
+struct BertEmbeddings {
+    word_embeddings: Embedding,
+    position_embeddings: Embedding,
+    token_type_embeddings: Embedding,
+    layer_norm: LayerNorm,
+}
+
+impl BertEmbeddings {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            word_embeddings: embedding(
+                config.vocab_size,
+                config.hidden_size,
+                vb.pp("word_embeddings"),
+            )?,
+            position_embeddings: embedding(
+                config.max_position_embeddings,
+                config.hidden_size,
+                vb.pp("position_embeddings"),
+            )?,
+            token_type_embeddings: embedding(
+                config.type_vocab_size,
+                config.hidden_size,
+                vb.pp("token_type_embeddings"),
+            )?,
+            layer_norm: LayerNorm::load(vb.pp("LayerNorm"), config)?,
+        })
+    }
+
+    fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> Result<Tensor> {
+        let (_, seq_len) = input_ids.dims2()?;
+        let position_ids = Tensor::arange(0u32, seq_len as u32, input_ids.device())?;
+        let embeddings = (self.word_embeddings.forward(input_ids)?
+            + self.token_type_embeddings.forward(token_type_ids)?)?
+        .broadcast_add(&self.position_embeddings.forward(&position_ids)?)?;
+        self.layer_norm.forward(&embeddings)
+    }
+}
+
+struct BertAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    output: Linear,
+    layer_norm: LayerNorm,
+    num_attention_heads: usize,
+    attention_head_size: usize,
+}
+
+impl BertAttention {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let hidden_size = config.hidden_size;
+        let self_vb = vb.pp("self");
+        let output_vb = vb.pp("output");
+        Ok(Self {
+            query: linear(hidden_size, hidden_size, self_vb.pp("query"))?,
+            key: linear(hidden_size, hidden_size, self_vb.pp("key"))?,
+            value: linear(hidden_size, hidden_size, self_vb.pp("value"))?,
+            output: linear(hidden_size, hidden_size, output_vb.pp("dense"))?,
+            layer_norm: LayerNorm::load(output_vb.pp("LayerNorm"), config)?,
+            num_attention_heads: config.num_attention_heads,
+            attention_head_size: hidden_size / config.num_attention_heads,
+        })
+    }
+
+    fn split_heads(&self, xs: &Tensor) -> Result<Tensor> {
+        let (batch_size, seq_len, _) = xs.dims3()?;
+        xs.reshape((
+            batch_size,
+            seq_len,
+            self.num_attention_heads,
+            self.attention_head_size,
+        ))?
+        .transpose(1, 2)?
+        .contiguous()
+    }
+
+    fn forward(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let query = self.split_heads(&self.query.forward(hidden_states)?)?;
+        let key = self.split_heads(&self.key.forward(hidden_states)?)?;
+        let value = self.split_heads(&self.value.forward(hidden_states)?)?;
+
+        let scores = (query.matmul(&key.t()?)? / (self.attention_head_size as f64).sqrt())?
+            .broadcast_add(attention_mask)?;
+        let probs = softmax(&scores, D::Minus1)?;
+        let context = probs
+            .matmul(&value)?
+            .transpose(1, 2)?
+            .contiguous()?
+            .flatten_from(D::Minus2)?;
+
+        let xs = self.output.forward(&context)?;
+        self.layer_norm.forward(&(xs + hidden_states)?)
+    }
+}
+
+struct BertLayer {
+    attention: BertAttention,
+    intermediate: Linear,
+    output: Linear,
+    layer_norm: LayerNorm,
+    hidden_act: HiddenAct,
+}
+
+impl BertLayer {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            attention: BertAttention::load(vb.pp("attention"), config)?,
+            intermediate: linear(
+                config.hidden_size,
+                config.intermediate_size,
+                vb.pp("intermediate").pp("dense"),
+            )?,
+            output: linear(
+                config.intermediate_size,
+                config.hidden_size,
+                vb.pp("output").pp("dense"),
+            )?,
+            layer_norm: LayerNorm::load(vb.pp("output").pp("LayerNorm"), config)?,
+            hidden_act: config.hidden_act,
+        })
+    }
+
+    fn forward(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let attention_output = self.attention.forward(hidden_states, attention_mask)?;
+        let xs = self.intermediate.forward(&attention_output)?;
+        let xs = match self.hidden_act {
+            HiddenAct::Gelu => xs.gelu_erf()?,
+            HiddenAct::GeluApproximate => xs.gelu()?,
+            HiddenAct::Relu => xs.relu()?,
+        };
+        let xs = self.output.forward(&xs)?;
+        self.layer_norm.forward(&(xs + attention_output)?)
+    }
+}
+
+pub struct BertModel {
+    embeddings: BertEmbeddings,
+    layers: Vec<BertLayer>,
+    pub device: Device,
+}
+
+impl BertModel {
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        match Self::load_prefixed(vb.clone(), config) {
+            Ok(model) => Ok(model),
+            // Checkpoints saved from a task model nest the backbone under its model type.
+            Err(err) => match &config.model_type {
+                Some(model_type) => Self::load_prefixed(vb.pp(model_type), config).map_err(|_| err),
+                None => Err(err),
+            },
+        }
+    }
+
+    fn load_prefixed(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let embeddings = BertEmbeddings::load(vb.pp("embeddings"), config)?;
+        let layers = (0..config.num_hidden_layers)
+            .map(|index| BertLayer::load(vb.pp(format!("encoder.layer.{index}")), config))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            embeddings,
+            layers,
+            device: vb.device().clone(),
+        })
+    }
+
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let mut xs = self.embeddings.forward(input_ids, token_type_ids)?;
+        let attention_mask = match attention_mask {
+            Some(attention_mask) => attention_mask.clone(),
+            None => input_ids.ones_like()?,
+        };
+        let attention_mask = extended_attention_mask(&attention_mask, xs.dtype())?;
+        for layer in self.layers.iter() {
+            xs = layer.forward(&xs, &attention_mask)?;
+        }
+        Ok(xs)
+    }
+}
+
+fn extended_attention_mask(attention_mask: &Tensor, dtype: DType) -> Result<Tensor> {
+    let attention_mask = attention_mask.unsqueeze(1)?.unsqueeze(1)?.to_dtype(dtype)?;
+    (attention_mask.ones_like()? - &attention_mask)?
+        .broadcast_mul(&Tensor::new(f32::MIN, attention_mask.device())?.to_dtype(dtype)?)
+}
@@ -1,7 +1,18 @@
-use crate::{error::ColbertError, model::ColBERT};
+use crate::{
+    cache::EmbeddingCache,
+    cross_encoder::CrossEncoder,
+    error::ColbertError,
+    masked_lm::MaskedLM,
+    model::ColBERT,
+    progress::{CancellationToken, ProgressCallback},
+    settings::{dense_modules, ColbertOverrides},
+};
 use candle_core::Device;
-use hf_hub::{api::sync::Api, Repo, RepoType};
-use std::{convert::TryFrom, fs, path::PathBuf};
+use hf_hub::{
+    api::sync::{Api, ApiError},
+    Repo, RepoType,
+};
+use std::{convert::TryFrom, fs, path::PathBuf, time::UNIX_EPOCH};
 
 /// A builder for configuring and creating a `ColBERT` model from the Hugging Face Hub.
 ///
@@ -11,15 +22,12 @@ use std::{convert::TryFrom, fs, path::PathBuf};
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 pub struct ColbertBuilder {
     repo_id: String,
-    query_prefix: Option<String>,
-    document_prefix: Option<String>,
-    mask_token: Option<String>,
-    do_query_expansion: Option<bool>,
-    attend_to_expansion_tokens: Option<bool>,
-    query_length: Option<usize>,
-    document_length: Option<usize>,
+    overrides: ColbertOverrides,
     batch_size: Option<usize>,
     device: Option<Device>,
+    cache: Option<EmbeddingCache>,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
 }
 
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
@@ -28,57 +36,75 @@ impl ColbertBuilder {
     pub(crate) fn new(repo_id: &str) -> Self {
         Self {
             repo_id: repo_id.to_string(),
-            query_prefix: None,
-            document_prefix: None,
-            mask_token: None,
-            do_query_expansion: None,
-            attend_to_expansion_tokens: None,
-            query_length: None,
-            document_length: None,
+            overrides: ColbertOverrides::default(),
             batch_size: None,
             device: None,
+            cache: None,
+            progress: None,
+            cancellation: None,
         }
     }
 
     /// Sets the query prefix token. Overrides the value from the config file.
     pub fn with_query_prefix(mut self, query_prefix: String) -> Self {
-        self.query_prefix = Some(query_prefix);
+        self.overrides.query_prefix = Some(query_prefix);
         self
     }
 
     /// Sets the document prefix token. Overrides the value from the config file.
     pub fn with_document_prefix(mut self, document_prefix: String) -> Self {
-        self.document_prefix = Some(document_prefix);
+        self.overrides.document_prefix = Some(document_prefix);
         self
     }
 
     /// Sets the mask token. Overrides the value from the `special_tokens_map.json` file.
     pub fn with_mask_token(mut self, mask_token: String) -> Self {
-        self.mask_token = Some(mask_token);
+        self.overrides.mask_token = Some(mask_token);
+        self
+    }
+
+    /// Sets the words whose token embeddings are removed from documents.
+    /// Overrides the value from the config file, which defaults to punctuation.
+    pub fn with_skiplist_words(mut self, skiplist_words: Vec<String>) -> Self {
+        self.overrides.skiplist_words = Some(skiplist_words);
         self
     }
 
     /// Sets whether to perform query expansion. Overrides the value from the config file.
     pub fn with_do_query_expansion(mut self, do_expansion: bool) -> Self {
-        self.do_query_expansion = Some(do_expansion);
+        self.overrides.do_query_expansion = Some(do_expansion);
         self
     }
 
     /// Sets whether to attend to expansion tokens. Overrides the value from the config file.
     pub fn with_attend_to_expansion_tokens(mut self, attend: bool) -> Self {
-        self.attend_to_expansion_tokens = Some(attend);
+        self.overrides.attend_to_expansion_tokens = Some(attend);
         self
     }
 
     /// Sets the maximum query length. Overrides the value from the config file.
     pub fn with_query_length(mut self, query_length: usize) -> Self {
-        self.query_length = Some(query_length);
+        self.overrides.query_length = Some(query_length);
         self
     }
 
     /// Sets the maximum document length. Overrides the value from the config file.
     pub fn with_document_length(mut self, document_length: usize) -> Self {
-        self.document_length = Some(document_length);
+        self.overrides.document_length = Some(document_length);
+        self
+    }
+
+    /// Sets the factor by which `encode` pools document embeddings. Defaults to 1, which
+    /// disables pooling.
+    pub fn with_pool_factor(mut self, pool_factor: usize) -> Self {
+        self.overrides.pool_factor = Some(pool_factor);
+        self
+    }
+
+    /// Replaces all the settings that take precedence over the configuration files.
+    #[cfg(feature = "ffi")]
+    pub(crate) fn with_overrides(mut self, overrides: ColbertOverrides) -> Self {
+        self.overrides = overrides;
         self
     }
 
@@ -93,6 +119,49 @@ impl ColbertBuilder {
         self.device = Some(device);
         self
     }
+
+    /// Sets the cache consulted by `encode` to skip the sentences encoded before.
+    pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sets the function `encode` reports its progress to after each batch.
+    pub fn with_progress_callback(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Sets the token checked by `encode` before each batch.
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+}
+
+/// The model files and effective settings resolved by a `ColbertBuilder`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) struct ColbertFiles {
+    pub(crate) model_id: String,
+    pub(crate) revision: String,
+    pub(crate) weights: Vec<u8>,
+    pub(crate) dense_weights: Vec<u8>,
+    pub(crate) dense2_weights: Option<Vec<u8>>,
+    pub(crate) tokenizer: Vec<u8>,
+    pub(crate) config: Vec<u8>,
+    pub(crate) dense_config: Vec<u8>,
+    pub(crate) dense2_config: Option<Vec<u8>>,
+    pub(crate) query_prefix: String,
+    pub(crate) document_prefix: String,
+    pub(crate) mask_token: String,
+    pub(crate) skiplist_words: Vec<String>,
+    pub(crate) do_query_expansion: bool,
+    pub(crate) attend_to_expansion_tokens: bool,
+    pub(crate) query_length: Option<usize>,
+    pub(crate) document_length: Option<usize>,
+    pub(crate) pool_factor: usize,
+    pub(crate) batch_size: Option<usize>,
+    pub(crate) device: Device,
 }
 
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
@@ -100,154 +169,348 @@ impl TryFrom<ColbertBuilder> for ColBERT {
     type Error = ColbertError;
 
     /// Builds the `ColBERT` model by downloading files from the hub and initializing the model.
-    fn try_from(builder: ColbertBuilder) -> Result<Self, Self::Error> {
-        let device = builder.device.unwrap_or(Device::Cpu);
+    fn try_from(mut builder: ColbertBuilder) -> Result<Self, Self::Error> {
+        let cache = builder.cache.take();
+        let progress = builder.progress.take();
+        let cancellation = builder.cancellation.take();
+        let files = builder.resolve()?;
+        let mut model = ColBERT::new_with_skiplist(
+            files.weights,
+            files.dense_weights,
+            files.dense2_weights,
+            files.tokenizer,
+            files.config,
+            files.dense_config,
+            files.dense2_config,
+            files.query_prefix,
+            files.document_prefix,
+            files.mask_token,
+            files.skiplist_words,
+            files.do_query_expansion,
+            files.attend_to_expansion_tokens,
+            files.query_length,
+            files.document_length,
+            files.batch_size,
+            &files.device,
+        )?;
+        model.model_id = Some(files.model_id);
+        model.revision = Some(files.revision);
+        model.pool_factor = files.pool_factor;
+        model.cache = cache;
+        model.progress = progress;
+        model.cancellation = cancellation;
+        Ok(model)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl ColbertBuilder {
+    /// Reads the model files from a local directory or the hub and resolves the
+    /// settings that were not overridden from the sentence-transformers config.
+    pub(crate) fn resolve(self) -> Result<ColbertFiles, ColbertError> {
+        let device = self.device.unwrap_or(Device::Cpu);
 
-        let local_path = PathBuf::from(&builder.repo_id);
-        let (
-            tokenizer_path,
-            weights_path,
-            config_path,
-            st_config_path,
-            dense_config_path,
-            dense_weights_path,
-            dense2_config_path,
-            dense2_weights_path,
-            special_tokens_map_path,
-        ) = if local_path.is_dir() {
-            let dense2_config = local_path.join("2_Dense/config.json");
-            let dense2_weights = local_path.join("2_Dense/model.safetensors");
-            (
-                local_path.join("tokenizer.json"),
-                local_path.join("model.safetensors"),
-                local_path.join("config.json"),
-                local_path.join("config_sentence_transformers.json"),
-                local_path.join("1_Dense/config.json"),
-                local_path.join("1_Dense/model.safetensors"),
-                if dense2_config.exists() {
-                    Some(dense2_config)
-                } else {
-                    None
-                },
-                if dense2_weights.exists() {
-                    Some(dense2_weights)
-                } else {
-                    None
-                },
-                local_path.join("special_tokens_map.json"),
-            )
+        let local_path = PathBuf::from(&self.repo_id);
+        let repo = if local_path.is_dir() {
+            None
         } else {
-            let api = Api::new()?;
-            let repo = api.repo(Repo::with_revision(
-                builder.repo_id.clone(),
+            Some(Api::new()?.repo(Repo::with_revision(
+                self.repo_id.clone(),
                 RepoType::Model,
                 "main".to_string(),
-            ));
-            let dense2_config = repo.get("2_Dense/config.json").ok();
-            let dense2_weights = repo.get("2_Dense/model.safetensors").ok();
-            (
-                repo.get("tokenizer.json")?,
-                repo.get("model.safetensors")?,
-                repo.get("config.json")?,
-                repo.get("config_sentence_transformers.json")?,
-                repo.get("1_Dense/config.json")?,
-                repo.get("1_Dense/model.safetensors")?,
-                dense2_config,
-                dense2_weights,
-                repo.get("special_tokens_map.json")?,
-            )
+            )))
         };
-
-        if local_path.is_dir() {
-            for path in [
-                &tokenizer_path,
-                &weights_path,
-                &config_path,
-                &st_config_path,
-                &dense_config_path,
-                &dense_weights_path,
-                &special_tokens_map_path,
-            ] {
-                if !path.exists() {
-                    return Err(ColbertError::Io(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        format!("File not found in local directory: {}", path.display()),
-                    )));
-                }
+        let locate = |file: &str| -> Result<PathBuf, ColbertError> {
+            let path = match &repo {
+                Some(repo) => repo.get(file).map_err(|err| {
+                    if is_not_found(&err) {
+                        ColbertError::MissingFile(PathBuf::from(file))
+                    } else {
+                        err.into()
+                    }
+                })?,
+                None => local_path.join(file),
+            };
+            if !path.exists() {
+                return Err(ColbertError::MissingFile(path));
             }
+            Ok(path)
+        };
+        let read = |file: &str| -> Result<Vec<u8>, ColbertError> { Ok(fs::read(locate(file)?)?) };
+
+        // The Dense modules listed in modules.json, or 1_Dense and an optional 2_Dense.
+        let (dense_path, dense2_path) = match read("modules.json") {
+            Ok(modules) => dense_modules(&modules)?,
+            Err(ColbertError::MissingFile(_)) => match read("2_Dense/config.json") {
+                Ok(_) => ("1_Dense".to_string(), Some("2_Dense".to_string())),
+                Err(ColbertError::MissingFile(_)) => ("1_Dense".to_string(), None),
+                Err(err) => return Err(err),
+            },
+            Err(err) => return Err(err),
+        };
+
+        let tokenizer_bytes = read("tokenizer.json")?;
+        let weights_bytes = read("model.safetensors")?;
+        let config_bytes = read("config.json")?;
+        let st_config_bytes = read("config_sentence_transformers.json")?;
+        let dense_config_bytes = read(&format!("{dense_path}/config.json"))?;
+        let dense_weights_bytes = read(&format!("{dense_path}/model.safetensors"))?;
+        let dense2_config_bytes = dense2_path
+            .as_ref()
+            .map(|path| read(&format!("{path}/config.json")))
+            .transpose()?;
+        let dense2_weights_bytes = dense2_path
+            .as_ref()
+            .map(|path| read(&format!("{path}/model.safetensors")))
+            .transpose()?;
+        let special_tokens_map_bytes = read("special_tokens_map.json")?;
+
+        let weight_files = [Some(dense_path.as_str()), dense2_path.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(|path| format!("{path}/model.safetensors"));
+        let weight_files = ["model.safetensors".to_string()]
+            .into_iter()
+            .chain(weight_files)
+            .map(|file| locate(&file))
+            .collect::<Result<Vec<_>, _>>()?;
+        let revision = weights_revision(&weight_files, repo.is_some())?;
+
+        let settings = self
+            .overrides
+            .resolve(&st_config_bytes, &special_tokens_map_bytes)?;
+
+        Ok(ColbertFiles {
+            model_id: self.repo_id,
+            revision,
+            weights: weights_bytes,
+            dense_weights: dense_weights_bytes,
+            dense2_weights: dense2_weights_bytes,
+            tokenizer: tokenizer_bytes,
+            config: config_bytes,
+            dense_config: dense_config_bytes,
+            dense2_config: dense2_config_bytes,
+            query_prefix: settings.query_prefix,
+            document_prefix: settings.document_prefix,
+            mask_token: settings.mask_token,
+            skiplist_words: settings.skiplist_words,
+            do_query_expansion: settings.do_query_expansion,
+            attend_to_expansion_tokens: settings.attend_to_expansion_tokens,
+            query_length: settings.query_length,
+            document_length: settings.document_length,
+            pool_factor: settings.pool_factor,
+            batch_size: self.batch_size,
+            device,
+        })
+    }
+}
+
+/// A builder for configuring and creating a `CrossEncoder` model from the Hugging Face Hub.
+///
+/// Like `ColbertBuilder`, it accepts either a repository identifier or a path to a
+/// local directory containing `tokenizer.json`, `model.safetensors` and `config.json`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct CrossEncoderBuilder {
+    repo_id: String,
+    max_length: Option<usize>,
+    batch_size: Option<usize>,
+    score_label: Option<usize>,
+    device: Option<Device>,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl CrossEncoderBuilder {
+    /// Creates a new `CrossEncoderBuilder`.
+    pub(crate) fn new(repo_id: &str) -> Self {
+        Self {
+            repo_id: repo_id.to_string(),
+            max_length: None,
+            batch_size: None,
+            score_label: None,
+            device: None,
         }
+    }
+
+    /// Sets the maximum length of a `(query, document)` pair. Defaults to 512.
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Sets the batch size for scoring. Defaults to 32.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size);
+        self
+    }
 
-        let tokenizer_bytes = fs::read(tokenizer_path)?;
-        let weights_bytes = fs::read(weights_path)?;
-        let config_bytes = fs::read(config_path)?;
-        let st_config_bytes = fs::read(st_config_path)?;
-        let dense_config_bytes = fs::read(dense_config_path)?;
-        let dense_weights_bytes = fs::read(dense_weights_path)?;
-
-        // Read optional 2_Dense files if present
-        let dense2_config_bytes = dense2_config_path.map(|path| fs::read(path)).transpose()?;
-        let dense2_weights_bytes = dense2_weights_path.map(|path| fs::read(path)).transpose()?;
-
-        let special_tokens_map_bytes = fs::read(special_tokens_map_path)?;
-
-        let st_config: serde_json::Value = serde_json::from_slice(&st_config_bytes)?;
-        let special_tokens_map: serde_json::Value =
-            serde_json::from_slice(&special_tokens_map_bytes)?;
-
-        let final_query_prefix = builder.query_prefix.unwrap_or_else(|| {
-            st_config["query_prefix"]
-                .as_str()
-                .unwrap_or("[Q]")
-                .to_string()
-        });
-        let final_document_prefix = builder.document_prefix.unwrap_or_else(|| {
-            st_config["document_prefix"]
-                .as_str()
-                .unwrap_or("[D]")
-                .to_string()
-        });
-
-        let mask_token = builder.mask_token.unwrap_or_else(|| {
-            special_tokens_map["mask_token"]
-                .as_str()
-                .unwrap_or("[MASK]")
-                .to_string()
-        });
-
-        let final_do_query_expansion = builder
-            .do_query_expansion
-            .unwrap_or_else(|| st_config["do_query_expansion"].as_bool().unwrap_or(true));
-
-        let final_attend_to_expansion_tokens =
-            builder.attend_to_expansion_tokens.unwrap_or_else(|| {
-                st_config["attend_to_expansion_tokens"]
-                    .as_bool()
-                    .unwrap_or(false)
-            });
-        let final_query_length = builder
-            .query_length
-            .or_else(|| st_config["query_length"].as_u64().map(|v| v as usize));
-        let final_document_length = builder
-            .document_length
-            .or_else(|| st_config["document_length"].as_u64().map(|v| v as usize));
-
-        ColBERT::new(
-            weights_bytes,
-            dense_weights_bytes,
-            dense2_weights_bytes,
-            tokenizer_bytes,
-            config_bytes,
-            dense_config_bytes,
-            dense2_config_bytes,
-            final_query_prefix,
-            final_document_prefix,
-            mask_token,
-            final_do_query_expansion,
-            final_attend_to_expansion_tokens,
-            final_query_length,
-            final_document_length,
+    /// Sets the label whose probability `predict` returns for multi-label heads.
+    /// Defaults to the last label.
+    pub fn with_score_label(mut self, score_label: usize) -> Self {
+        self.score_label = Some(score_label);
+        self
+    }
+
+    /// Sets the device to run the model on.
+    pub fn with_device(mut self, device: Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl TryFrom<CrossEncoderBuilder> for CrossEncoder {
+    type Error = ColbertError;
+
+    /// Builds the `CrossEncoder` model by downloading files from the hub and initializing the model.
+    fn try_from(builder: CrossEncoderBuilder) -> Result<Self, Self::Error> {
+        let device = builder.device.unwrap_or(Device::Cpu);
+
+        let [tokenizer_path, weights_path, config_path] = resolve_files(
+            &builder.repo_id,
+            ["tokenizer.json", "model.safetensors", "config.json"],
+        )?;
+
+        let mut model = CrossEncoder::new(
+            fs::read(weights_path)?,
+            fs::read(tokenizer_path)?,
+            fs::read(config_path)?,
+            builder.max_length,
             builder.batch_size,
             &device,
+        )?;
+        if let Some(score_label) = builder.score_label {
+            if score_label >= model.num_labels {
+                return Err(ColbertError::IndexOutOfRange {
+                    index: score_label,
+                    len: model.num_labels,
+                });
+            }
+            model.score_label = score_label;
+        }
+        Ok(model)
+    }
+}
+
+/// A builder for configuring and creating a `MaskedLM` model from the Hugging Face Hub.
+///
+/// It accepts either a repository identifier or a path to a local directory
+/// containing `tokenizer.json`, `model.safetensors` and `config.json`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct MaskedLMBuilder {
+    repo_id: String,
+    mask_token: Option<String>,
+    device: Option<Device>,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl MaskedLMBuilder {
+    /// Creates a new `MaskedLMBuilder`.
+    pub(crate) fn new(repo_id: &str) -> Self {
+        Self {
+            repo_id: repo_id.to_string(),
+            mask_token: None,
+            device: None,
+        }
+    }
+
+    /// Sets the mask token. Defaults to `[MASK]`.
+    pub fn with_mask_token(mut self, mask_token: String) -> Self {
+        self.mask_token = Some(mask_token);
+        self
+    }
+
+    /// Sets the device to run the model on.
+    pub fn with_device(mut self, device: Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl TryFrom<MaskedLMBuilder> for MaskedLM {
+    type Error = ColbertError;
+
+    /// Builds the `MaskedLM` model by downloading files from the hub and initializing the model.
+    fn try_from(builder: MaskedLMBuilder) -> Result<Self, Self::Error> {
+        let device = builder.device.unwrap_or(Device::Cpu);
+
+        let [tokenizer_path, weights_path, config_path] = resolve_files(
+            &builder.repo_id,
+            ["tokenizer.json", "model.safetensors", "config.json"],
+        )?;
+
+        MaskedLM::new(
+            fs::read(weights_path)?,
+            fs::read(tokenizer_path)?,
+            fs::read(config_path)?,
+            builder.mask_token.unwrap_or_else(|| "[MASK]".to_string()),
+            &device,
         )
     }
 }
+
+/// Resolves the paths of `files` in a local directory, or downloads them from the hub
+/// when `repo_id` is not a directory.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn resolve_files<const N: usize>(
+    repo_id: &str,
+    files: [&str; N],
+) -> Result<[PathBuf; N], ColbertError> {
+    let local_path = PathBuf::from(repo_id);
+    if local_path.is_dir() {
+        let paths = files.map(|file| local_path.join(file));
+        for path in &paths {
+            if !path.exists() {
+                return Err(ColbertError::MissingFile(path.to_path_buf()));
+            }
+        }
+        Ok(paths)
+    } else {
+        let api = Api::new()?;
+        let repo = api.repo(Repo::with_revision(
+            repo_id.to_string(),
+            RepoType::Model,
+            "main".to_string(),
+        ));
+        let mut paths = Vec::with_capacity(N);
+        for file in files {
+            paths.push(repo.get(file)?);
+        }
+        Ok(paths
+            .try_into()
+            .expect("one path is resolved per requested file"))
+    }
+}
+
+/// Whether a hub download failed because the repository has no such file.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn is_not_found(err: &ApiError) -> bool {
+    matches!(err, ApiError::RequestError(err) if matches!(**err, ureq::Error::Status(404, _)))
+}
+
+/// Identifies the weights in `files`, backbone first: the commit they were downloaded
+/// from for the hub, or their sizes and modification times for a local directory.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn weights_revision(files: &[PathBuf], from_hub: bool) -> Result<String, ColbertError> {
+    if from_hub {
+        // Hub files are stored in a snapshot directory named after the resolved commit.
+        let commit = files
+            .first()
+            .and_then(|path| path.parent()?.file_name()?.to_str())
+            .unwrap_or_default();
+        return Ok(commit.to_string());
+    }
+    files
+        .iter()
+        .map(|path| {
+            let metadata = fs::metadata(path)?;
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            Ok(format!("{}:{}", metadata.len(), modified))
+        })
+        .collect::<Result<Vec<_>, ColbertError>>()
+        .map(|parts| parts.join(","))
+}
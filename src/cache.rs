@@ -0,0 +1,236 @@
+use crate::error::ColbertError;
+use candle_core::Tensor;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use candle_core::{DType, Device};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A cache of token embeddings consulted by `ColBERT::encode`, which only encodes the
+/// sentences it misses.
+///
+/// Entries are keyed by a hash of the text, whether it is a query or a document, the
+/// settings of the model that affect its embeddings (prefixes, lengths, query expansion,
+/// skiplist, pool factor, configuration files and model id) and the revision of its
+/// weights. Models loaded with `ColbertBuilder` identify their weights by the Hub commit
+/// they were downloaded from, or by the sizes and modification times of local weight
+/// files; `with_revision` overrides this.
+///
+/// The most recently used `capacity` entries are kept in memory. With a directory,
+/// every entry is also written to disk and entries evicted from memory are read back
+/// from there; entries on disk are never evicted, and corrupt ones are removed and
+/// count as misses.
+///
+/// Clones share the same entries, so one cache can serve several models or threads.
+#[derive(Clone)]
+pub struct EmbeddingCache {
+    state: Arc<Mutex<CacheState>>,
+    revision: Option<String>,
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    directory: Option<PathBuf>,
+}
+
+struct CacheState {
+    capacity: usize,
+    /// The embeddings of each key, with the tick of their last use.
+    entries: HashMap<String, (Tensor, u64)>,
+    /// The keys in memory, least recently used first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl CacheState {
+    /// Marks `key` as the most recently used entry and returns its embeddings.
+    fn touch(&mut self, key: &str) -> Option<Tensor> {
+        let (embeddings, used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        self.recency.remove(used);
+        *used = self.tick;
+        self.recency.insert(self.tick, key.to_string());
+        Some(embeddings.clone())
+    }
+
+    /// Stores `embeddings` in memory, evicting the least recently used entries.
+    fn insert(&mut self, key: &str, embeddings: Tensor) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self
+            .entries
+            .insert(key.to_string(), (embeddings, self.tick))
+        {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, key.to_string());
+        while self.entries.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, evicted)) => self.entries.remove(&evicted),
+                None => break,
+            };
+        }
+    }
+}
+
+impl EmbeddingCache {
+    /// Creates a cache keeping up to `capacity` embeddings in memory.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                capacity,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                hits: 0,
+                misses: 0,
+            })),
+            revision: None,
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            directory: None,
+        }
+    }
+
+    /// Also stores the embeddings as safetensors files in `directory`, which is created
+    /// if it does not exist, so that they outlive the process.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn with_directory<P: AsRef<Path>>(mut self, directory: P) -> Result<Self, ColbertError> {
+        fs::create_dir_all(directory.as_ref())?;
+        self.directory = Some(directory.as_ref().to_path_buf());
+        Ok(self)
+    }
+
+    /// Sets the revision of the model weights used in the keys, instead of the one
+    /// derived when the model was loaded.
+    ///
+    /// Models not loaded with `ColbertBuilder` have no revision of their own, so the
+    /// revision must change whenever their weights do.
+    pub fn with_revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = Some(revision.into());
+        self
+    }
+
+    /// Returns the number of embeddings held in memory.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns `true` if no embeddings are held in memory.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of sentences found in the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// Returns the number of sentences that had to be encoded.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Drops the embeddings held in memory. Files on disk are kept.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.entries.clear();
+        state.recency.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the key of `text` encoded by a model whose weights have `revision` and
+    /// whose settings serialize to `fingerprint`.
+    pub(crate) fn key(&self, revision: Option<&str>, fingerprint: &[u8], text: &str) -> String {
+        let revision = self.revision.as_deref().or(revision).unwrap_or_default();
+        let mut hasher = Sha256::new();
+        for part in [revision.as_bytes(), fingerprint, text.as_bytes()] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns the `[length, embedding_dim]` embeddings stored under `key`, looking on
+    /// disk when they are not in memory.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Tensor>, ColbertError> {
+        {
+            let mut state = self.lock();
+            if let Some(embeddings) = state.touch(key) {
+                state.hits += 1;
+                return Ok(Some(embeddings));
+            }
+        }
+
+        // Read without the lock, so that other threads keep using the memory entries.
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        if let Some(path) = self.path(key).filter(|path| path.exists()) {
+            match read_entry(&path) {
+                Some(embeddings) => {
+                    let mut state = self.lock();
+                    state.insert(key, embeddings.clone());
+                    state.hits += 1;
+                    return Ok(Some(embeddings));
+                },
+                // A truncated or corrupt entry, e.g. left by a full disk, is encoded
+                // again and rewritten.
+                None => {
+                    let _ = fs::remove_file(&path);
+                },
+            }
+        }
+
+        self.lock().misses += 1;
+        Ok(None)
+    }
+
+    /// Stores the `[length, embedding_dim]` embeddings of a sentence under `key`.
+    pub(crate) fn insert(&self, key: &str, embeddings: &Tensor) -> Result<(), ColbertError> {
+        // Copy so that the entry does not keep the rest of its batch alive.
+        let embeddings = embeddings.copy()?;
+
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        if let Some(path) = self.path(key) {
+            // Written next to its final path and renamed, so that readers never see a
+            // partial file.
+            let partial = path.with_extension("partial");
+            embeddings.save_safetensors("embeddings", &partial)?;
+            fs::rename(&partial, &path)?;
+        }
+
+        self.lock().insert(key, embeddings);
+        Ok(())
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.directory
+            .as_ref()
+            .map(|directory| directory.join(format!("{}.safetensors", key)))
+    }
+}
+
+/// Reads the `[length, embedding_dim]` embeddings of a disk entry, or `None` if the file
+/// is not a valid entry.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn read_entry(path: &Path) -> Option<Tensor> {
+    candle_core::safetensors::load(path, &Device::Cpu)
+        .ok()?
+        .remove("embeddings")
+        .filter(|embeddings| embeddings.rank() == 2 && embeddings.dtype() == DType::F32)
+}
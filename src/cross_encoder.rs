@@ -0,0 +1,267 @@
+use crate::{
+    error::ColbertError,
+    modernbert::{Config as ModernBertConfig, ModernBertForSequenceClassification},
+    types::RankedDocument,
+};
+use candle_core::{DType, Device, IndexOp, Tensor, D};
+use candle_nn::{ops::softmax, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use tokenizers::Tokenizer;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::builder::CrossEncoderBuilder;
+
+/// A BERT sequence-classification model, as exported by Transformers'
+/// `BertForSequenceClassification`.
+pub struct BertForSequenceClassification {
+    bert: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+}
+
+impl BertForSequenceClassification {
+    /// Loads the backbone, pooler and classification layer from a var builder.
+    pub fn load(
+        vb: VarBuilder,
+        config: &BertConfig,
+        num_labels: usize,
+    ) -> Result<Self, candle_core::Error> {
+        let bert = BertModel::load(vb.clone(), config)?;
+        let pooler_prefix = if vb.contains_tensor("bert.pooler.dense.weight") {
+            "bert.pooler.dense"
+        } else {
+            "pooler.dense"
+        };
+        let pooler =
+            candle_nn::linear(config.hidden_size, config.hidden_size, vb.pp(pooler_prefix))?;
+        let classifier = candle_nn::linear(config.hidden_size, num_labels, vb.pp("classifier"))?;
+        Ok(Self {
+            bert,
+            pooler,
+            classifier,
+        })
+    }
+
+    /// Returns the unnormalized classifier outputs for a batch of sequences.
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        token_type_ids: &Tensor,
+    ) -> Result<Tensor, candle_core::Error> {
+        let sequence_output = self
+            .bert
+            .forward(input_ids, token_type_ids, Some(attention_mask))?;
+        let pooled = self.pooler.forward(&sequence_output.i((.., 0))?)?.tanh()?;
+        self.classifier.forward(&pooled)
+    }
+}
+
+/// An enum to abstract over the supported sequence-classification models.
+pub enum CrossEncoderModel {
+    /// A variant holding a `ModernBertForSequenceClassification` model.
+    ModernBert(ModernBertForSequenceClassification),
+    /// A variant holding a `BertForSequenceClassification` model.
+    Bert(BertForSequenceClassification),
+}
+
+impl CrossEncoderModel {
+    /// Performs a forward pass and returns the classifier logits.
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        token_type_ids: &Tensor,
+    ) -> Result<Tensor, candle_core::Error> {
+        match self {
+            CrossEncoderModel::ModernBert(model) => model.logits(input_ids, attention_mask),
+            CrossEncoderModel::Bert(model) => {
+                model.forward(input_ids, attention_mask, token_type_ids)
+            },
+        }
+    }
+}
+
+/// A cross-encoder that scores `(query, document)` pairs jointly.
+///
+/// Cross-encoders are typically used to rerank the top candidates retrieved
+/// with a `ColBERT` model, as they are more accurate but much slower since
+/// every pair requires its own forward pass.
+pub struct CrossEncoder {
+    pub(crate) model: CrossEncoderModel,
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) num_labels: usize,
+    pub(crate) score_label: usize,
+    pub(crate) max_length: usize,
+    pub(crate) batch_size: usize,
+    /// The device (CPU or GPU) on which the model is loaded.
+    pub device: Device,
+}
+
+impl CrossEncoder {
+    /// Creates a new instance of the `CrossEncoder` model from byte buffers.
+    pub fn new(
+        weights: Vec<u8>,
+        tokenizer_bytes: Vec<u8>,
+        config_bytes: Vec<u8>,
+        max_length: Option<usize>,
+        batch_size: Option<usize>,
+        device: &Device,
+    ) -> Result<Self, ColbertError> {
+        let vb = VarBuilder::from_buffered_safetensors(weights, DType::F32, device)?;
+
+        let config_value: serde_json::Value = serde_json::from_slice(&config_bytes)?;
+        let architectures = config_value["architectures"]
+            .as_array()
+            .and_then(|arr| arr.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ColbertError::missing_config_field("config.json", "architectures"))?;
+        let num_labels = config_value["id2label"]
+            .as_object()
+            .map_or(1, |labels| labels.len());
+
+        let model = match architectures {
+            "ModernBertForSequenceClassification" => {
+                let config: ModernBertConfig = serde_json::from_slice(&config_bytes)?;
+                if config.classifier_config.is_none() {
+                    return Err(ColbertError::missing_config_field(
+                        "config.json",
+                        "classifier_pooling",
+                    ));
+                }
+                let model = ModernBertForSequenceClassification::load(vb, &config)?;
+                CrossEncoderModel::ModernBert(model)
+            },
+            "BertForSequenceClassification" => {
+                let config: BertConfig = serde_json::from_slice(&config_bytes)?;
+                let model = BertForSequenceClassification::load(vb, &config, num_labels)?;
+                CrossEncoderModel::Bert(model)
+            },
+            arch => return Err(ColbertError::UnsupportedArchitecture(arch.to_string())),
+        };
+
+        let tokenizer = Tokenizer::from_bytes(&tokenizer_bytes)?;
+
+        Ok(Self {
+            model,
+            tokenizer,
+            num_labels,
+            score_label: num_labels - 1,
+            max_length: max_length.unwrap_or(512),
+            batch_size: batch_size.unwrap_or(32),
+            device: device.clone(),
+        })
+    }
+
+    /// Creates a `CrossEncoderBuilder` to construct a `CrossEncoder` from a Hugging Face repository.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn from(repo_id: &str) -> CrossEncoderBuilder {
+        CrossEncoderBuilder::new(repo_id)
+    }
+
+    /// Scores a list of `(query, document)` pairs, processing them in batches.
+    ///
+    /// Single-label heads (the usual reranker setup) return their raw logit.
+    /// Multi-label heads return the softmax probability of the scored label, the
+    /// last one unless the builder sets another; `predict_logits` returns every
+    /// label's logit instead.
+    pub fn predict(&mut self, pairs: &[(String, String)]) -> Result<Vec<f32>, ColbertError> {
+        self.forward_batches(pairs, |logits, num_labels, score_label| {
+            if num_labels == 1 {
+                logits.squeeze(D::Minus1)?.to_vec1::<f32>()
+            } else {
+                softmax(logits, D::Minus1)?
+                    .i((.., score_label))?
+                    .to_vec1::<f32>()
+            }
+        })
+    }
+
+    /// Returns the raw logit of every label for each `(query, document)` pair.
+    pub fn predict_logits(
+        &mut self,
+        pairs: &[(String, String)],
+    ) -> Result<Vec<Vec<f32>>, ColbertError> {
+        self.forward_batches(pairs, |logits, _, _| logits.to_vec2::<f32>())
+    }
+
+    /// Runs the classifier over `pairs` in batches and collects what `scores` extracts
+    /// from the logits of each batch.
+    fn forward_batches<T>(
+        &mut self,
+        pairs: &[(String, String)],
+        scores: impl Fn(&Tensor, usize, usize) -> candle_core::Result<Vec<T>>,
+    ) -> Result<Vec<T>, ColbertError> {
+        if pairs.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+
+        let mut outputs = Vec::with_capacity(pairs.len());
+        for batch_pairs in pairs.chunks(self.batch_size) {
+            let (token_ids, attention_mask, token_type_ids) = self.tokenize(batch_pairs)?;
+            let logits = self
+                .model
+                .forward(&token_ids, &attention_mask, &token_type_ids)?;
+            outputs.extend(scores(&logits, self.num_labels, self.score_label)?);
+        }
+
+        Ok(outputs)
+    }
+
+    /// Scores every document against a single query and returns them sorted by
+    /// decreasing score.
+    pub fn rank(
+        &mut self,
+        query: &str,
+        documents: &[String],
+    ) -> Result<Vec<RankedDocument>, ColbertError> {
+        let pairs: Vec<(String, String)> = documents
+            .iter()
+            .map(|document| (query.to_string(), document.clone()))
+            .collect();
+        let scores = self.predict(&pairs)?;
+
+        let mut ranked: Vec<RankedDocument> = scores
+            .into_iter()
+            .enumerate()
+            .map(|(index, score)| RankedDocument { index, score })
+            .collect();
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(ranked)
+    }
+
+    /// Tokenizes a batch of `(query, document)` pairs into a single sequence each.
+    fn tokenize(
+        &mut self,
+        pairs: &[(String, String)],
+    ) -> Result<(Tensor, Tensor, Tensor), ColbertError> {
+        let _ = self
+            .tokenizer
+            .with_truncation(Some(tokenizers::TruncationParams {
+                max_length: self.max_length,
+                ..Default::default()
+            }));
+        self.tokenizer.with_padding(Some(tokenizers::PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+
+        let encodings = self.tokenizer.encode_batch(pairs.to_vec(), true)?;
+
+        let batch_size = encodings.len();
+        let seq_len = encodings.first().map_or(0, |e| e.get_ids().len());
+        let (mut ids_vec, mut mask_vec, mut type_ids_vec) =
+            (Vec::<u32>::new(), Vec::<u32>::new(), Vec::<u32>::new());
+        for enc in &encodings {
+            ids_vec.extend(enc.get_ids());
+            mask_vec.extend(enc.get_attention_mask());
+            type_ids_vec.extend(enc.get_type_ids());
+        }
+
+        let token_ids = Tensor::from_vec(ids_vec, (batch_size, seq_len), &self.device)?;
+        let attention_mask = Tensor::from_vec(mask_vec, (batch_size, seq_len), &self.device)?;
+        let token_type_ids = Tensor::from_vec(type_ids_vec, (batch_size, seq_len), &self.device)?;
+
+        Ok((token_ids, attention_mask, token_type_ids))
+    }
+}
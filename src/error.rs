@@ -26,7 +26,105 @@ pub enum ColbertError {
     #[error("WASM Bindgen Deserialization Error: {0}")]
     SerdeWasm(#[from] serde_wasm_bindgen::Error),
 
-    /// Custom operational errors, e.g., missing configuration values.
+    /// The model architecture declared in `config.json` is not supported.
+    #[error("Unsupported architecture: {0}")]
+    UnsupportedArchitecture(String),
+
+    /// A required field is missing or has an invalid type in a configuration file.
+    #[error("Missing or invalid '{field}' in {file}")]
+    MissingConfigField {
+        /// The configuration file, e.g. `1_Dense/config.json`.
+        file: String,
+        /// The name of the missing field.
+        field: String,
+    },
+
+    /// Two dimensions that must agree do not, e.g. chained Dense layers or tensor ranks.
+    #[error("Dimension mismatch in {context}: expected {expected}, found {found}")]
+    DimensionMismatch {
+        /// A description of where the mismatch happened.
+        context: String,
+        /// The expected dimension.
+        expected: usize,
+        /// The dimension that was found.
+        found: usize,
+    },
+
+    /// The input list of sentences or pairs is empty.
+    #[error("Input cannot be empty.")]
+    EmptyInput,
+
+    /// Encoding was stopped by a `CancellationToken`.
+    #[error("Encoding was cancelled.")]
+    Cancelled,
+
+    /// An index, such as a document or a label, is out of range.
+    #[error("Index {index} is out of range for {len} items")]
+    IndexOutOfRange {
+        /// The requested index.
+        index: usize,
+        /// The number of items.
+        len: usize,
+    },
+
+    /// A required model file is missing from a local directory.
+    #[error("File not found in local directory: {}", .0.display())]
+    MissingFile(std::path::PathBuf),
+
+    /// A token is not part of the tokenizer's vocabulary.
+    #[error("Token '{0}' not found in the tokenizer's vocabulary.")]
+    UnknownToken(String),
+
+    /// A fill-mask input does not contain any mask token.
+    #[error("Input text does not contain any mask token.")]
+    MissingMaskToken,
+
+    /// Error originating from the `safetensors` library.
+    #[error("Safetensors Error: {0}")]
+    Safetensors(#[from] safetensors::SafeTensorError),
+
+    /// A file written by this library, such as an embeddings collection, is malformed.
+    #[error("Malformed '{field}' in {file}: {reason}")]
+    MalformedFile {
+        /// The file or buffer being read.
+        file: String,
+        /// The header field or tensor that is malformed.
+        field: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+
+    /// A line of a qrels or run file cannot be parsed.
+    #[error("Invalid line {line} of {}: {reason}", .path.display())]
+    Parse {
+        /// The file being read.
+        path: std::path::PathBuf,
+        /// The 1-based number of the line.
+        line: usize,
+        /// What is wrong with the line.
+        reason: String,
+    },
+
+    /// A background thread panicked, failing the calls waiting on it.
+    #[error("The {0} panicked")]
+    WorkerPanicked(String),
+
+    /// A pointer passed to the C API is null.
+    #[cfg(feature = "ffi")]
+    #[error("`{0}` is a null pointer")]
+    NullPointer(String),
+
+    /// A string passed to the C API is not valid UTF-8.
+    #[cfg(feature = "ffi")]
+    #[error("`{0}` is not valid UTF-8")]
+    InvalidUtf8(String),
+
+    /// The HTTP server cannot be started.
+    #[cfg(feature = "server")]
+    #[error("HTTP Server Error: {0}")]
+    Http(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// Custom operational errors that do not fit any other variant.
     #[error("Operation Error: {0}")]
     Operation(String),
 
@@ -41,6 +139,25 @@ pub enum ColbertError {
     Io(#[from] std::io::Error),
 }
 
+impl ColbertError {
+    /// Creates a `MalformedFile` error for `field` in `file`.
+    pub(crate) fn malformed_file(file: &str, field: &str, reason: impl Into<String>) -> Self {
+        ColbertError::MalformedFile {
+            file: file.to_string(),
+            field: field.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Creates a `MissingConfigField` error for `field` in `file`.
+    pub(crate) fn missing_config_field(file: &str, field: &str) -> Self {
+        ColbertError::MissingConfigField {
+            file: file.to_string(),
+            field: field.to_string(),
+        }
+    }
+}
+
 impl From<Box<dyn std::error::Error + Send + Sync>> for ColbertError {
     fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Self {
         ColbertError::Tokenizer(err.to_string())
@@ -0,0 +1,202 @@
+use crate::error::ColbertError;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+/// Relevance judgments: query id -> document id -> graded relevance.
+pub type Qrels = HashMap<String, HashMap<String, i32>>;
+
+/// Retrieval results: query id -> `(document id, score)` pairs sorted by decreasing score.
+pub type Run = HashMap<String, Vec<(String, f32)>>;
+
+/// Mean retrieval metrics over the judged queries of a run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EvaluationReport {
+    /// The number of queries with at least one relevant document.
+    pub num_queries: usize,
+    /// nDCG at each cutoff, with linear gains as in `trec_eval`.
+    pub ndcg: BTreeMap<usize, f64>,
+    /// Reciprocal rank of the first relevant document at each cutoff.
+    pub mrr: BTreeMap<usize, f64>,
+    /// Fraction of the relevant documents retrieved at each cutoff.
+    pub recall: BTreeMap<usize, f64>,
+    /// Mean average precision over the full ranking.
+    pub map: f64,
+}
+
+/// Computes nDCG@k of a ranking of document ids.
+pub fn ndcg_at_k(ranking: &[String], relevance: &HashMap<String, i32>, k: usize) -> f64 {
+    let gain = |rel: i32| f64::from(rel.max(0));
+    let discount = |rank: usize| (rank as f64 + 2.0).log2();
+
+    let dcg: f64 = ranking
+        .iter()
+        .take(k)
+        .enumerate()
+        .map(|(rank, id)| gain(relevance.get(id).copied().unwrap_or(0)) / discount(rank))
+        .sum();
+
+    let mut ideal: Vec<i32> = relevance.values().copied().filter(|&rel| rel > 0).collect();
+    ideal.sort_unstable_by(|a, b| b.cmp(a));
+    let idcg: f64 = ideal
+        .into_iter()
+        .take(k)
+        .enumerate()
+        .map(|(rank, rel)| gain(rel) / discount(rank))
+        .sum();
+
+    if idcg == 0.0 {
+        0.0
+    } else {
+        dcg / idcg
+    }
+}
+
+/// Computes the reciprocal rank of the first relevant document within the top `k`.
+pub fn mrr_at_k(ranking: &[String], relevance: &HashMap<String, i32>, k: usize) -> f64 {
+    ranking
+        .iter()
+        .take(k)
+        .position(|id| is_relevant(relevance, id))
+        .map_or(0.0, |rank| 1.0 / (rank as f64 + 1.0))
+}
+
+/// Computes the fraction of the relevant documents found within the top `k`.
+pub fn recall_at_k(ranking: &[String], relevance: &HashMap<String, i32>, k: usize) -> f64 {
+    let num_relevant = relevance.values().filter(|&&rel| rel > 0).count();
+    if num_relevant == 0 {
+        return 0.0;
+    }
+    let found = ranking
+        .iter()
+        .take(k)
+        .filter(|id| is_relevant(relevance, id))
+        .count();
+    found as f64 / num_relevant as f64
+}
+
+/// Computes the average precision of a ranking.
+pub fn average_precision(ranking: &[String], relevance: &HashMap<String, i32>) -> f64 {
+    let num_relevant = relevance.values().filter(|&&rel| rel > 0).count();
+    if num_relevant == 0 {
+        return 0.0;
+    }
+    let mut found = 0;
+    let mut precision_sum = 0.0;
+    for (rank, id) in ranking.iter().enumerate() {
+        if is_relevant(relevance, id) {
+            found += 1;
+            precision_sum += found as f64 / (rank as f64 + 1.0);
+        }
+    }
+    precision_sum / num_relevant as f64
+}
+
+fn is_relevant(relevance: &HashMap<String, i32>, id: &str) -> bool {
+    relevance.get(id).is_some_and(|&rel| rel > 0)
+}
+
+/// Averages the metrics of `run` over the queries of `qrels` with a relevant document.
+///
+/// Judged queries missing from the run count as empty rankings.
+pub fn evaluate(run: &Run, qrels: &Qrels, cutoffs: &[usize]) -> EvaluationReport {
+    let mut report = EvaluationReport::default();
+    for &k in cutoffs {
+        report.ndcg.insert(k, 0.0);
+        report.mrr.insert(k, 0.0);
+        report.recall.insert(k, 0.0);
+    }
+    let empty = Vec::new();
+
+    for (query_id, relevance) in qrels {
+        if !relevance.values().any(|&rel| rel > 0) {
+            continue;
+        }
+        let ranking: Vec<String> = run
+            .get(query_id)
+            .unwrap_or(&empty)
+            .iter()
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        report.num_queries += 1;
+        for &k in cutoffs {
+            *report.ndcg.get_mut(&k).unwrap() += ndcg_at_k(&ranking, relevance, k);
+            *report.mrr.get_mut(&k).unwrap() += mrr_at_k(&ranking, relevance, k);
+            *report.recall.get_mut(&k).unwrap() += recall_at_k(&ranking, relevance, k);
+        }
+        report.map += average_precision(&ranking, relevance);
+    }
+
+    if report.num_queries > 0 {
+        let n = report.num_queries as f64;
+        for metric in [&mut report.ndcg, &mut report.mrr, &mut report.recall] {
+            metric.values_mut().for_each(|value| *value /= n);
+        }
+        report.map /= n;
+    }
+    report
+}
+
+/// Reads relevance judgments in BEIR TSV or TREC format.
+///
+/// BEIR files have a `query-id<TAB>corpus-id<TAB>score` header line. TREC files have
+/// whitespace-separated `query-id iteration corpus-id score` lines without a header.
+pub fn read_qrels<P: AsRef<Path>>(path: P) -> Result<Qrels, ColbertError> {
+    let content = fs::read_to_string(path.as_ref())?;
+    let mut qrels = Qrels::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let (query_id, document_id, score) = match columns.as_slice() {
+            [] => continue,
+            ["query-id", ..] => continue,
+            [query_id, document_id, score] => (query_id, document_id, score),
+            [query_id, _, document_id, score] => (query_id, document_id, score),
+            _ => {
+                return Err(ColbertError::Parse {
+                    path: path.as_ref().to_path_buf(),
+                    line: line_number + 1,
+                    reason: format!("expected 3 or 4 columns, found {}", columns.len()),
+                })
+            },
+        };
+        let score = score.parse::<i32>().map_err(|_| ColbertError::Parse {
+            path: path.as_ref().to_path_buf(),
+            line: line_number + 1,
+            reason: format!("invalid relevance score '{}'", score),
+        })?;
+        qrels
+            .entry(query_id.to_string())
+            .or_default()
+            .insert(document_id.to_string(), score);
+    }
+    Ok(qrels)
+}
+
+/// Writes a run in the TREC format `query-id Q0 document-id rank score tag`.
+pub fn write_trec_run<P: AsRef<Path>>(run: &Run, path: P, tag: &str) -> Result<(), ColbertError> {
+    let mut query_ids: Vec<&String> = run.keys().collect();
+    query_ids.sort();
+
+    let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+    for query_id in query_ids {
+        for (rank, (document_id, score)) in run[query_id].iter().enumerate() {
+            writeln!(
+                file,
+                "{} Q0 {} {} {} {}",
+                query_id,
+                document_id,
+                rank + 1,
+                score,
+                tag
+            )?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
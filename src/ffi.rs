@@ -0,0 +1,418 @@
+//! A C API for embedding ColBERT models in other languages, enabled by the `ffi` feature.
+//!
+//! The header `include/pylate_rs.h` is generated from this module by cbindgen when the
+//! crate is built with the feature. Every function returning an `int32_t` returns
+//! `COLBERT_OK` on success and a negative status otherwise, in which case
+//! `colbert_last_error` describes the failure. Embeddings are exchanged as ragged
+//! buffers: the rows of all the sentences concatenated in a row-major `float` array,
+//! together with the number of rows of each sentence.
+
+use crate::{
+    error::ColbertError, model::ColBERT, pooling::pool_documents, settings::ColbertOverrides,
+};
+use candle_core::{Device, Tensor};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    sync::{Mutex, PoisonError},
+};
+
+/// The call succeeded.
+pub const COLBERT_OK: i32 = 0;
+/// The call failed, see `colbert_last_error`.
+pub const COLBERT_ERROR: i32 = -1;
+/// A caller-provided buffer cannot hold the output, see `colbert_encode_into`.
+pub const COLBERT_BUFFER_TOO_SMALL: i32 = -2;
+
+/// A loaded ColBERT model. Calls on the same model from several threads are serialized.
+pub struct ColbertModel {
+    model: Mutex<ColBERT>,
+}
+
+/// Ragged embeddings allocated by the library, released with `colbert_embeddings_free`.
+#[repr(C)]
+pub struct ColbertEmbeddings {
+    /// The `[sum(lengths), dim]` rows of all the sentences, row-major.
+    pub data: *mut f32,
+    /// The number of rows of each sentence.
+    pub lengths: *mut usize,
+    /// The number of sentences.
+    pub num_sentences: usize,
+    /// The dimension of the embeddings.
+    pub dim: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, recording its error or panic for `colbert_last_error`.
+fn status(f: impl FnOnce() -> Result<i32, ColbertError>) -> i32 {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(code)) => code,
+        Ok(Err(err)) => {
+            set_last_error(err.to_string());
+            COLBERT_ERROR
+        },
+        Err(_) => {
+            set_last_error("pylate-rs panicked".to_string());
+            COLBERT_ERROR
+        },
+    }
+}
+
+fn null_pointer(name: &str) -> ColbertError {
+    ColbertError::NullPointer(name.to_string())
+}
+
+/// Reads a NUL-terminated UTF-8 string.
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<&'a str, ColbertError> {
+    if value.is_null() {
+        return Err(null_pointer(name));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| ColbertError::InvalidUtf8(name.to_string()))
+}
+
+/// Borrows `len` elements, allowing a null pointer when `len` is 0.
+unsafe fn read_slice<'a, T>(
+    data: *const T,
+    len: usize,
+    name: &str,
+) -> Result<&'a [T], ColbertError> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(null_pointer(name));
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+/// Mutably borrows `len` elements, allowing a null pointer when `len` is 0.
+unsafe fn write_slice<'a, T>(
+    data: *mut T,
+    len: usize,
+    name: &str,
+) -> Result<&'a mut [T], ColbertError> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    if data.is_null() {
+        return Err(null_pointer(name));
+    }
+    Ok(slice::from_raw_parts_mut(data, len))
+}
+
+/// Splits ragged embeddings into one `[length, dim]` tensor per sentence.
+unsafe fn read_embeddings(
+    data: *const f32,
+    lengths: *const usize,
+    num_sentences: usize,
+    dim: usize,
+    name: &str,
+) -> Result<Vec<Tensor>, ColbertError> {
+    let lengths = read_slice(lengths, num_sentences, name)?;
+    let num_rows: usize = lengths.iter().sum();
+    let data = read_slice(data, num_rows * dim, name)?;
+    let mut offset = 0;
+    lengths
+        .iter()
+        .map(|&length| {
+            let rows = &data[offset * dim..(offset + length) * dim];
+            offset += length;
+            Ok(Tensor::from_slice(rows, (length, dim), &Device::Cpu)?)
+        })
+        .collect()
+}
+
+/// Encodes sentences and returns their embeddings without padding rows, as the flat
+/// rows, the number of rows of each sentence and the embedding dimension.
+unsafe fn encode(
+    model: *mut ColbertModel,
+    sentences: *const *const c_char,
+    num_sentences: usize,
+    is_query: bool,
+) -> Result<(Vec<f32>, Vec<usize>, usize), ColbertError> {
+    let model = model.as_ref().ok_or_else(|| null_pointer("model"))?;
+    let sentences = read_slice(sentences, num_sentences, "sentences")?
+        .iter()
+        .map(|&sentence| read_str(sentence, "sentences").map(String::from))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut model = model.model.lock().unwrap_or_else(PoisonError::into_inner);
+    let embeddings = model.encode(&sentences, is_query)?;
+    flatten(&model.split_embeddings(&embeddings, is_query)?)
+}
+
+/// Concatenates `[length, dim]` tensors into flat rows and their lengths.
+fn flatten(embeddings: &[Tensor]) -> Result<(Vec<f32>, Vec<usize>, usize), ColbertError> {
+    let dim = match embeddings.first() {
+        Some(embedding) => embedding.dim(1)?,
+        None => 0,
+    };
+    let mut data = Vec::new();
+    let mut lengths = Vec::with_capacity(embeddings.len());
+    for embedding in embeddings {
+        lengths.push(embedding.dim(0)?);
+        data.extend(embedding.flatten_all()?.to_vec1::<f32>()?);
+    }
+    Ok((data, lengths, dim))
+}
+
+/// Hands buffers over to the caller, who releases them with `colbert_embeddings_free`.
+fn into_embeddings(data: Vec<f32>, lengths: Vec<usize>, dim: usize) -> ColbertEmbeddings {
+    let num_sentences = lengths.len();
+    ColbertEmbeddings {
+        data: Box::into_raw(data.into_boxed_slice()) as *mut f32,
+        lengths: Box::into_raw(lengths.into_boxed_slice()) as *mut usize,
+        num_sentences,
+        dim,
+    }
+}
+
+/// Returns the message of the last error raised on the calling thread, or null if no
+/// call has failed yet. The string is owned by the library and remains valid until the
+/// next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn colbert_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Loads a ColBERT model from a local directory on the CPU.
+///
+/// `options` is either null or a JSON object overriding the settings of the model, with
+/// the same fields as the options of the WASM constructor, e.g.
+/// `{"query_length": 32, "pool_factor": 2}`. Returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `options` null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_model_load(
+    path: *const c_char,
+    options: *const c_char,
+) -> *mut ColbertModel {
+    let mut model = ptr::null_mut();
+    status(|| {
+        let path = read_str(path, "path")?;
+        let overrides: ColbertOverrides = if options.is_null() {
+            ColbertOverrides::default()
+        } else {
+            serde_json::from_str(read_str(options, "options")?)?
+        };
+        let colbert: ColBERT = ColBERT::from(path).with_overrides(overrides).try_into()?;
+        model = Box::into_raw(Box::new(ColbertModel {
+            model: Mutex::new(colbert),
+        }));
+        Ok(COLBERT_OK)
+    });
+    model
+}
+
+/// Releases a model returned by `colbert_model_load`. Does nothing if `model` is null.
+///
+/// # Safety
+///
+/// `model` must be null or a model that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_model_free(model: *mut ColbertModel) {
+    if !model.is_null() {
+        drop(Box::from_raw(model));
+    }
+}
+
+/// Returns the dimension of the token embeddings of a model, or 0 if `model` is null.
+///
+/// # Safety
+///
+/// `model` must be null or a model returned by `colbert_model_load`.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_model_embedding_dim(model: *const ColbertModel) -> usize {
+    model.as_ref().map_or(0, |model| {
+        let model = model.model.lock().unwrap_or_else(PoisonError::into_inner);
+        model.info().embedding_dim
+    })
+}
+
+/// Encodes sentences into buffers allocated by the library.
+///
+/// Documents are returned without padding rows and pooled with the pool factor of the
+/// model. Queries keep their expansion tokens, and so have the same number of rows
+/// each, unless query expansion is disabled. On success, `out` must be
+/// released with `colbert_embeddings_free`.
+///
+/// # Safety
+///
+/// `model` must be a model returned by `colbert_model_load`, `sentences` must point to
+/// `num_sentences` NUL-terminated strings and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_encode(
+    model: *mut ColbertModel,
+    sentences: *const *const c_char,
+    num_sentences: usize,
+    is_query: bool,
+    out: *mut ColbertEmbeddings,
+) -> i32 {
+    status(|| {
+        let out = out.as_mut().ok_or_else(|| null_pointer("out"))?;
+        let (data, lengths, dim) = encode(model, sentences, num_sentences, is_query)?;
+        *out = into_embeddings(data, lengths, dim);
+        Ok(COLBERT_OK)
+    })
+}
+
+/// Encodes sentences into buffers provided by the caller.
+///
+/// The number of rows of each sentence is written to `lengths`, which holds
+/// `num_sentences` elements, and the rows to `data`, which holds `capacity` floats.
+/// When `data` is too small, nothing is written to it and `COLBERT_BUFFER_TOO_SMALL` is
+/// returned. In every case where encoding succeeds, the number of floats of the output
+/// is written to `required` unless it is null.
+///
+/// # Safety
+///
+/// `model` must be a model returned by `colbert_model_load`, `sentences` must point to
+/// `num_sentences` NUL-terminated strings, `data` to `capacity` floats, `lengths` to
+/// `num_sentences` elements and `required` must be null or valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn colbert_encode_into(
+    model: *mut ColbertModel,
+    sentences: *const *const c_char,
+    num_sentences: usize,
+    is_query: bool,
+    data: *mut f32,
+    capacity: usize,
+    lengths: *mut usize,
+    required: *mut usize,
+) -> i32 {
+    status(|| {
+        let (embeddings, embeddings_lengths, _) =
+            encode(model, sentences, num_sentences, is_query)?;
+        write_slice(lengths, num_sentences, "lengths")?.copy_from_slice(&embeddings_lengths);
+        if let Some(required) = required.as_mut() {
+            *required = embeddings.len();
+        }
+        if embeddings.len() > capacity {
+            return Ok(COLBERT_BUFFER_TOO_SMALL);
+        }
+        write_slice(data, embeddings.len(), "data")?.copy_from_slice(&embeddings);
+        Ok(COLBERT_OK)
+    })
+}
+
+/// Releases the buffers of embeddings returned by the library and resets `embeddings`.
+/// Does nothing if `embeddings` is null.
+///
+/// # Safety
+///
+/// `embeddings` must be null or filled by `colbert_encode` or
+/// `colbert_hierarchical_pooling` and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_embeddings_free(embeddings: *mut ColbertEmbeddings) {
+    let Some(embeddings) = embeddings.as_mut() else {
+        return;
+    };
+    let num_rows: usize = read_slice(embeddings.lengths, embeddings.num_sentences, "lengths")
+        .map(|lengths| lengths.iter().sum())
+        .unwrap_or_default();
+    if !embeddings.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            embeddings.data,
+            num_rows * embeddings.dim,
+        )));
+    }
+    if !embeddings.lengths.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            embeddings.lengths,
+            embeddings.num_sentences,
+        )));
+    }
+    *embeddings = ColbertEmbeddings {
+        data: ptr::null_mut(),
+        lengths: ptr::null_mut(),
+        num_sentences: 0,
+        dim: 0,
+    };
+}
+
+/// Computes the MaxSim score of every query against every document.
+///
+/// Queries and documents are ragged embeddings of dimension `dim`, such as the output
+/// of `colbert_encode`. The `[num_queries, num_documents]` scores are written row-major
+/// to `scores`.
+///
+/// # Safety
+///
+/// `queries` and `documents` must hold the rows given by their lengths, the lengths
+/// arrays must hold `num_queries` and `num_documents` elements and `scores` must hold
+/// `num_queries * num_documents` floats.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn colbert_maxsim(
+    queries: *const f32,
+    query_lengths: *const usize,
+    num_queries: usize,
+    documents: *const f32,
+    document_lengths: *const usize,
+    num_documents: usize,
+    dim: usize,
+    scores: *mut f32,
+) -> i32 {
+    status(|| {
+        let queries = read_embeddings(queries, query_lengths, num_queries, dim, "queries")?;
+        let documents =
+            read_embeddings(documents, document_lengths, num_documents, dim, "documents")?;
+        let scores = write_slice(scores, num_queries * num_documents, "scores")?;
+        for (query, row) in queries.iter().zip(scores.chunks_mut(num_documents.max(1))) {
+            for (document, score) in documents.iter().zip(row.iter_mut()) {
+                *score = query
+                    .matmul(&document.t()?)?
+                    .max(1)?
+                    .sum_all()?
+                    .to_scalar::<f32>()?;
+            }
+        }
+        Ok(COLBERT_OK)
+    })
+}
+
+/// Reduces the number of rows of each document by `pool_factor` with hierarchical
+/// pooling, writing the pooled embeddings to buffers allocated by the library.
+///
+/// On success, `out` must be released with `colbert_embeddings_free`.
+///
+/// # Safety
+///
+/// `embeddings` must hold the rows given by `lengths`, which holds `num_documents`
+/// elements, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn colbert_hierarchical_pooling(
+    embeddings: *const f32,
+    lengths: *const usize,
+    num_documents: usize,
+    dim: usize,
+    pool_factor: usize,
+    out: *mut ColbertEmbeddings,
+) -> i32 {
+    status(|| {
+        let out = out.as_mut().ok_or_else(|| null_pointer("out"))?;
+        let documents = read_embeddings(embeddings, lengths, num_documents, dim, "embeddings")?;
+        let (data, lengths, _) = flatten(&pool_documents(&documents, pool_factor)?)?;
+        *out = into_embeddings(data, lengths, dim);
+        Ok(COLBERT_OK)
+    })
+}
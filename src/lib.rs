@@ -1,21 +1,63 @@
+pub mod bert;
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 pub mod builder;
+pub mod cache;
+pub mod cross_encoder;
 pub mod error;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod evaluation;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod masked_lm;
 pub mod model;
 pub mod modernbert;
 pub mod pooling;
+pub mod progress;
+#[cfg(feature = "server")]
+pub mod server;
+pub(crate) mod settings;
+pub mod storage;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod stream;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod training;
 pub mod types;
 pub mod utils;
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
+// The fixtures of the integration tests refer to the crate by name.
+#[cfg(test)]
+extern crate self as pylate_rs;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod fixtures;
+
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
-pub use builder::ColbertBuilder;
+pub use builder::{ColbertBuilder, CrossEncoderBuilder, MaskedLMBuilder};
+pub use cache::EmbeddingCache;
+pub use cross_encoder::CrossEncoder;
 pub use error::ColbertError;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use evaluation::{evaluate, read_qrels, write_trec_run, EvaluationReport, Qrels, Run};
+pub use masked_lm::MaskedLM;
 pub use model::{BaseModel, ColBERT};
 pub use pooling::hierarchical_pooling;
-pub use types::{EncodeInput, EncodeOutput, RawSimilarityOutput, Similarities, SimilarityInput};
-pub use utils::normalize_l2;
+pub use progress::{CancellationToken, EncodeProgress, ProgressCallback};
+#[cfg(feature = "server")]
+pub use server::{EmbeddingServer, ServerConfig, ServerStats};
+pub use storage::EmbeddingCollection;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use storage::EmbeddingStore;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use stream::{EncodeStream, EncodedBatch};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use training::{ColbertTrainer, ContrastiveExample, DistillationExample, TrainingConfig};
+pub use types::{
+    EncodeInput, EncodeOutput, EncodeRequest, MaskPrediction, ModelInfo, RankedDocument,
+    RawSimilarityOutput, RerankRequest, Similarities, SimilarityInput,
+};
+pub use utils::{default_skiplist_words, normalize_l2, unpad_embeddings};
 
 #[cfg(feature = "python")]
 pub mod python;
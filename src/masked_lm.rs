@@ -0,0 +1,246 @@
+use crate::{
+    error::ColbertError,
+    model::ColBERT,
+    modernbert::{Config as ModernBertConfig, ModernBertForMaskedLM},
+    types::MaskPrediction,
+};
+use candle_core::{DType, Device, IndexOp, Tensor, D};
+use candle_nn::{ops::softmax, VarBuilder};
+use candle_transformers::models::bert::{BertForMaskedLM, Config as BertConfig};
+use tokenizers::Tokenizer;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::builder::MaskedLMBuilder;
+
+/// An enum to abstract over the supported masked language models.
+pub enum MaskedLMModel {
+    /// A variant holding a `ModernBertForMaskedLM` model.
+    ModernBert(ModernBertForMaskedLM),
+    /// A variant holding a `BertForMaskedLM` model.
+    Bert(Box<BertForMaskedLM>),
+}
+
+impl MaskedLMModel {
+    /// Performs a forward pass and returns the vocabulary logits of every position.
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        token_type_ids: &Tensor,
+    ) -> Result<Tensor, candle_core::Error> {
+        match self {
+            MaskedLMModel::ModernBert(model) => model.forward(input_ids, attention_mask),
+            MaskedLMModel::Bert(model) => {
+                model.forward(input_ids, token_type_ids, Some(attention_mask))
+            },
+        }
+    }
+}
+
+/// A masked language model used for fill-mask predictions.
+///
+/// PyLate checkpoints only ship the encoder, so this is loaded from the base
+/// model a ColBERT was trained from (e.g. `answerdotai/ModernBERT-base`). It can
+/// then be used to inspect what the `[MASK]` query-expansion tokens of a `ColBERT`
+/// model would decode to.
+pub struct MaskedLM {
+    pub(crate) model: MaskedLMModel,
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) mask_token_id: u32,
+    pub(crate) vocab_size: usize,
+    /// The device (CPU or GPU) on which the model is loaded.
+    pub device: Device,
+}
+
+impl MaskedLM {
+    /// Creates a new instance of the `MaskedLM` model from byte buffers.
+    pub fn new(
+        weights: Vec<u8>,
+        tokenizer_bytes: Vec<u8>,
+        config_bytes: Vec<u8>,
+        mask_token: String,
+        device: &Device,
+    ) -> Result<Self, ColbertError> {
+        let vb = VarBuilder::from_buffered_safetensors(weights, DType::F32, device)?;
+
+        let config_value: serde_json::Value = serde_json::from_slice(&config_bytes)?;
+        let architectures = config_value["architectures"]
+            .as_array()
+            .and_then(|arr| arr.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ColbertError::missing_config_field("config.json", "architectures"))?;
+
+        let (model, vocab_size) = match architectures {
+            "ModernBertForMaskedLM" => {
+                let config: ModernBertConfig = serde_json::from_slice(&config_bytes)?;
+                let model = ModernBertForMaskedLM::load(vb, &config)?;
+                (MaskedLMModel::ModernBert(model), config.vocab_size)
+            },
+            "BertForMaskedLM" => {
+                let config: BertConfig = serde_json::from_slice(&config_bytes)?;
+                let model = BertForMaskedLM::load(vb, &config)?;
+                (MaskedLMModel::Bert(Box::new(model)), config.vocab_size)
+            },
+            arch => return Err(ColbertError::UnsupportedArchitecture(arch.to_string())),
+        };
+
+        let tokenizer = Tokenizer::from_bytes(&tokenizer_bytes)?;
+        let mask_token_id = tokenizer
+            .token_to_id(mask_token.as_str())
+            .ok_or_else(|| ColbertError::UnknownToken(mask_token.clone()))?;
+
+        Ok(Self {
+            model,
+            tokenizer,
+            mask_token_id,
+            vocab_size,
+            device: device.clone(),
+        })
+    }
+
+    /// Creates a `MaskedLMBuilder` to construct a `MaskedLM` from a Hugging Face repository.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn from(repo_id: &str) -> MaskedLMBuilder {
+        MaskedLMBuilder::new(repo_id)
+    }
+
+    /// Predicts the `top_k` most likely tokens for every mask token in `text`.
+    pub fn fill_mask(
+        &mut self,
+        text: &str,
+        top_k: usize,
+    ) -> Result<Vec<MaskPrediction>, ColbertError> {
+        self.tokenizer.with_padding(None);
+        let encoding = self.tokenizer.encode(text, true)?;
+
+        let ids = encoding.get_ids();
+        let positions: Vec<usize> = ids
+            .iter()
+            .enumerate()
+            .filter(|(_, &id)| id == self.mask_token_id)
+            .map(|(position, _)| position)
+            .collect();
+        if positions.is_empty() {
+            return Err(ColbertError::MissingMaskToken);
+        }
+
+        let seq_len = ids.len();
+        let token_ids = Tensor::from_slice(ids, (1, seq_len), &self.device)?;
+        let attention_mask =
+            Tensor::from_slice(encoding.get_attention_mask(), (1, seq_len), &self.device)?;
+        let token_type_ids =
+            Tensor::from_slice(encoding.get_type_ids(), (1, seq_len), &self.device)?;
+
+        let mut predictions = self.predict(
+            &token_ids,
+            &attention_mask,
+            &token_type_ids,
+            &[positions],
+            top_k,
+        )?;
+        Ok(predictions.remove(0))
+    }
+
+    /// Returns the `top_k` predictions at the given positions of each sequence.
+    pub(crate) fn predict(
+        &self,
+        token_ids: &Tensor,
+        attention_mask: &Tensor,
+        token_type_ids: &Tensor,
+        positions: &[Vec<usize>],
+        top_k: usize,
+    ) -> Result<Vec<Vec<MaskPrediction>>, ColbertError> {
+        let logits = self
+            .model
+            .forward(token_ids, attention_mask, token_type_ids)?;
+
+        let mut all_predictions = Vec::with_capacity(positions.len());
+        for (i, sequence_positions) in positions.iter().enumerate() {
+            let mut predictions = Vec::with_capacity(sequence_positions.len());
+            for &position in sequence_positions {
+                let probabilities =
+                    softmax(&logits.i((i, position))?, D::Minus1)?.to_vec1::<f32>()?;
+
+                let mut ranked: Vec<(usize, f32)> = probabilities.into_iter().enumerate().collect();
+                ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+                ranked.truncate(top_k);
+
+                let token_ids: Vec<u32> = ranked.iter().map(|&(id, _)| id as u32).collect();
+                predictions.push(MaskPrediction {
+                    position,
+                    tokens: token_ids
+                        .iter()
+                        .map(|&id| self.tokenizer.id_to_token(id).unwrap_or_default())
+                        .collect(),
+                    token_ids,
+                    scores: ranked.iter().map(|&(_, score)| score).collect(),
+                });
+            }
+            all_predictions.push(predictions);
+        }
+
+        Ok(all_predictions)
+    }
+}
+
+impl ColBERT {
+    /// Predicts the `top_k` most likely vocabulary tokens for each query-expansion
+    /// position of the given queries.
+    ///
+    /// Queries are tokenized exactly as `encode` tokenizes them, so expansion
+    /// positions are the mask tokens appended as padding. Tokens are then mapped to
+    /// the masked LM vocabulary: expansion positions get its mask token, as do tokens
+    /// it does not know (such as the `[Q]` prefix added by PyLate).
+    pub fn query_expansion_tokens(
+        &mut self,
+        masked_lm: &MaskedLM,
+        queries: &[String],
+        top_k: usize,
+    ) -> Result<Vec<Vec<MaskPrediction>>, ColbertError> {
+        if queries.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+
+        let (token_ids, attention_mask, token_type_ids) = self.tokenize(queries, true)?;
+        let (batch_size, seq_len) = token_ids.dims2()?;
+        let ids = token_ids.to_vec2::<u32>()?;
+
+        // The expansion tokens are the trailing mask tokens used as padding.
+        let positions: Vec<Vec<usize>> = ids
+            .iter()
+            .map(|sequence_ids| {
+                let num_expansion = sequence_ids
+                    .iter()
+                    .rev()
+                    .take_while(|&&id| id == self.mask_token_id)
+                    .count();
+                (seq_len - num_expansion..seq_len).collect()
+            })
+            .collect();
+
+        // The tokenizers may differ, so tokens are mapped by their text.
+        let mut masked_lm_ids = Vec::with_capacity(batch_size * seq_len);
+        for (sequence_ids, sequence_positions) in ids.iter().zip(&positions) {
+            for (position, &id) in sequence_ids.iter().enumerate() {
+                let masked_lm_id = if sequence_positions.contains(&position) {
+                    None
+                } else {
+                    self.tokenizer
+                        .id_to_token(id)
+                        .and_then(|token| masked_lm.tokenizer.token_to_id(&token))
+                        .filter(|&id| (id as usize) < masked_lm.vocab_size)
+                };
+                masked_lm_ids.push(masked_lm_id.unwrap_or(masked_lm.mask_token_id));
+            }
+        }
+        let token_ids = Tensor::from_vec(masked_lm_ids, (batch_size, seq_len), &masked_lm.device)?;
+
+        masked_lm.predict(
+            &token_ids,
+            &attention_mask.to_device(&masked_lm.device)?,
+            &token_type_ids.to_device(&masked_lm.device)?,
+            &positions,
+            top_k,
+        )
+    }
+}
@@ -1,12 +1,22 @@
 use crate::{
+    bert::BertModel,
+    cache::EmbeddingCache,
     error::ColbertError,
     modernbert::{Config as ModernBertConfig, ModernBert},
-    types::Similarities,
-    utils::normalize_l2,
+    pooling::pool_documents,
+    progress::{CancellationToken, ProgressCallback, ProgressTracker},
+    types::{ModelInfo, Similarities},
+    utils::{default_skiplist_words, normalize_l2, pad_embeddings, unpad_embeddings},
+};
+use candle_core::{
+    safetensors::BufferedSafetensors, DType, Device, DeviceLocation, IndexOp, Shape, Tensor,
+};
+use candle_nn::{var_builder::SimpleBackend, Init, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::Config as BertConfig;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
 };
-use candle_core::{DType, Device, IndexOp, Tensor};
-use candle_nn::{Linear, Module, VarBuilder};
-use candle_transformers::models::bert::{BertModel, Config as BertConfig};
 use tokenizers::Tokenizer;
 
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
@@ -45,6 +55,64 @@ impl BaseModel {
     }
 }
 
+/// A variable builder backend that keeps the tensors handed out to the model, so that
+/// the weights a model was built with can be written back by `save_pretrained`.
+struct RecordingBackend {
+    inner: Box<dyn SimpleBackend>,
+    tensors: Arc<Mutex<HashMap<String, Tensor>>>,
+}
+
+impl RecordingBackend {
+    /// Wraps `inner` in a `VarBuilder` and returns the map filled as tensors are loaded.
+    fn var_builder(
+        inner: Box<dyn SimpleBackend>,
+        device: &Device,
+    ) -> (VarBuilder<'static>, Arc<Mutex<HashMap<String, Tensor>>>) {
+        let tensors = Arc::new(Mutex::new(HashMap::new()));
+        let backend = RecordingBackend {
+            inner,
+            tensors: tensors.clone(),
+        };
+        let vb = VarBuilder::from_backend(Box::new(backend), DType::F32, device.clone());
+        (vb, tensors)
+    }
+
+    fn record(&self, name: &str, tensor: &Tensor) {
+        let mut tensors = self.tensors.lock().unwrap_or_else(|e| e.into_inner());
+        tensors.insert(name.to_string(), tensor.clone());
+    }
+
+    /// Takes the tensors recorded so far.
+    fn take(tensors: &Mutex<HashMap<String, Tensor>>) -> HashMap<String, Tensor> {
+        std::mem::take(&mut *tensors.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl SimpleBackend for RecordingBackend {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        h: Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let tensor = self.inner.get(s, name, h, dtype, dev)?;
+        self.record(name, &tensor);
+        Ok(tensor)
+    }
+
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> candle_core::Result<Tensor> {
+        let tensor = self.inner.get_unchecked(name, dtype, dev)?;
+        self.record(name, &tensor);
+        Ok(tensor)
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.inner.contains_tensor(name)
+    }
+}
+
 /// The main ColBERT model structure.
 ///
 /// This struct encapsulates the language model, a linear projection layer,
@@ -58,6 +126,7 @@ pub struct ColBERT {
     pub(crate) tokenizer: Tokenizer,
     pub(crate) mask_token_id: u32,
     pub(crate) mask_token: String,
+    pub(crate) skiplist_ids: HashSet<u32>,
     pub(crate) query_prefix: String,
     pub(crate) document_prefix: String,
     pub(crate) do_query_expansion: bool,
@@ -65,6 +134,31 @@ pub struct ColBERT {
     pub(crate) query_length: usize,
     pub(crate) document_length: usize,
     pub(crate) batch_size: usize,
+    /// The factor by which `encode` pools document embeddings, 1 disabling pooling.
+    pub(crate) pool_factor: usize,
+    /// The repository or directory the model was loaded from, set by `ColbertBuilder`.
+    pub(crate) model_id: Option<String>,
+    /// Identifies the weights the model was loaded with, set by `ColbertBuilder`.
+    pub(crate) revision: Option<String>,
+    /// The cache consulted by `encode`, if any.
+    pub(crate) cache: Option<EmbeddingCache>,
+    /// The function `encode` reports its progress to after each batch, if any.
+    pub(crate) progress: Option<ProgressCallback>,
+    /// The token `encode` checks before each batch, if any.
+    pub(crate) cancellation: Option<CancellationToken>,
+    /// The backbone configuration and the tensors the backbone was built with, which
+    /// `save_pretrained` writes back.
+    pub(crate) config: serde_json::Value,
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), allow(dead_code))]
+    pub(crate) weights: HashMap<String, Tensor>,
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), allow(dead_code))]
+    pub(crate) dense_config: serde_json::Value,
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), allow(dead_code))]
+    pub(crate) dense_weights: HashMap<String, Tensor>,
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), allow(dead_code))]
+    pub(crate) dense2_config: Option<serde_json::Value>,
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), allow(dead_code))]
+    pub(crate) dense2_weights: Option<HashMap<String, Tensor>>,
     /// The device (CPU or GPU) on which the model is loaded.
     #[cfg_attr(feature = "wasm", wasm_bindgen(skip))]
     pub device: Device,
@@ -72,6 +166,10 @@ pub struct ColBERT {
 
 impl ColBERT {
     /// Creates a new instance of the `ColBERT` model from byte buffers.
+    ///
+    /// The embeddings of punctuation tokens are dropped from documents; use
+    /// `new_with_skiplist` to choose the skipped tokens.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         weights: Vec<u8>,
         dense_weights: Vec<u8>,
@@ -90,97 +188,194 @@ impl ColBERT {
         batch_size: Option<usize>,
         device: &Device,
     ) -> Result<Self, ColbertError> {
-        let vb = VarBuilder::from_buffered_safetensors(weights, DType::F32, device)?;
+        Self::new_with_skiplist(
+            weights,
+            dense_weights,
+            dense2_weights,
+            tokenizer_bytes,
+            config_bytes,
+            dense_config_bytes,
+            dense2_config_bytes,
+            query_prefix,
+            document_prefix,
+            mask_token,
+            default_skiplist_words(),
+            do_query_expansion,
+            attend_to_expansion_tokens,
+            query_length,
+            document_length,
+            batch_size,
+            device,
+        )
+    }
+
+    /// Creates a new instance of the `ColBERT` model from byte buffers with the given
+    /// skiplist.
+    ///
+    /// `skiplist_words` lists the tokens whose embeddings are dropped from documents
+    /// after the forward pass. Words that are not part of the tokenizer's vocabulary
+    /// are ignored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_skiplist(
+        weights: Vec<u8>,
+        dense_weights: Vec<u8>,
+        dense2_weights: Option<Vec<u8>>,
+        tokenizer_bytes: Vec<u8>,
+        config_bytes: Vec<u8>,
+        dense_config_bytes: Vec<u8>,
+        dense2_config_bytes: Option<Vec<u8>>,
+        query_prefix: String,
+        document_prefix: String,
+        mask_token: String,
+        skiplist_words: Vec<String>,
+        do_query_expansion: bool,
+        attend_to_expansion_tokens: bool,
+        query_length: Option<usize>,
+        document_length: Option<usize>,
+        batch_size: Option<usize>,
+        device: &Device,
+    ) -> Result<Self, ColbertError> {
+        let backend = Box::new(BufferedSafetensors::new(weights)?);
+        let dense_backend = Box::new(BufferedSafetensors::new(dense_weights)?);
+        let dense2_backend = dense2_weights
+            .map(|weights| -> Result<Box<dyn SimpleBackend>, ColbertError> {
+                Ok(Box::new(BufferedSafetensors::new(weights)?))
+            })
+            .transpose()?;
+
+        Self::from_backends(
+            backend,
+            dense_backend,
+            dense2_backend,
+            &tokenizer_bytes,
+            &config_bytes,
+            &dense_config_bytes,
+            dense2_config_bytes.as_deref(),
+            query_prefix,
+            document_prefix,
+            mask_token,
+            skiplist_words,
+            do_query_expansion,
+            attend_to_expansion_tokens,
+            query_length,
+            document_length,
+            batch_size,
+            device,
+        )
+    }
+
+    /// Creates a new instance of the `ColBERT` model from backends holding the backbone
+    /// and Dense weights, so that they can be backed by trainable variables.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_backends(
+        backend: Box<dyn SimpleBackend>,
+        dense_backend: Box<dyn SimpleBackend>,
+        dense2_backend: Option<Box<dyn SimpleBackend>>,
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+        dense_config_bytes: &[u8],
+        dense2_config_bytes: Option<&[u8]>,
+        query_prefix: String,
+        document_prefix: String,
+        mask_token: String,
+        skiplist_words: Vec<String>,
+        do_query_expansion: bool,
+        attend_to_expansion_tokens: bool,
+        query_length: Option<usize>,
+        document_length: Option<usize>,
+        batch_size: Option<usize>,
+        device: &Device,
+    ) -> Result<Self, ColbertError> {
+        let (vb, weights) = RecordingBackend::var_builder(backend, device);
+        let (dense_vb, dense_weights) = RecordingBackend::var_builder(dense_backend, device);
 
-        let config_value: serde_json::Value = serde_json::from_slice(&config_bytes)?;
+        let config_value: serde_json::Value = serde_json::from_slice(config_bytes)?;
         let architectures = config_value["architectures"]
             .as_array()
-            .and_then(|arr| arr.get(0))
+            .and_then(|arr| arr.first())
             .and_then(|v| v.as_str())
-            .ok_or_else(|| {
-                ColbertError::Operation("Missing or invalid 'architectures' in config.json".into())
-            })?;
+            .ok_or_else(|| ColbertError::missing_config_field("config.json", "architectures"))?;
 
         let model = match architectures {
             "ModernBertModel" => {
-                let config: ModernBertConfig = serde_json::from_slice(&config_bytes)?;
-                let model = ModernBert::load(vb.clone(), &config)?;
+                let config: ModernBertConfig = serde_json::from_slice(config_bytes)?;
+                let model = ModernBert::load(vb, &config)?;
                 BaseModel::ModernBert(model)
             },
             "BertForMaskedLM" | "BertModel" => {
-                let config: BertConfig = serde_json::from_slice(&config_bytes)?;
-                let model = BertModel::load(vb.clone(), &config)?;
+                let config: BertConfig = serde_json::from_slice(config_bytes)?;
+                let model = BertModel::load(vb, &config)?;
                 BaseModel::Bert(model)
             },
-            arch => {
-                return Err(ColbertError::Operation(format!(
-                    "Unsupported architecture: {}",
-                    arch
-                )))
-            },
+            arch => return Err(ColbertError::UnsupportedArchitecture(arch.to_string())),
         };
 
-        let dense_config: serde_json::Value = serde_json::from_slice(&dense_config_bytes)?;
-        let tokenizer = Tokenizer::from_bytes(&tokenizer_bytes)?;
+        let dense_config: serde_json::Value = serde_json::from_slice(dense_config_bytes)?;
+        let tokenizer = Tokenizer::from_bytes(tokenizer_bytes)?;
 
-        let mask_token_id = tokenizer.token_to_id(mask_token.as_str()).ok_or_else(|| {
-            ColbertError::Operation(format!(
-                "Token '{}' not found in the tokenizer's vocabulary.",
-                mask_token
-            ))
-        })?;
+        let mask_token_id = tokenizer
+            .token_to_id(mask_token.as_str())
+            .ok_or_else(|| ColbertError::UnknownToken(mask_token.clone()))?;
+
+        let skiplist_ids = skiplist_words
+            .iter()
+            .filter_map(|word| tokenizer.token_to_id(word))
+            .collect();
 
-        let dense_vb = VarBuilder::from_buffered_safetensors(dense_weights, DType::F32, device)?;
         let in_features = dense_config["in_features"]
             .as_u64()
             .map(|v| v as usize)
             .ok_or_else(|| {
-                ColbertError::Operation("Missing 'in_features' in dense config".into())
+                ColbertError::missing_config_field("1_Dense/config.json", "in_features")
             })?;
         let out_features = dense_config["out_features"]
             .as_u64()
             .map(|v| v as usize)
             .ok_or_else(|| {
-                ColbertError::Operation("Missing 'out_features' in dense config".into())
+                ColbertError::missing_config_field("1_Dense/config.json", "out_features")
             })?;
 
         let linear = candle_nn::linear_no_bias(in_features, out_features, dense_vb.pp("linear"))?;
 
         // Load optional 2_Dense layer if present
-        let linear2 = if let (Some(weights2), Some(config2)) = (dense2_weights, dense2_config_bytes)
+        let (linear2, dense2) = if let (Some(dense2_backend), Some(config2)) =
+            (dense2_backend, dense2_config_bytes)
         {
-            let dense2_vb = VarBuilder::from_buffered_safetensors(weights2, DType::F32, device)?;
-            let dense2_config: serde_json::Value = serde_json::from_slice(&config2)?;
+            let (dense2_vb, dense2_weights) = RecordingBackend::var_builder(dense2_backend, device);
+            let dense2_config: serde_json::Value = serde_json::from_slice(config2)?;
 
             let in_features2 = dense2_config["in_features"]
                 .as_u64()
                 .map(|v| v as usize)
                 .ok_or_else(|| {
-                    ColbertError::Operation("Missing 'in_features' in 2_Dense config".into())
+                    ColbertError::missing_config_field("2_Dense/config.json", "in_features")
                 })?;
             let out_features2 = dense2_config["out_features"]
                 .as_u64()
                 .map(|v| v as usize)
                 .ok_or_else(|| {
-                    ColbertError::Operation("Missing 'out_features' in 2_Dense config".into())
+                    ColbertError::missing_config_field("2_Dense/config.json", "out_features")
                 })?;
 
             // Validate that output of 1_Dense matches input of 2_Dense
             if out_features != in_features2 {
-                return Err(ColbertError::Operation(format!(
-                    "Dimension mismatch: 1_Dense output ({}) != 2_Dense input ({})",
-                    out_features, in_features2
-                )));
+                return Err(ColbertError::DimensionMismatch {
+                    context: "2_Dense input features".into(),
+                    expected: out_features,
+                    found: in_features2,
+                });
             }
 
-            Some(candle_nn::linear_no_bias(
-                in_features2,
-                out_features2,
-                dense2_vb.pp("linear"),
-            )?)
+            let linear2 =
+                candle_nn::linear_no_bias(in_features2, out_features2, dense2_vb.pp("linear"))?;
+            (
+                Some(linear2),
+                Some((dense2_config, RecordingBackend::take(&dense2_weights))),
+            )
         } else {
-            None
+            (None, None)
         };
+        let (dense2_config, dense2_weights) = dense2.unzip();
 
         // If do_query_expansion is false, attend_to_expansion_tokens should also be false
         let final_attend_to_expansion_tokens = if !do_query_expansion {
@@ -195,7 +390,8 @@ impl ColBERT {
             linear2,
             tokenizer,
             mask_token_id,
-            mask_token: mask_token,
+            mask_token,
+            skiplist_ids,
             query_prefix,
             document_prefix,
             do_query_expansion,
@@ -203,6 +399,18 @@ impl ColBERT {
             query_length: query_length.unwrap_or(32),
             document_length: document_length.unwrap_or(180),
             batch_size: batch_size.unwrap_or(32),
+            pool_factor: 1,
+            model_id: None,
+            revision: None,
+            cache: None,
+            progress: None,
+            cancellation: None,
+            config: config_value,
+            weights: RecordingBackend::take(&weights),
+            dense_config,
+            dense_weights: RecordingBackend::take(&dense_weights),
+            dense2_config,
+            dense2_weights,
             device: device.clone(),
         })
     }
@@ -213,17 +421,142 @@ impl ColBERT {
         ColbertBuilder::new(repo_id)
     }
 
+    /// Describes the loaded model, e.g. to check that stored embeddings match its output.
+    pub fn info(&self) -> ModelInfo {
+        let embedding_dim = self
+            .linear2
+            .as_ref()
+            .unwrap_or(&self.linear)
+            .weight()
+            .dims2()
+            .map_or(0, |(out_features, _)| out_features);
+        let architecture = self.config["architectures"]
+            .as_array()
+            .and_then(|arr| arr.first())
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let device = match self.device.location() {
+            DeviceLocation::Cpu => "cpu".to_string(),
+            DeviceLocation::Cuda { gpu_id } => format!("cuda:{}", gpu_id),
+            DeviceLocation::Metal { .. } => "mps".to_string(),
+        };
+
+        ModelInfo {
+            model_id: self.model_id.clone(),
+            architecture,
+            embedding_dim,
+            vocab_size: self.tokenizer.get_vocab_size(true),
+            query_length: self.query_length,
+            document_length: self.document_length,
+            query_prefix: self.query_prefix.clone(),
+            document_prefix: self.document_prefix.clone(),
+            mask_token: self.mask_token.clone(),
+            do_query_expansion: self.do_query_expansion,
+            attend_to_expansion_tokens: self.attend_to_expansion_tokens,
+            pool_factor: self.pool_factor,
+            device,
+        }
+    }
+
+    /// Writes the model to `path` in the PyLate layout read by `ColbertBuilder`.
+    ///
+    /// The backbone and Dense weights are stored as the model holds them, so fine-tuned
+    /// weights round-trip, and `config_sentence_transformers.json` records the effective
+    /// prefixes, lengths, query expansion flags and skiplist, including any setting
+    /// overridden through the builder.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn save_pretrained<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), ColbertError> {
+        use serde_json::json;
+        use std::fs;
+
+        let path = path.as_ref();
+        let mut modules = vec![json!({
+            "idx": 0,
+            "name": "0",
+            "path": "",
+            "type": "sentence_transformers.models.Transformer",
+        })];
+        let dense_modules = [
+            Some((&self.dense_config, &self.dense_weights)),
+            self.dense2_config
+                .as_ref()
+                .zip(self.dense2_weights.as_ref()),
+        ];
+        for (idx, (config, weights)) in dense_modules.into_iter().flatten().enumerate() {
+            let name = format!("{}_Dense", idx + 1);
+            fs::create_dir_all(path.join(&name))?;
+            fs::write(
+                path.join(&name).join("config.json"),
+                serde_json::to_vec_pretty(config)?,
+            )?;
+            candle_core::safetensors::save(weights, path.join(&name).join("model.safetensors"))?;
+            modules.push(json!({
+                "idx": idx + 1,
+                "name": (idx + 1).to_string(),
+                "path": name,
+                "type": "pylate.models.Dense.Dense",
+            }));
+        }
+
+        fs::write(
+            path.join("config.json"),
+            serde_json::to_vec_pretty(&self.config)?,
+        )?;
+        candle_core::safetensors::save(&self.weights, path.join("model.safetensors"))?;
+        fs::write(
+            path.join("modules.json"),
+            serde_json::to_vec_pretty(&modules)?,
+        )?;
+
+        // Encoding sets the truncation and padding of the tokenizer, which are not part
+        // of the saved model.
+        let mut tokenizer = self.tokenizer.clone();
+        tokenizer.with_padding(None);
+        tokenizer.with_truncation(None)?;
+        tokenizer.save(path.join("tokenizer.json"), false)?;
+
+        let mut skiplist_words: Vec<String> = self
+            .skiplist_ids
+            .iter()
+            .filter_map(|&id| self.tokenizer.id_to_token(id))
+            .collect();
+        skiplist_words.sort();
+        let st_config = json!({
+            "query_prefix": self.query_prefix,
+            "document_prefix": self.document_prefix,
+            "query_length": self.query_length,
+            "document_length": self.document_length,
+            "do_query_expansion": self.do_query_expansion,
+            "attend_to_expansion_tokens": self.attend_to_expansion_tokens,
+            "skiplist_words": skiplist_words,
+            "similarity_fn_name": "MaxSim",
+        });
+        fs::write(
+            path.join("config_sentence_transformers.json"),
+            serde_json::to_vec_pretty(&st_config)?,
+        )?;
+        fs::write(
+            path.join("special_tokens_map.json"),
+            serde_json::to_vec_pretty(&json!({ "mask_token": self.mask_token }))?,
+        )?;
+        Ok(())
+    }
+
     /// Processes document embeddings by filtering based on an attention mask,
     /// normalizing the results, and padding them to a uniform length.
     ///
     /// This method iterates through each embedding in the batch,
     /// removes vectors corresponding to padding tokens (where attention_mask is 0),
-    /// normalizes the remaining vectors, and then pads all sequences with zeros to match
-    /// the longest sequence in the batch.
+    /// and, when `apply_skiplist` is set, vectors whose token id is in the skiplist.
+    /// It then normalizes the remaining vectors and pads all sequences with zeros to
+    /// match the longest sequence in the batch.
     fn filter_normalize_and_pad(
         &self,
         embeddings: &Tensor,
         attention_mask: &Tensor,
+        token_ids: &Tensor,
+        apply_skiplist: bool,
     ) -> Result<Tensor, candle_core::Error> {
         let (batch_size, _, dim) = embeddings.dims3()?;
         let mut processed_embeddings: Vec<Tensor> = Vec::with_capacity(batch_size);
@@ -233,11 +566,20 @@ impl ColBERT {
         for i in 0..batch_size {
             let single_embedding = embeddings.i(i)?;
             let single_mask = attention_mask.i(i)?.to_vec1::<u32>()?;
+            let single_ids = if apply_skiplist && !self.skiplist_ids.is_empty() {
+                Some(token_ids.i(i)?.to_vec1::<u32>()?)
+            } else {
+                None
+            };
 
-            // Collect embedding vectors where the attention mask is 1.
+            // Collect embedding vectors where the attention mask is 1 and the token
+            // is not part of the skiplist.
             let mut kept_rows = Vec::new();
             for (j, &mask_val) in single_mask.iter().enumerate() {
-                if mask_val == 1 {
+                let skipped = single_ids
+                    .as_ref()
+                    .is_some_and(|ids| self.skiplist_ids.contains(&ids[j]));
+                if mask_val == 1 && !skipped {
                     kept_rows.push(single_embedding.i(j)?);
                 }
             }
@@ -283,92 +625,278 @@ impl ColBERT {
 
     /// Encodes a batch of sentences (queries or documents) into embeddings.
     ///
-    /// On CPU and non-WASM targets, this method leverages Rayon for parallel batch processing
+    /// Document embeddings are pooled with the pool factor of the model, set with
+    /// `ColbertBuilder::with_pool_factor` and 1 (no pooling) by default. On CPU and
+    /// non-WASM targets, this method leverages Rayon for parallel batch processing
     /// to accelerate encoding. On other targets (like GPU or WASM), it processes
-    /// batches sequentially.
+    /// batches sequentially. With an `EmbeddingCache`, only the sentences missing from
+    /// the cache are encoded.
     pub fn encode(&mut self, sentences: &[String], is_query: bool) -> Result<Tensor, ColbertError> {
+        self.encode_with_pool_factor(sentences, is_query, self.pool_factor)
+    }
+
+    /// Encodes sentences like `encode`, pooling documents with `pool_factor` instead of
+    /// the pool factor of the model.
+    ///
+    /// Each document is pooled separately with `hierarchical_pooling`, without its
+    /// padding rows, and the batch is padded again with zero rows. Query embeddings are
+    /// never pooled, and a factor of 1 disables pooling.
+    pub fn encode_with_pool_factor(
+        &mut self,
+        sentences: &[String],
+        is_query: bool,
+        pool_factor: usize,
+    ) -> Result<Tensor, ColbertError> {
+        match self.cache.clone() {
+            Some(cache) => self.encode_cached(&cache, sentences, is_query, pool_factor),
+            None => self.encode_uncached(sentences, is_query, pool_factor),
+        }
+    }
+
+    /// Sets the cache consulted by `encode`, or disables caching with `None`.
+    pub fn set_cache(&mut self, cache: Option<EmbeddingCache>) {
+        self.cache = cache;
+    }
+
+    /// Sets the function `encode` reports its progress to after each batch.
+    pub fn set_progress_callback(&mut self, progress: Option<ProgressCallback>) {
+        self.progress = progress;
+    }
+
+    /// Sets the token checked by `encode` before each batch, failing with
+    /// `ColbertError::Cancelled` once it is cancelled.
+    pub fn set_cancellation_token(&mut self, cancellation: Option<CancellationToken>) {
+        self.cancellation = cancellation;
+    }
+
+    /// Returns the embeddings of the sentences found in `cache` and encodes the others
+    /// once each, adding them to the cache.
+    fn encode_cached(
+        &mut self,
+        cache: &EmbeddingCache,
+        sentences: &[String],
+        is_query: bool,
+        pool_factor: usize,
+    ) -> Result<Tensor, ColbertError> {
+        if sentences.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+        let fingerprint = self.cache_fingerprint(is_query, pool_factor)?;
+        let keys: Vec<String> = sentences
+            .iter()
+            .map(|sentence| cache.key(self.revision.as_deref(), &fingerprint, sentence))
+            .collect();
+
+        let mut found = HashMap::new();
+        let mut missing = HashSet::new();
+        let mut texts = Vec::new();
+        for (sentence, key) in sentences.iter().zip(&keys) {
+            if found.contains_key(key) || missing.contains(key) {
+                continue;
+            }
+            match cache.get(key)? {
+                Some(embeddings) => {
+                    found.insert(key, embeddings.to_device(&self.device)?);
+                },
+                None => {
+                    missing.insert(key);
+                    texts.push((sentence.clone(), key));
+                },
+            }
+        }
+
+        if !texts.is_empty() {
+            let (texts, missing_keys): (Vec<String>, Vec<&String>) = texts.into_iter().unzip();
+            let encoded = self.encode_uncached(&texts, is_query, pool_factor)?;
+            for (key, embeddings) in missing_keys.into_iter().zip(unpad_embeddings(&encoded)?) {
+                cache.insert(key, &embeddings)?;
+                found.insert(key, embeddings);
+            }
+        }
+
+        let embeddings: Vec<Tensor> = keys.iter().map(|key| found[key].clone()).collect();
+        pad_embeddings(&embeddings)
+    }
+
+    /// Serializes the settings that affect the embeddings of queries or documents.
+    fn cache_fingerprint(
+        &self,
+        is_query: bool,
+        pool_factor: usize,
+    ) -> Result<Vec<u8>, ColbertError> {
+        let settings = if is_query {
+            serde_json::json!({
+                "query_prefix": self.query_prefix,
+                "query_length": self.query_length,
+                "mask_token": self.mask_token,
+                "do_query_expansion": self.do_query_expansion,
+                "attend_to_expansion_tokens": self.attend_to_expansion_tokens,
+            })
+        } else {
+            let mut skiplist_ids: Vec<u32> = self.skiplist_ids.iter().copied().collect();
+            skiplist_ids.sort_unstable();
+            serde_json::json!({
+                "document_prefix": self.document_prefix,
+                "document_length": self.document_length,
+                "skiplist_ids": skiplist_ids,
+                "pool_factor": pool_factor.max(1),
+            })
+        };
+        Ok(serde_json::to_vec(&serde_json::json!({
+            "model_id": self.model_id,
+            "config": self.config,
+            "dense_config": self.dense_config,
+            "dense2_config": self.dense2_config,
+            "is_query": is_query,
+            "settings": settings,
+        }))?)
+    }
+
+    /// Encodes sentences like `encode_with_pool_factor`, without consulting the cache.
+    fn encode_uncached(
+        &mut self,
+        sentences: &[String],
+        is_query: bool,
+        pool_factor: usize,
+    ) -> Result<Tensor, ColbertError> {
+        let embeddings = self.encode_batches(sentences, is_query)?;
+        if is_query || pool_factor <= 1 {
+            return Ok(embeddings);
+        }
+        let documents = pool_documents(&unpad_embeddings(&embeddings)?, pool_factor)?;
+        Ok(pad_embeddings(&documents)?.to_device(&self.device)?)
+    }
+
+    /// Encodes sentences in batches of `batch_size`, without pooling.
+    fn encode_batches(
+        &mut self,
+        sentences: &[String],
+        is_query: bool,
+    ) -> Result<Tensor, ColbertError> {
         if sentences.is_empty() {
-            return Err(ColbertError::Operation(
-                "Input sentences cannot be empty.".into(),
-            ));
+            return Err(ColbertError::EmptyInput);
         }
 
+        let (progress, cancellation) = (self.progress.clone(), self.cancellation.clone());
+        let tracker = ProgressTracker::new(
+            progress.as_ref(),
+            cancellation.as_ref(),
+            sentences.len().div_ceil(self.batch_size),
+            sentences.len(),
+        );
+        tracker.check()?;
+
         // Use Rayon for parallel processing on CPU, but not on WASM.
         #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
         if self.device.is_cpu() {
             let mut tokenized_batches = Vec::new();
             for batch_sentences in sentences.chunks(self.batch_size) {
-                tokenized_batches.push(self.tokenize(batch_sentences, is_query)?);
+                let (token_ids, attention_mask, token_type_ids) =
+                    self.tokenize(batch_sentences, is_query)?;
+                tokenized_batches.push((
+                    token_ids,
+                    attention_mask,
+                    token_type_ids,
+                    batch_sentences.len(),
+                ));
             }
 
             let all_embeddings = tokenized_batches
                 .into_par_iter()
                 .map(
-                    |(token_ids, attention_mask, token_type_ids)| -> Result<Tensor, ColbertError> {
-                        let token_embeddings =
-                            self.model
-                                .forward(&token_ids, &attention_mask, &token_type_ids)?;
-
-                        // Apply 1_Dense
-                        let mut projected_embeddings = self.linear.forward(&token_embeddings)?;
-
-                        // Apply 2_Dense if present
-                        if let Some(ref linear2) = self.linear2 {
-                            projected_embeddings = linear2.forward(&projected_embeddings)?;
-                        }
-
-                        if !self.do_query_expansion || !is_query {
-                            // Apply filtering, normalization, and padding.
-                            self.filter_normalize_and_pad(&projected_embeddings, &attention_mask)
-                                .map_err(ColbertError::from)
-                        } else {
-                            // Original behavior: just normalize.
-                            normalize_l2(&projected_embeddings)
-                        }
+                    |(token_ids, attention_mask, token_type_ids, num_sentences)| {
+                        tracker.check()?;
+                        let embeddings = self.forward_batch(
+                            &token_ids,
+                            &attention_mask,
+                            &token_type_ids,
+                            is_query,
+                        )?;
+                        tracker.record(num_sentences, count_tokens(&attention_mask)?);
+                        Ok(embeddings)
                     },
                 )
-                .collect::<Result<Vec<_>, _>>()?;
+                .collect::<Result<Vec<_>, ColbertError>>()?;
+            // Batches already running when the call was cancelled may all have finished.
+            tracker.check()?;
 
-            if all_embeddings.len() == 1 {
-                return Ok(all_embeddings.into_iter().next().unwrap());
-            }
-            return Tensor::cat(&all_embeddings, 0).map_err(ColbertError::from);
+            return Self::concatenate_batches(all_embeddings);
         }
 
         // Fallback to sequential processing for GPU, WASM, or other devices.
         let mut all_embeddings = Vec::new();
         for batch_sentences in sentences.chunks(self.batch_size) {
+            tracker.check()?;
             let (token_ids, attention_mask, token_type_ids) =
                 self.tokenize(batch_sentences, is_query)?;
+            all_embeddings.push(self.forward_batch(
+                &token_ids,
+                &attention_mask,
+                &token_type_ids,
+                is_query,
+            )?);
+            tracker.record(batch_sentences.len(), count_tokens(&attention_mask)?);
+        }
+        tracker.check()?;
 
-            let token_embeddings =
-                self.model
-                    .forward(&token_ids, &attention_mask, &token_type_ids)?;
+        Self::concatenate_batches(all_embeddings)
+    }
 
-            // Apply 1_Dense
-            let mut projected_embeddings = self.linear.forward(&token_embeddings)?;
+    /// Runs the backbone and the Dense layers on a tokenized batch, then filters,
+    /// normalizes and pads the embeddings of documents and of queries without query
+    /// expansion, without pooling.
+    pub(crate) fn forward_batch(
+        &self,
+        token_ids: &Tensor,
+        attention_mask: &Tensor,
+        token_type_ids: &Tensor,
+        is_query: bool,
+    ) -> Result<Tensor, ColbertError> {
+        let token_embeddings = self
+            .model
+            .forward(token_ids, attention_mask, token_type_ids)?;
 
-            // Apply 2_Dense if present
-            if let Some(ref linear2) = self.linear2 {
-                projected_embeddings = linear2.forward(&projected_embeddings)?;
-            }
+        // Apply 1_Dense
+        let mut projected_embeddings = self.linear.forward(&token_embeddings)?;
 
-            let final_embeddings = if !self.do_query_expansion || !is_query {
-                // Apply filtering, normalization, and padding.
-                self.filter_normalize_and_pad(&projected_embeddings, &attention_mask)?
-            } else {
-                // Original behavior: just normalize.
-                normalize_l2(&projected_embeddings)?
-            };
+        // Apply 2_Dense if present
+        if let Some(ref linear2) = self.linear2 {
+            projected_embeddings = linear2.forward(&projected_embeddings)?;
+        }
 
-            all_embeddings.push(final_embeddings);
+        if !self.do_query_expansion || !is_query {
+            // Apply filtering, normalization, and padding.
+            Ok(self.filter_normalize_and_pad(
+                &projected_embeddings,
+                attention_mask,
+                token_ids,
+                !is_query,
+            )?)
+        } else {
+            // Original behavior: just normalize.
+            normalize_l2(&projected_embeddings)
         }
+    }
 
-        if all_embeddings.len() == 1 {
-            return Ok(all_embeddings.remove(0));
+    /// Concatenates the embeddings of several batches, padding documents with zero rows
+    /// to the longest sequence across all batches.
+    fn concatenate_batches(mut batches: Vec<Tensor>) -> Result<Tensor, ColbertError> {
+        if batches.len() == 1 {
+            return Ok(batches.remove(0));
         }
 
-        Tensor::cat(&all_embeddings, 0).map_err(ColbertError::from)
+        let max_len = batches
+            .iter()
+            .map(|batch| batch.dim(1))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        let padded = batches
+            .iter()
+            .map(|batch| batch.pad_with_zeros(1, 0, max_len - batch.dim(1)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        Tensor::cat(&padded, 0).map_err(ColbertError::from)
     }
 
     /// Calculates the similarity scores between query and document embeddings.
@@ -401,37 +929,82 @@ impl ColBERT {
             .map_err(ColbertError::from)
     }
 
+    /// Splits the output of `encode` into one `[n_tokens, embedding_dim]` tensor per
+    /// sentence, without the rows padding the batch.
+    ///
+    /// Queries keep their expansion tokens when query expansion is enabled; otherwise
+    /// they are padded with zero rows, which are dropped like those of documents.
+    pub fn split_embeddings(
+        &self,
+        embeddings: &Tensor,
+        is_query: bool,
+    ) -> Result<Vec<Tensor>, ColbertError> {
+        if is_query && self.do_query_expansion {
+            (0..embeddings.dim(0)?)
+                .map(|i| embeddings.get(i).map_err(ColbertError::from))
+                .collect()
+        } else {
+            unpad_embeddings(embeddings)
+        }
+    }
+
+    /// Returns the tokens of each text, aligned with the embeddings `encode` produces
+    /// without pooling.
+    ///
+    /// Padding tokens are dropped, as are the skiplist tokens of documents, so that the
+    /// i-th token of a text matches the i-th row of its embeddings. Queries keep their
+    /// mask tokens when query expansion is enabled.
+    pub fn tokens(
+        &mut self,
+        texts: &[String],
+        is_query: bool,
+    ) -> Result<Vec<Vec<String>>, ColbertError> {
+        if texts.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+        let (token_ids, attention_mask, _) = self.tokenize(texts, is_query)?;
+        let token_ids = token_ids.to_vec2::<u32>()?;
+        let attention_mask = attention_mask.to_vec2::<u32>()?;
+        let keep_all = is_query && self.do_query_expansion;
+
+        Ok(token_ids
+            .iter()
+            .zip(&attention_mask)
+            .map(|(ids, mask)| {
+                ids.iter()
+                    .zip(mask)
+                    .filter(|&(id, &mask_val)| {
+                        keep_all || (mask_val == 1 && (is_query || !self.skiplist_ids.contains(id)))
+                    })
+                    .map(|(&id, _)| self.tokenizer.id_to_token(id).unwrap_or_default())
+                    .collect()
+            })
+            .collect())
+    }
+
     /// Tokenizes a batch of texts, applying specific logic for queries and documents.
     pub(crate) fn tokenize(
         &mut self,
         texts: &[String],
         is_query: bool,
     ) -> Result<(Tensor, Tensor, Tensor), ColbertError> {
-        let device = &self.device;
+        let tokenization = self.tokenization(is_query);
+        tokenization.configure(&mut self.tokenizer);
+        tokenization
+            .tokenize(&self.tokenizer, texts)?
+            .into_tensors(&self.device)
+    }
+
+    /// Returns the tokenization settings of queries or documents.
+    pub(crate) fn tokenization(&self, is_query: bool) -> Tokenization {
         let (prefix, max_length) = if is_query {
-            (self.query_prefix.as_str(), self.query_length)
+            (self.query_prefix.clone(), self.query_length)
         } else {
-            (self.document_prefix.as_str(), self.document_length)
+            (self.document_prefix.clone(), self.document_length)
         };
 
-        // Prepend the appropriate prefix to each text to create the full input strings.
-        // This simplifies the logic by allowing the tokenizer to handle the entire sequence at once,
-        // avoiding manual and complex tensor concatenation.
-        let texts_with_prefix: Vec<_> = texts
-            .iter()
-            .map(|text| format!("{}{}", prefix, text))
-            .collect();
-
-        // Configure tokenizer truncation to the model's maximum sequence length.
-        let _ = self
-            .tokenizer
-            .with_truncation(Some(tokenizers::TruncationParams {
-                max_length,
-                ..Default::default()
-            }));
-
         // Configure the padding strategy based on the input type.
-        let padding_params = if is_query {
+        let padding = if is_query {
             // For ColBERT queries, pad to a fixed length with the [MASK] token.
             tokenizers::PaddingParams {
                 strategy: tokenizers::PaddingStrategy::Fixed(max_length),
@@ -446,38 +1019,103 @@ impl ColBERT {
                 ..Default::default()
             }
         };
-        self.tokenizer.with_padding(Some(padding_params));
+
+        Tokenization {
+            prefix,
+            // Truncate to the model's maximum sequence length.
+            truncation: tokenizers::TruncationParams {
+                max_length,
+                ..Default::default()
+            },
+            padding,
+            // For queries, optionally attend to the padded tokens.
+            attend_to_padding: is_query && self.attend_to_expansion_tokens,
+        }
+    }
+}
+
+/// Returns the number of tokens attended to in a batch.
+pub(crate) fn count_tokens(attention_mask: &Tensor) -> Result<usize, ColbertError> {
+    Ok(attention_mask.sum_all()?.to_scalar::<u32>()? as usize)
+}
+
+/// The tokenization of queries or documents, detached from the model so that it can
+/// run on another thread with a clone of the tokenizer.
+#[derive(Clone)]
+pub(crate) struct Tokenization {
+    prefix: String,
+    truncation: tokenizers::TruncationParams,
+    padding: tokenizers::PaddingParams,
+    attend_to_padding: bool,
+}
+
+/// The flat token ids, attention mask and token type ids of a tokenized batch.
+pub(crate) struct TokenizedBatch {
+    token_ids: Vec<u32>,
+    attention_mask: Vec<u32>,
+    token_type_ids: Vec<u32>,
+    shape: (usize, usize),
+}
+
+impl Tokenization {
+    /// Sets the truncation and padding of `tokenizer`.
+    pub(crate) fn configure(&self, tokenizer: &mut Tokenizer) {
+        let _ = tokenizer.with_truncation(Some(self.truncation.clone()));
+        tokenizer.with_padding(Some(self.padding.clone()));
+    }
+
+    /// Tokenizes a batch of texts with a tokenizer set up by `configure`.
+    pub(crate) fn tokenize(
+        &self,
+        tokenizer: &Tokenizer,
+        texts: &[String],
+    ) -> Result<TokenizedBatch, ColbertError> {
+        // Prepend the appropriate prefix to each text to create the full input strings.
+        // This simplifies the logic by allowing the tokenizer to handle the entire sequence at once,
+        // avoiding manual and complex tensor concatenation.
+        let texts_with_prefix: Vec<_> = texts
+            .iter()
+            .map(|text| format!("{}{}", self.prefix, text))
+            .collect();
 
         // Tokenize the batch of prepared texts.
-        let encodings = self.tokenizer.encode_batch(texts_with_prefix, true)?;
+        let encodings = tokenizer.encode_batch(texts_with_prefix, true)?;
 
         let batch_size = encodings.len();
         if batch_size == 0 {
-            return Err(ColbertError::Operation(
-                "Input sentences cannot be empty.".into(),
-            ));
+            return Err(ColbertError::EmptyInput);
         }
 
         // Collect tokenization outputs into flat vectors.
         let seq_len = encodings.first().map_or(0, |e| e.get_ids().len());
-        let (mut ids_vec, mut mask_vec, mut type_ids_vec) =
-            (Vec::<u32>::new(), Vec::<u32>::new(), Vec::<u32>::new());
+        let mut batch = TokenizedBatch {
+            token_ids: Vec::with_capacity(batch_size * seq_len),
+            attention_mask: Vec::with_capacity(batch_size * seq_len),
+            token_type_ids: Vec::with_capacity(batch_size * seq_len),
+            shape: (batch_size, seq_len),
+        };
         for enc in &encodings {
-            ids_vec.extend(enc.get_ids());
-            mask_vec.extend(enc.get_attention_mask());
-            type_ids_vec.extend(enc.get_type_ids());
+            batch.token_ids.extend(enc.get_ids());
+            batch.attention_mask.extend(enc.get_attention_mask());
+            batch.token_type_ids.extend(enc.get_type_ids());
         }
-
-        // Create tensors from the vectors.
-        let token_ids = Tensor::from_vec(ids_vec, (batch_size, seq_len), device)?;
-        let mut attention_mask = Tensor::from_vec(mask_vec, (batch_size, seq_len), device)?;
-        let token_type_ids = Tensor::from_vec(type_ids_vec, (batch_size, seq_len), device)?;
-
-        // For queries, optionally set the attention mask to all ones to attend to padded tokens.
-        if is_query && self.attend_to_expansion_tokens {
-            attention_mask = attention_mask.ones_like()?;
+        if self.attend_to_padding {
+            batch.attention_mask.fill(1);
         }
+        Ok(batch)
+    }
+}
 
-        Ok((token_ids, attention_mask, token_type_ids))
+impl TokenizedBatch {
+    /// Returns the token ids, attention mask and token type ids as tensors.
+    pub(crate) fn into_tensors(
+        self,
+        device: &Device,
+    ) -> Result<(Tensor, Tensor, Tensor), ColbertError> {
+        Ok((
+            Tensor::from_vec(self.token_ids, self.shape, device)?,
+            Tensor::from_vec(self.attention_mask, self.shape, device)?,
+            Tensor::from_vec(self.token_type_ids, self.shape, device)?,
+        ))
     }
 }
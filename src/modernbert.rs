@@ -41,7 +41,7 @@ pub enum ClassifierPooling {
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ClassifierConfig {
     pub id2label: HashMap<String, String>,
-    pub label2id: HashMap<String, String>,
+    pub label2id: HashMap<String, usize>,
     pub classifier_pooling: ClassifierPooling,
 }
 
@@ -72,8 +72,15 @@ impl RotaryEmbedding {
     }
 
     fn apply_rotary_emb_qkv(&self, q: &Tensor, k: &Tensor) -> Result<(Tensor, Tensor)> {
-        let q_embed = candle_nn::rotary_emb::rope(&q.contiguous()?, &self.cos, &self.sin)?;
-        let k_embed = candle_nn::rotary_emb::rope(&k.contiguous()?, &self.cos, &self.sin)?;
+        // The fused kernel has no backward pass, so gradients would not reach the
+        // query and key projections when fine-tuning.
+        let rope = if q.track_op() {
+            candle_nn::rotary_emb::rope_slow
+        } else {
+            candle_nn::rotary_emb::rope
+        };
+        let q_embed = rope(&q.contiguous()?, &self.cos, &self.sin)?;
+        let k_embed = rope(&k.contiguous()?, &self.cos, &self.sin)?;
         Ok((q_embed, k_embed))
     }
 }
@@ -256,9 +263,9 @@ pub struct ModernBertDecoder {
 }
 
 impl ModernBertDecoder {
-    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+    fn load(vb: VarBuilder, model_vb: VarBuilder, config: &Config) -> Result<Self> {
         // The decoder weights are tied with the embeddings layer weights
-        let decoder_weights = vb.get(
+        let decoder_weights = model_vb.get(
             (config.vocab_size, config.hidden_size),
             "embeddings.tok_embeddings.weight",
         )?;
@@ -396,6 +403,15 @@ impl ModernBert {
     }
 }
 
+// Transformers checkpoints nest the backbone under `model.`, PyLate ones do not
+fn backbone_var_builder<'a>(vb: &VarBuilder<'a>) -> VarBuilder<'a> {
+    if vb.contains_tensor("model.embeddings.tok_embeddings.weight") {
+        vb.pp("model")
+    } else {
+        vb.clone()
+    }
+}
+
 // ModernBERT for the fill-mask task
 #[derive(Clone)]
 pub struct ModernBertForMaskedLM {
@@ -406,8 +422,9 @@ pub struct ModernBertForMaskedLM {
 
 impl ModernBertForMaskedLM {
     pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let model = ModernBert::load(vb.clone(), config)?;
-        let decoder = ModernBertDecoder::load(vb.clone(), config)?;
+        let model_vb = backbone_var_builder(&vb);
+        let model = ModernBert::load(model_vb.clone(), config)?;
+        let decoder = ModernBertDecoder::load(vb.clone(), model_vb, config)?;
         let head = ModernBertHead::load(vb.pp("head"), config)?;
         Ok(Self {
             model,
@@ -464,7 +481,7 @@ pub struct ModernBertForSequenceClassification {
 
 impl ModernBertForSequenceClassification {
     pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let model = ModernBert::load(vb.clone(), config)?;
+        let model = ModernBert::load(backbone_var_builder(&vb), config)?;
         let classifier = ModernBertClassifier::load(vb.clone(), config)?;
         let head = ModernBertHead::load(vb.pp("head"), config)?;
         Ok(Self {
@@ -480,9 +497,14 @@ impl ModernBertForSequenceClassification {
     }
 
     pub fn forward(&self, xs: &Tensor, mask: &Tensor) -> Result<Tensor> {
+        softmax(&self.logits(xs, mask)?, D::Minus1)
+    }
+
+    // Unnormalized classifier outputs, used for single-label heads such as rerankers
+    pub fn logits(&self, xs: &Tensor, mask: &Tensor) -> Result<Tensor> {
         let output = self.model.forward(xs, mask)?;
         let last_hidden_state = match self.classifier_pooling {
-            ClassifierPooling::CLS => output.i((.., .., 0))?,
+            ClassifierPooling::CLS => output.i((.., 0))?,
             ClassifierPooling::MEAN => {
                 let unsqueezed_mask = &mask.unsqueeze(D::Minus1)?.to_dtype(DType::F32)?;
                 let sum_output = output.broadcast_mul(unsqueezed_mask)?.sum(1)?;
@@ -492,7 +514,7 @@ impl ModernBertForSequenceClassification {
         let xs = self
             .head
             .forward(&last_hidden_state)?
-            .apply(&self.classifier)?;
+            .apply(&self.classifier.classifier)?;
         Ok(xs)
     }
 }
@@ -1,4 +1,4 @@
-use anyhow::anyhow;
+use crate::error::ColbertError;
 use candle_core::{Device, Tensor};
 use candle_transformers::models::deepseek2::NonZeroOp;
 use kodama::{linkage, Method};
@@ -44,16 +44,17 @@ impl Dsu {
 pub fn hierarchical_pooling(
     documents_embeddings: &Tensor,
     pool_factor: usize,
-) -> anyhow::Result<Tensor> {
+) -> Result<Tensor, ColbertError> {
     if pool_factor <= 1 {
         return Ok(documents_embeddings.clone());
     }
 
     if documents_embeddings.dims().len() != 3 {
-        return Err(anyhow!(
-            "Input tensor must have 3 dimensions [batch_size, n_tokens, embedding_dim], but got {} dimensions.",
-            documents_embeddings.dims().len()
-        ));
+        return Err(ColbertError::DimensionMismatch {
+            context: "documents embeddings rank [batch_size, n_tokens, embedding_dim]".into(),
+            expected: 3,
+            found: documents_embeddings.dims().len(),
+        });
     }
 
     let device = documents_embeddings.device();
@@ -153,3 +154,15 @@ pub fn hierarchical_pooling(
 
     Ok(Tensor::stack(&all_pooled_embeddings, 0)?)
 }
+
+/// Pools each `[document_length, embedding_dim]` document separately with
+/// `hierarchical_pooling`.
+pub(crate) fn pool_documents(
+    documents: &[Tensor],
+    pool_factor: usize,
+) -> Result<Vec<Tensor>, ColbertError> {
+    documents
+        .iter()
+        .map(|document| Ok(hierarchical_pooling(&document.unsqueeze(0)?, pool_factor)?.squeeze(0)?))
+        .collect()
+}
@@ -0,0 +1,107 @@
+use crate::error::ColbertError;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError,
+};
+
+/// The progress of an `encode` call, reported after each batch.
+///
+/// Only the sentences that go through the model are counted, so sentences served by an
+/// `EmbeddingCache` are not part of the totals.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EncodeProgress {
+    /// The number of batches encoded so far.
+    pub batches_done: usize,
+    /// The number of batches of the call.
+    pub total_batches: usize,
+    /// The number of sentences encoded so far.
+    pub sentences_done: usize,
+    /// The number of sentences of the call.
+    pub total_sentences: usize,
+    /// The number of tokens the model attended to, including query expansion tokens
+    /// when it attends to them.
+    pub tokens_processed: usize,
+}
+
+/// A function called with the progress of `encode` after each batch.
+///
+/// On CPU, batches are encoded in parallel and the callback may run on any of the
+/// threads of the pool, one call at a time.
+pub type ProgressCallback = Arc<dyn Fn(&EncodeProgress) + Send + Sync>;
+
+/// A flag checked by `encode` before each batch, which then fails with
+/// `ColbertError::Cancelled`.
+///
+/// Clones share the same flag, so a token can be cancelled from another thread or from
+/// a progress callback. A cancelled token stays cancelled: use a new token per job.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the encoding calls using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks the batches of a single `encode` call.
+pub(crate) struct ProgressTracker<'a> {
+    callback: Option<&'a ProgressCallback>,
+    cancellation: Option<&'a CancellationToken>,
+    progress: Mutex<EncodeProgress>,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(
+        callback: Option<&'a ProgressCallback>,
+        cancellation: Option<&'a CancellationToken>,
+        total_batches: usize,
+        total_sentences: usize,
+    ) -> Self {
+        Self {
+            callback,
+            cancellation,
+            progress: Mutex::new(EncodeProgress {
+                total_batches,
+                total_sentences,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Fails if the call was cancelled.
+    pub(crate) fn check(&self) -> Result<(), ColbertError> {
+        match self.cancellation {
+            Some(token) if token.is_cancelled() => Err(ColbertError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records an encoded batch and reports the progress, unless the call was cancelled.
+    pub(crate) fn record(&self, sentences: usize, tokens: usize) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        // The lock is held during the callback so that reports arrive in order, and so
+        // that batches finishing after a callback cancels the call are not reported.
+        let mut progress = self.progress.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.check().is_err() {
+            return;
+        }
+        progress.batches_done += 1;
+        progress.sentences_done += sentences;
+        progress.tokens_processed += tokens;
+        callback(&progress);
+    }
+}
@@ -1,13 +1,35 @@
-use crate::{error::ColbertError, model::ColBERT};
+use crate::{
+    error::ColbertError,
+    model::ColBERT,
+    progress::{CancellationToken, EncodeProgress, ProgressCallback},
+    utils::unpad_embeddings,
+};
 use candle_core::{Device, Tensor};
 use ndarray::Array;
 use numpy::{ndarray::IxDyn, PyArray, PyReadonlyArrayDyn, PyUntypedArrayMethods};
 use pyo3::prelude::*;
-use pyo3::{exceptions::PyValueError, types::PyModule, Bound};
-use std::convert::TryFrom;
+use pyo3::{
+    exceptions::PyValueError,
+    types::{PyDict, PyList, PyModule},
+    Bound,
+};
+use std::{
+    convert::TryFrom,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
 
 use crate::pooling::hierarchical_pooling;
 
+/// Embeddings passed from Python, either as one padded array or one array per sentence.
+#[derive(FromPyObject)]
+pub enum PyEmbeddings<'py> {
+    /// A `[batch_size, length, embedding_dim]` array padded with zero rows.
+    Padded(PyReadonlyArrayDyn<'py, f32>),
+    /// A list of `[length, embedding_dim]` arrays.
+    Ragged(Vec<PyReadonlyArrayDyn<'py, f32>>),
+}
+
 // Custom Python exception for Colbert errors
 pyo3::create_exception!(pylate_rs, ColbertException, pyo3::exceptions::PyException);
 
@@ -17,13 +39,118 @@ impl From<ColbertError> for PyErr {
     }
 }
 
+/// A token that stops an `encode` call before its next batch once cancelled.
+///
+/// It can be cancelled from another thread or from the progress callback of the call.
+#[pyclass(name = "CancellationToken")]
+#[derive(Default)]
+pub struct PyCancellationToken {
+    token: CancellationToken,
+}
+
+#[pymethods]
+impl PyCancellationToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the `encode` calls using this token.
+    fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether the token was cancelled.
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// Converts the progress of `encode` into a dict for Python callbacks.
+fn progress_dict<'py>(py: Python<'py>, progress: &EncodeProgress) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("batches_done", progress.batches_done)?;
+    dict.set_item("total_batches", progress.total_batches)?;
+    dict.set_item("sentences_done", progress.sentences_done)?;
+    dict.set_item("total_sentences", progress.total_sentences)?;
+    dict.set_item("tokens_processed", progress.tokens_processed)?;
+    Ok(dict)
+}
+
 /// A Python wrapper for the Rust ColBERT model.
 ///
 /// This class provides a Python interface to the underlying Rust implementation,
 /// allowing for model loading, encoding, and similarity calculations.
+///
+/// The model sits behind a mutex so that methods can release the GIL: calls from
+/// several Python threads run one at a time without blocking other Python code.
 #[pyclass(name = "PyColBERT")]
 pub struct PyColBERT {
-    model: ColBERT,
+    model: Mutex<ColBERT>,
+}
+
+impl PyColBERT {
+    /// Locks the model, recovering it if a previous call panicked.
+    ///
+    /// The lock must only be taken with the GIL released: a call holding it may wait
+    /// for the GIL to run a progress callback.
+    fn model(&self) -> MutexGuard<'_, ColBERT> {
+        self.model.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The locked model with the settings of a single call, restored when dropped so that
+/// they do not outlive the call even if it panics.
+struct CallSettings<'a> {
+    model: MutexGuard<'a, ColBERT>,
+    batch_size: usize,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl<'a> CallSettings<'a> {
+    fn new(
+        mut model: MutexGuard<'a, ColBERT>,
+        batch_size: Option<usize>,
+        progress: Option<ProgressCallback>,
+        cancellation: Option<CancellationToken>,
+    ) -> Self {
+        let default_batch_size = model.batch_size;
+        if let Some(batch_size) = batch_size {
+            model.batch_size = batch_size.max(1);
+        }
+        let progress = std::mem::replace(&mut model.progress, progress);
+        let cancellation = std::mem::replace(&mut model.cancellation, cancellation);
+        Self {
+            model,
+            batch_size: default_batch_size,
+            progress,
+            cancellation,
+        }
+    }
+}
+
+impl Deref for CallSettings<'_> {
+    type Target = ColBERT;
+
+    fn deref(&self) -> &ColBERT {
+        &self.model
+    }
+}
+
+impl DerefMut for CallSettings<'_> {
+    fn deref_mut(&mut self) -> &mut ColBERT {
+        &mut self.model
+    }
+}
+
+impl Drop for CallSettings<'_> {
+    fn drop(&mut self) {
+        self.model.batch_size = self.batch_size;
+        self.model.progress = self.progress.take();
+        self.model.cancellation = self.cancellation.take();
+    }
 }
 
 #[pymethods]
@@ -43,11 +170,14 @@ impl PyColBERT {
     /// * `query_prefix` - The prefix to add to queries.
     /// * `document_prefix` - The prefix to add to documents.
     /// * `mask_token` - The mask token to use for padding queries.
+    /// * `skiplist_words` - The words whose embeddings are removed from documents.
+    /// * `pool_factor` - The factor by which `encode` pools document embeddings by default.
     ///
     /// # Returns
     ///
     /// A new instance of `PyColBERT`.
     #[staticmethod]
+    #[allow(clippy::too_many_arguments)]
     #[pyo3(signature = (
         repo_id,
         device=None,
@@ -58,7 +188,9 @@ impl PyColBERT {
         attend_to_expansion_tokens=None,
         query_prefix=None,
         document_prefix=None,
-        mask_token=None
+        mask_token=None,
+        skiplist_words=None,
+        pool_factor=None
     ))]
     pub fn from_pretrained(
         repo_id: &str,
@@ -71,6 +203,8 @@ impl PyColBERT {
         query_prefix: Option<String>,
         document_prefix: Option<String>,
         mask_token: Option<String>,
+        skiplist_words: Option<Vec<String>>,
+        pool_factor: Option<usize>,
     ) -> PyResult<Self> {
         let device = match device {
             Some(device_str) if device_str.starts_with("cuda") => {
@@ -116,9 +250,17 @@ impl PyColBERT {
         if let Some(mt) = mask_token {
             builder = builder.with_mask_token(mt);
         }
+        if let Some(words) = skiplist_words {
+            builder = builder.with_skiplist_words(words);
+        }
+        if let Some(factor) = pool_factor {
+            builder = builder.with_pool_factor(factor);
+        }
 
         let model = ColBERT::try_from(builder)?;
-        Ok(Self { model })
+        Ok(Self {
+            model: Mutex::new(model),
+        })
     }
 
     /// Encodes a list of sentences (queries or documents) into embeddings.
@@ -127,60 +269,256 @@ impl PyColBERT {
     ///
     /// * `sentences` - A list of strings to encode.
     /// * `is_query` - A boolean flag indicating whether the sentences are queries (`true`) or documents (`false`).
+    /// * `pool_factor` - The factor by which document token embeddings are pooled, instead
+    ///   of the pool factor of the model.
+    /// * `batch_size` - The batch size for this call, instead of the model's batch size.
+    /// * `ragged` - Whether to return one array per sentence without its padding rows.
+    /// * `progress` - A function called with a dict of `batches_done`, `total_batches`,
+    ///   `sentences_done`, `total_sentences` and `tokens_processed` after each batch. An
+    ///   exception raised by the function stops encoding and is raised by `encode`.
+    /// * `cancel` - A `CancellationToken` stopping encoding before its next batch.
+    ///
+    /// The GIL is released while encoding, and taken back to call `progress`.
     ///
     /// # Returns
     ///
-    /// A NumPy array containing the embeddings.
+    /// A NumPy array of shape `[batch_size, max_length, embedding_dim]` padded with zero
+    /// rows, or with `ragged` a list of `[length, embedding_dim]` NumPy arrays.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (sentences, is_query, pool_factor=None, batch_size=None, ragged=false, progress=None, cancel=None))]
     pub fn encode<'py>(
-        &mut self,
+        &self,
         py: Python<'py>,
         sentences: Vec<String>,
         is_query: bool,
-        pool_factor: usize,
-    ) -> PyResult<Bound<'py, PyArray<f32, IxDyn>>> {
-        let embeddings = self.model.encode(&sentences, is_query)?;
+        pool_factor: Option<usize>,
+        batch_size: Option<usize>,
+        ragged: bool,
+        progress: Option<PyObject>,
+        cancel: Option<PyRef<'py, PyCancellationToken>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        // The callback cancels the call when it raises, so it needs a token of its own
+        // when none is given.
+        let cancellation = match (cancel, &progress) {
+            (Some(cancel), _) => Some(cancel.token.clone()),
+            (None, Some(_)) => Some(CancellationToken::new()),
+            (None, None) => None,
+        };
+        let callback_error = Arc::new(Mutex::new(None::<PyErr>));
+        let callback = progress.map(|progress| {
+            let cancellation = cancellation.clone();
+            let callback_error = callback_error.clone();
+            Arc::new(move |state: &EncodeProgress| {
+                Python::with_gil(|py| {
+                    let result = progress_dict(py, state)
+                        .and_then(|dict| progress.call1(py, (dict,)).map(drop));
+                    if let Err(err) = result {
+                        *callback_error
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner) = Some(err);
+                        if let Some(cancellation) = &cancellation {
+                            cancellation.cancel();
+                        }
+                    }
+                });
+            }) as ProgressCallback
+        });
 
-        let embeddings = if pool_factor > 1 {
-            hierarchical_pooling(&embeddings, pool_factor)
-                .map_err(|e| PyValueError::new_err(e.to_string()))?
+        let arrays = py.allow_threads(|| -> Result<_, ColbertError> {
+            let mut model = CallSettings::new(self.model(), batch_size, callback, cancellation);
+            let pool_factor = pool_factor.unwrap_or(model.pool_factor);
+            let embeddings = model.encode_with_pool_factor(&sentences, is_query, pool_factor);
+            drop(model);
+
+            let embeddings = embeddings?;
+            if ragged {
+                unpad_embeddings(&embeddings)?
+                    .iter()
+                    .map(tensor_data)
+                    .collect::<Result<Vec<_>, _>>()
+            } else {
+                Ok(vec![tensor_data(&embeddings)?])
+            }
+        });
+        if let Some(err) = callback_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            return Err(err);
+        }
+        let arrays = arrays?;
+
+        let mut arrays = arrays
+            .into_iter()
+            .map(|(shape, data)| array_from_data(py, shape, data))
+            .collect::<PyResult<Vec<_>>>()?;
+        if ragged {
+            Ok(PyList::new(py, arrays)?.into_any())
         } else {
-            embeddings
-        };
+            Ok(arrays.remove(0).into_any())
+        }
+    }
 
-        let shape = embeddings.dims();
-        let data = embeddings
-            .flatten_all()
-            .map_err(|e| PyValueError::new_err(e.to_string()))?
-            .to_vec1::<f32>()
-            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    /// Tokenizes sentences with the prefixes, lengths and padding used by `encode`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sentences` - A list of strings to tokenize.
+    /// * `is_query` - A boolean flag indicating whether the sentences are queries (`true`) or documents (`false`).
+    ///
+    /// # Returns
+    ///
+    /// The tokens of each sentence, aligned with the rows of its embeddings: padding is
+    /// dropped, as are the skiplist tokens of documents.
+    pub fn tokenize(
+        &self,
+        py: Python<'_>,
+        sentences: Vec<String>,
+        is_query: bool,
+    ) -> PyResult<Vec<Vec<String>>> {
+        Ok(py.allow_threads(|| self.model().tokens(&sentences, is_query))?)
+    }
 
-        let ndarray = Array::from_shape_vec(shape, data)
-            .map_err(|e| PyValueError::new_err(format!("Error creating ndarray: {}", e)))?;
+    /// Computes the un-reduced similarity matrix between queries and documents.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - A list of queries.
+    /// * `documents` - A list of documents.
+    ///
+    /// The GIL is released while encoding and scoring.
+    ///
+    /// # Returns
+    ///
+    /// A dictionary with the `similarity_matrix` NumPy array of shape
+    /// `[num_queries, num_documents, query_length, document_length]`, and the
+    /// `query_tokens` and `document_tokens` matching its last two dimensions.
+    pub fn raw_similarity<'py>(
+        &self,
+        py: Python<'py>,
+        queries: Vec<String>,
+        documents: Vec<String>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let (query_tokens, document_tokens, (shape, data)) =
+            py.allow_threads(|| -> Result<_, ColbertError> {
+                let mut model = self.model();
+                let query_tokens = model.tokens(&queries, true)?;
+                let document_tokens = model.tokens(&documents, false)?;
+                // Documents are not pooled, to stay aligned with their tokens.
+                let queries_embeddings = model.encode(&queries, true)?;
+                let documents_embeddings = model.encode_with_pool_factor(&documents, false, 1)?;
+                let scores = model.raw_similarity(&queries_embeddings, &documents_embeddings)?;
+                Ok((query_tokens, document_tokens, tensor_data(&scores)?))
+            })?;
 
-        Ok(PyArray::from_owned_array(py, ndarray))
+        let dict = PyDict::new(py);
+        dict.set_item("similarity_matrix", array_from_data(py, shape, data)?)?;
+        dict.set_item("query_tokens", query_tokens)?;
+        dict.set_item("document_tokens", document_tokens)?;
+        Ok(dict)
     }
 
     /// Calculates similarity scores between query and document embeddings.
     ///
     /// # Arguments
     ///
-    /// * `queries_embeddings` - A NumPy array of query embeddings.
-    /// * `documents_embeddings` - A NumPy array of document embeddings.
+    /// * `queries_embeddings` - A NumPy array of query embeddings, or a list of one array per query.
+    /// * `documents_embeddings` - A NumPy array of document embeddings, or a list of one array per document.
+    ///
+    /// Lists of arrays, as returned by `encode` with `ragged`, are padded with zero rows.
+    /// The GIL is released while scoring.
     ///
     /// # Returns
     ///
     /// A nested list of f32 similarity scores.
     pub fn similarity(
         &self,
-        queries_embeddings: PyReadonlyArrayDyn<f32>,
-        documents_embeddings: PyReadonlyArrayDyn<f32>,
+        py: Python<'_>,
+        queries_embeddings: PyEmbeddings,
+        documents_embeddings: PyEmbeddings,
     ) -> PyResult<Vec<Vec<f32>>> {
-        let queries_tensor = tensor_from_array(queries_embeddings, &self.model.device)?;
-        let documents_tensor = tensor_from_array(documents_embeddings, &self.model.device)?;
+        let device = py.allow_threads(|| self.model().device.clone());
+        let queries_tensor = tensor_from_embeddings(queries_embeddings, &device)?;
+        let documents_tensor = tensor_from_embeddings(documents_embeddings, &device)?;
 
-        let similarities = self.model.similarity(&queries_tensor, &documents_tensor)?;
+        let similarities =
+            py.allow_threads(|| self.model().similarity(&queries_tensor, &documents_tensor))?;
         Ok(similarities.data)
     }
+
+    /// Describes the loaded model.
+    ///
+    /// # Returns
+    ///
+    /// A dictionary with the model id, architecture, embedding dimension, vocabulary size,
+    /// prefixes, query and document lengths, query expansion flags and device.
+    pub fn info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let info = py.allow_threads(|| self.model().info());
+        let dict = PyDict::new(py);
+        dict.set_item("model_id", info.model_id)?;
+        dict.set_item("architecture", info.architecture)?;
+        dict.set_item("embedding_dim", info.embedding_dim)?;
+        dict.set_item("vocab_size", info.vocab_size)?;
+        dict.set_item("query_length", info.query_length)?;
+        dict.set_item("document_length", info.document_length)?;
+        dict.set_item("query_prefix", info.query_prefix)?;
+        dict.set_item("document_prefix", info.document_prefix)?;
+        dict.set_item("mask_token", info.mask_token)?;
+        dict.set_item("do_query_expansion", info.do_query_expansion)?;
+        dict.set_item(
+            "attend_to_expansion_tokens",
+            info.attend_to_expansion_tokens,
+        )?;
+        dict.set_item("pool_factor", info.pool_factor)?;
+        dict.set_item("device", info.device)?;
+        Ok(dict)
+    }
+}
+
+/// Pools already-computed document embeddings with hierarchical clustering.
+///
+/// # Arguments
+///
+/// * `embeddings` - A NumPy array of shape `[batch_size, n_tokens, embedding_dim]`, or
+///   `[n_tokens, embedding_dim]` for a single document.
+/// * `pool_factor` - The factor by which the number of token embeddings is divided.
+///
+/// # Returns
+///
+/// A NumPy array of pooled embeddings with the same rank as the input.
+#[pyfunction(name = "hierarchical_pooling")]
+fn hierarchical_pooling_py<'py>(
+    py: Python<'py>,
+    embeddings: PyReadonlyArrayDyn<f32>,
+    pool_factor: usize,
+) -> PyResult<Bound<'py, PyArray<f32, IxDyn>>> {
+    let embeddings = tensor_from_array(embeddings, &Device::Cpu)?;
+    let (shape, data) = py.allow_threads(|| -> Result<_, ColbertError> {
+        if embeddings.rank() == 2 {
+            tensor_data(&hierarchical_pooling(&embeddings.unsqueeze(0)?, pool_factor)?.squeeze(0)?)
+        } else {
+            tensor_data(&hierarchical_pooling(&embeddings, pool_factor)?)
+        }
+    })?;
+    array_from_data(py, shape, data)
+}
+
+/// Helper function to read the shape and values of a Candle tensor.
+fn tensor_data(tensor: &Tensor) -> Result<(Vec<usize>, Vec<f32>), ColbertError> {
+    let data = tensor.flatten_all()?.to_vec1::<f32>()?;
+    Ok((tensor.dims().to_vec(), data))
+}
+
+/// Helper function to build a NumPy array from a shape and values.
+fn array_from_data(
+    py: Python<'_>,
+    shape: Vec<usize>,
+    data: Vec<f32>,
+) -> PyResult<Bound<'_, PyArray<f32, IxDyn>>> {
+    let ndarray = Array::from_shape_vec(shape, data)
+        .map_err(|e| PyValueError::new_err(format!("Error creating ndarray: {}", e)))?;
+    Ok(PyArray::from_owned_array(py, ndarray))
 }
 
 /// Helper function to convert a NumPy array to a Candle tensor.
@@ -190,11 +528,45 @@ fn tensor_from_array(array: PyReadonlyArrayDyn<f32>, device: &Device) -> PyResul
     Tensor::from_vec(data.to_vec(), shape, device).map_err(|e| PyValueError::new_err(e.to_string()))
 }
 
+/// Helper function to convert padded or ragged embeddings to a padded Candle tensor.
+fn tensor_from_embeddings(embeddings: PyEmbeddings, device: &Device) -> PyResult<Tensor> {
+    let arrays = match embeddings {
+        PyEmbeddings::Padded(array) => return tensor_from_array(array, device),
+        PyEmbeddings::Ragged(arrays) => arrays,
+    };
+    if arrays.is_empty() {
+        return Err(ColbertError::EmptyInput.into());
+    }
+
+    let tensors = arrays
+        .into_iter()
+        .map(|array| {
+            if array.ndim() != 2 {
+                return Err(PyValueError::new_err(format!(
+                    "Expected arrays of shape [length, embedding_dim], found {} dimensions",
+                    array.ndim()
+                )));
+            }
+            tensor_from_array(array, device)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let max_len = tensors.iter().map(|t| t.dims()[0]).max().unwrap_or(0);
+    let padded = tensors
+        .iter()
+        .map(|t| t.pad_with_zeros(0, 0, max_len - t.dims()[0]))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ColbertError::from)?;
+    Ok(Tensor::stack(&padded, 0).map_err(ColbertError::from)?)
+}
+
 /// The main Python module definition.
 ///
-/// This module, named `pylate_rs`, exposes the `PyColBERT` class to Python.
+/// This module, named `pylate_rs`, exposes the `PyColBERT` class and the
+/// `hierarchical_pooling` function to Python.
 #[pymodule]
 fn pylate_rs<'py>(_py: Python<'py>, m: &Bound<'py, PyModule>) -> PyResult<()> {
     m.add_class::<PyColBERT>()?;
+    m.add_class::<PyCancellationToken>()?;
+    m.add_function(wrap_pyfunction!(hierarchical_pooling_py, m)?)?;
     Ok(())
 }
@@ -0,0 +1,362 @@
+use crate::{
+    error::ColbertError,
+    model::ColBERT,
+    pooling::pool_documents,
+    types::{
+        EncodeOutput, EncodeRequest, ModelInfo, RankedDocument, RerankRequest, Similarities,
+        SimilarityInput,
+    },
+};
+use candle_core::Tensor;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tiny_http::{Header, Method, Request, Response};
+
+/// Settings of an `EmbeddingServer`.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// The model id reported by `/info`, instead of the one the model was loaded from.
+    pub model_id: Option<String>,
+    /// The number of threads parsing HTTP requests.
+    pub num_threads: usize,
+    /// The maximum number of sentences encoded together across concurrent requests.
+    pub max_batch_size: usize,
+    /// How long the first request of a batch waits for other requests to join it.
+    pub batch_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            model_id: None,
+            num_threads: 4,
+            max_batch_size: 64,
+            batch_timeout: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Counters of an `EmbeddingServer`, as returned by `EmbeddingServer::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ServerStats {
+    /// The number of encoding jobs received by the worker, one per side of a request.
+    pub jobs: u64,
+    /// The number of `encode` calls the worker made to encode them.
+    pub model_calls: u64,
+}
+
+/// The counters behind `ServerStats`, shared with the worker.
+#[derive(Default)]
+struct Counters {
+    jobs: AtomicU64,
+    model_calls: AtomicU64,
+}
+
+/// A sentence encoding request sent to the worker that owns the model.
+struct Job {
+    sentences: Vec<String>,
+    is_query: bool,
+    reply: Sender<Result<Vec<Tensor>, ColbertError>>,
+}
+
+/// A local HTTP server exposing a `ColBERT` model.
+///
+/// The model is loaded once and owned by a single worker thread. Requests are parsed
+/// by a pool of HTTP threads and their sentences are grouped into shared batches, so
+/// concurrent callers are encoded together. The server exposes the following endpoints:
+///
+/// - `GET /health` returns `{"status": "ok"}`.
+/// - `GET /info` returns a `ModelInfo`.
+/// - `POST /encode` takes an `EncodeRequest` and returns an `EncodeOutput` without padding rows.
+/// - `POST /similarity` takes a `SimilarityInput` and returns `Similarities`.
+/// - `POST /rerank` takes a `RerankRequest` and returns a list of `RankedDocument`.
+pub struct EmbeddingServer {
+    http: Arc<tiny_http::Server>,
+    handlers: Vec<JoinHandle<()>>,
+    worker: JoinHandle<()>,
+    counters: Arc<Counters>,
+}
+
+impl EmbeddingServer {
+    /// Binds `addr` and starts serving `model` in background threads.
+    pub fn start<A: ToSocketAddrs>(
+        model: ColBERT,
+        addr: A,
+        config: ServerConfig,
+    ) -> Result<Self, ColbertError> {
+        let http = Arc::new(tiny_http::Server::http(addr).map_err(ColbertError::Http)?);
+
+        let mut info = model.info();
+        if config.model_id.is_some() {
+            info.model_id = config.model_id.clone();
+        }
+        let info = Arc::new(info);
+
+        let (jobs, receiver) = mpsc::channel();
+        let counters = Arc::new(Counters::default());
+        let worker = {
+            let config = config.clone();
+            let counters = Arc::clone(&counters);
+            thread::spawn(move || run_worker(model, receiver, &config, &counters))
+        };
+
+        let handlers = (0..config.num_threads.max(1))
+            .map(|_| {
+                let http = Arc::clone(&http);
+                let info = Arc::clone(&info);
+                let jobs = jobs.clone();
+                thread::spawn(move || {
+                    while let Ok(request) = http.recv() {
+                        handle(request, &jobs, &info);
+                    }
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            http,
+            handlers,
+            worker,
+            counters,
+        })
+    }
+
+    /// Returns the number of jobs encoded so far and the model calls they took, which
+    /// tells how well concurrent requests are batched together.
+    pub fn stats(&self) -> ServerStats {
+        ServerStats {
+            jobs: self.counters.jobs.load(Ordering::Relaxed),
+            model_calls: self.counters.model_calls.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Blocks until the server stops.
+    pub fn join(self) {
+        for handler in self.handlers {
+            let _ = handler.join();
+        }
+        let _ = self.worker.join();
+    }
+
+    /// Stops accepting requests and waits for the running ones to complete.
+    pub fn shutdown(self) {
+        for _ in 0..self.handlers.len() {
+            self.http.unblock();
+        }
+        self.join();
+    }
+}
+
+/// Encodes the queued jobs, grouping the ones that arrive within the batch timeout.
+fn run_worker(mut model: ColBERT, jobs: Receiver<Job>, config: &ServerConfig, counters: &Counters) {
+    while let Ok(first) = jobs.recv() {
+        let deadline = Instant::now() + config.batch_timeout;
+        let mut num_sentences = first.sentences.len();
+        let mut pending = vec![first];
+        while num_sentences < config.max_batch_size {
+            match jobs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(job) => {
+                    num_sentences += job.sentences.len();
+                    pending.push(job);
+                },
+                Err(_) => break,
+            }
+        }
+
+        counters
+            .jobs
+            .fetch_add(pending.len() as u64, Ordering::Relaxed);
+        let (queries, documents): (Vec<Job>, Vec<Job>) =
+            pending.into_iter().partition(|job| job.is_query);
+        for (group, is_query) in [(queries, true), (documents, false)] {
+            if !group.is_empty() {
+                encode_group(&mut model, group, is_query, counters);
+            }
+        }
+    }
+}
+
+/// Encodes the sentences of several jobs in a single call and sends each job its
+/// unpadded embeddings.
+///
+/// If the shared call fails, each job is encoded on its own so that it gets its own
+/// error and the other jobs still succeed.
+fn encode_group(model: &mut ColBERT, group: Vec<Job>, is_query: bool, counters: &Counters) {
+    let sentences: Vec<String> = group
+        .iter()
+        .flat_map(|job| job.sentences.iter().cloned())
+        .collect();
+
+    match encode_sentences(model, &sentences, is_query, counters) {
+        Ok(embeddings) => {
+            let mut embeddings = embeddings.into_iter();
+            for job in group {
+                let job_embeddings = embeddings.by_ref().take(job.sentences.len()).collect();
+                let _ = job.reply.send(Ok(job_embeddings));
+            }
+        },
+        Err(err) if group.len() == 1 => {
+            let _ = group[0].reply.send(Err(err));
+        },
+        Err(_) => {
+            for job in group {
+                let _ = job
+                    .reply
+                    .send(encode_sentences(model, &job.sentences, is_query, counters));
+            }
+        },
+    }
+}
+
+/// Encodes sentences into one unpadded tensor each.
+fn encode_sentences(
+    model: &mut ColBERT,
+    sentences: &[String],
+    is_query: bool,
+    counters: &Counters,
+) -> Result<Vec<Tensor>, ColbertError> {
+    counters.model_calls.fetch_add(1, Ordering::Relaxed);
+    // Documents are pooled by the request handlers, which may override the pool factor.
+    let embeddings = model.encode_with_pool_factor(sentences, is_query, 1)?;
+    model.split_embeddings(&embeddings, is_query)
+}
+
+/// Sends sentences to the worker and waits for their embeddings.
+fn encode(
+    jobs: &Sender<Job>,
+    sentences: Vec<String>,
+    is_query: bool,
+) -> Result<Vec<Tensor>, ColbertError> {
+    if sentences.is_empty() {
+        return Err(ColbertError::EmptyInput);
+    }
+    let (reply, receiver) = mpsc::channel();
+    jobs.send(Job {
+        sentences,
+        is_query,
+        reply,
+    })
+    .map_err(|_| ColbertError::WorkerPanicked("encoding worker".into()))?;
+    receiver
+        .recv()
+        .map_err(|_| ColbertError::WorkerPanicked("encoding worker".into()))?
+}
+
+/// Computes the MaxSim score of every document for a single query.
+fn maxsim(query: &Tensor, documents: &[Tensor]) -> Result<Vec<f32>, ColbertError> {
+    documents
+        .iter()
+        .map(|document| {
+            query
+                .matmul(&document.t()?)?
+                .max(1)?
+                .sum_all()?
+                .to_scalar::<f32>()
+                .map_err(ColbertError::from)
+        })
+        .collect()
+}
+
+/// An error returned to the client with its HTTP status code.
+struct HttpError(u16, String);
+
+impl From<ColbertError> for HttpError {
+    fn from(err: ColbertError) -> Self {
+        let status = match err {
+            ColbertError::EmptyInput | ColbertError::Json(_) => 400,
+            _ => 500,
+        };
+        HttpError(status, err.to_string())
+    }
+}
+
+fn parse_body<T: DeserializeOwned>(request: &mut Request) -> Result<T, HttpError> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| HttpError(400, e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| HttpError(400, e.to_string()))
+}
+
+fn route(request: &mut Request, jobs: &Sender<Job>, info: &ModelInfo) -> Result<String, HttpError> {
+    let body = match (request.method(), request.url()) {
+        (Method::Get, "/health") => serde_json::json!({ "status": "ok" }).to_string(),
+        (Method::Get, "/info") => serde_json::to_string(info).map_err(ColbertError::from)?,
+        (Method::Post, "/encode") => {
+            let input: EncodeRequest = parse_body(request)?;
+            let mut embeddings = encode(jobs, input.sentences, input.is_query)?;
+            if !input.is_query {
+                let pool_factor = input.pool_factor.unwrap_or(info.pool_factor);
+                embeddings = pool_documents(&embeddings, pool_factor)?;
+            }
+            let embeddings = embeddings
+                .iter()
+                .map(|embedding| embedding.to_vec2::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(ColbertError::from)?;
+            to_json(&EncodeOutput { embeddings })?
+        },
+        (Method::Post, "/similarity") => {
+            let input: SimilarityInput = parse_body(request)?;
+            let queries = encode(jobs, input.queries, true)?;
+            let documents =
+                pool_documents(&encode(jobs, input.documents, false)?, info.pool_factor)?;
+            let data = queries
+                .iter()
+                .map(|query| maxsim(query, &documents))
+                .collect::<Result<_, _>>()?;
+            to_json(&Similarities { data })?
+        },
+        (Method::Post, "/rerank") => {
+            let input: RerankRequest = parse_body(request)?;
+            let num_documents = input.documents.len();
+            let query = encode(jobs, vec![input.query], true)?;
+            let documents =
+                pool_documents(&encode(jobs, input.documents, false)?, info.pool_factor)?;
+            let mut ranked: Vec<RankedDocument> = maxsim(&query[0], &documents)?
+                .into_iter()
+                .enumerate()
+                .map(|(index, score)| RankedDocument { index, score })
+                .collect();
+            ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+            ranked.truncate(input.top_k.unwrap_or(num_documents));
+            to_json(&ranked)?
+        },
+        (_, url) => return Err(HttpError(404, format!("Unknown endpoint {}", url))),
+    };
+    Ok(body)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, ColbertError> {
+    serde_json::to_string(value).map_err(ColbertError::from)
+}
+
+fn handle(mut request: Request, jobs: &Sender<Job>, info: &ModelInfo) {
+    let (status, body) = match route(&mut request, jobs, info) {
+        Ok(body) => (200, body),
+        Err(HttpError(status, message)) => {
+            (status, serde_json::json!({ "error": message }).to_string())
+        },
+    };
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type);
+    let _ = request.respond(response);
+}
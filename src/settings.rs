@@ -0,0 +1,139 @@
+use crate::{error::ColbertError, utils::default_skiplist_words};
+use serde::{Deserialize, Serialize};
+
+/// Settings of a ColBERT model that take precedence over its configuration files.
+///
+/// Set by the `ColbertBuilder` methods natively and passed as an object to the WASM
+/// constructor, where the fields use the same names.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ColbertOverrides {
+    pub(crate) query_prefix: Option<String>,
+    pub(crate) document_prefix: Option<String>,
+    pub(crate) mask_token: Option<String>,
+    pub(crate) skiplist_words: Option<Vec<String>>,
+    pub(crate) do_query_expansion: Option<bool>,
+    pub(crate) attend_to_expansion_tokens: Option<bool>,
+    pub(crate) query_length: Option<usize>,
+    pub(crate) document_length: Option<usize>,
+    pub(crate) pool_factor: Option<usize>,
+}
+
+/// The effective settings of a ColBERT model.
+pub(crate) struct ColbertSettings {
+    pub(crate) query_prefix: String,
+    pub(crate) document_prefix: String,
+    pub(crate) mask_token: String,
+    pub(crate) skiplist_words: Vec<String>,
+    pub(crate) do_query_expansion: bool,
+    pub(crate) attend_to_expansion_tokens: bool,
+    pub(crate) query_length: Option<usize>,
+    pub(crate) document_length: Option<usize>,
+    pub(crate) pool_factor: usize,
+}
+
+impl ColbertOverrides {
+    /// Resolves the settings that were not overridden from the sentence-transformers
+    /// config and the special tokens map, falling back to the PyLate defaults. Pooling
+    /// is not part of the config files and is disabled unless overridden.
+    pub(crate) fn resolve(
+        self,
+        st_config: &[u8],
+        special_tokens_map: &[u8],
+    ) -> Result<ColbertSettings, ColbertError> {
+        let st_config: serde_json::Value = serde_json::from_slice(st_config)?;
+        let special_tokens_map: serde_json::Value = serde_json::from_slice(special_tokens_map)?;
+
+        let query_prefix = self.query_prefix.unwrap_or_else(|| {
+            st_config["query_prefix"]
+                .as_str()
+                .unwrap_or("[Q]")
+                .to_string()
+        });
+        let document_prefix = self.document_prefix.unwrap_or_else(|| {
+            st_config["document_prefix"]
+                .as_str()
+                .unwrap_or("[D]")
+                .to_string()
+        });
+
+        let mask_token = self.mask_token.unwrap_or_else(|| {
+            special_tokens_map["mask_token"]
+                .as_str()
+                .unwrap_or("[MASK]")
+                .to_string()
+        });
+
+        let skiplist_words = self.skiplist_words.unwrap_or_else(|| {
+            st_config["skiplist_words"]
+                .as_array()
+                .map(|words| {
+                    words
+                        .iter()
+                        .filter_map(|word| word.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_else(default_skiplist_words)
+        });
+
+        let do_query_expansion = self
+            .do_query_expansion
+            .unwrap_or_else(|| st_config["do_query_expansion"].as_bool().unwrap_or(true));
+
+        let attend_to_expansion_tokens = self.attend_to_expansion_tokens.unwrap_or_else(|| {
+            st_config["attend_to_expansion_tokens"]
+                .as_bool()
+                .unwrap_or(false)
+        });
+        let query_length = self
+            .query_length
+            .or_else(|| st_config["query_length"].as_u64().map(|v| v as usize));
+        let document_length = self
+            .document_length
+            .or_else(|| st_config["document_length"].as_u64().map(|v| v as usize));
+
+        Ok(ColbertSettings {
+            query_prefix,
+            document_prefix,
+            mask_token,
+            skiplist_words,
+            do_query_expansion,
+            attend_to_expansion_tokens,
+            query_length,
+            document_length,
+            pool_factor: self.pool_factor.unwrap_or(1).max(1),
+        })
+    }
+}
+
+/// Returns the directories of the Dense modules listed in `modules.json`, in order.
+///
+/// ColBERT models have one Dense projection, optionally followed by a second one.
+pub(crate) fn dense_modules(modules: &[u8]) -> Result<(String, Option<String>), ColbertError> {
+    let modules: serde_json::Value = serde_json::from_slice(modules)?;
+    let modules = modules
+        .as_array()
+        .ok_or_else(|| ColbertError::missing_config_field("modules.json", "modules"))?;
+
+    let mut paths = Vec::new();
+    for module in modules {
+        let kind = module["type"]
+            .as_str()
+            .ok_or_else(|| ColbertError::missing_config_field("modules.json", "type"))?;
+        if kind.ends_with(".Dense") {
+            let path = module["path"]
+                .as_str()
+                .ok_or_else(|| ColbertError::missing_config_field("modules.json", "path"))?;
+            paths.push(path.to_string());
+        }
+    }
+
+    if paths.is_empty() || paths.len() > 2 {
+        return Err(ColbertError::UnsupportedArchitecture(format!(
+            "modules.json lists {} Dense modules, expected one or two",
+            paths.len()
+        )));
+    }
+    let dense2 = (paths.len() == 2).then(|| paths.remove(1));
+    Ok((paths.remove(0), dense2))
+}
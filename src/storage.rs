@@ -0,0 +1,554 @@
+use crate::{error::ColbertError, types::RankedDocument, utils::unpad_embeddings};
+use candle_core::{DType, Device, Tensor};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use memmap2::Mmap;
+use safetensors::{Dtype, SafeTensors};
+use std::collections::HashMap;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::{fs::File, path::Path};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The value of the `format` metadata entry written by `EmbeddingCollection::save`.
+const FORMAT: &str = "pylate-rs-embeddings";
+/// The version of the storage format.
+const VERSION: &str = "2";
+/// The version that stored ids and metadata as JSON in the safetensors header, which
+/// safetensors caps at 100MB.
+const HEADER_VERSION: &str = "1";
+
+/// An in-memory collection of ragged document embeddings, with ids and metadata.
+///
+/// The collection is written to a single safetensors file holding the concatenated
+/// token embeddings and the length of each document. Ids and serialized metadata are
+/// stored the same way, as concatenated bytes and lengths, so that the header stays
+/// small however large the collection grows. Use `EmbeddingStore` to read it back
+/// from disk, or `EmbeddingCollection::from_bytes` to load it in memory.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Default)]
+pub struct EmbeddingCollection {
+    ids: Vec<String>,
+    metadata: Vec<serde_json::Value>,
+    embeddings: Vec<Tensor>,
+}
+
+impl EmbeddingCollection {
+    /// Creates an empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of documents in the collection.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns `true` if the collection contains no documents.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Adds a single document with embeddings of shape `[document_length, embedding_dim]`.
+    pub fn push(
+        &mut self,
+        id: String,
+        embeddings: Tensor,
+        metadata: serde_json::Value,
+    ) -> Result<(), ColbertError> {
+        let (_, dim) = embeddings.dims2()?;
+        if let Some(first) = self.embeddings.first() {
+            let expected = first.dim(1)?;
+            if dim != expected {
+                return Err(ColbertError::DimensionMismatch {
+                    context: "collection embedding dimension".into(),
+                    expected,
+                    found: dim,
+                });
+            }
+        }
+
+        self.ids.push(id);
+        self.metadata.push(metadata);
+        self.embeddings
+            .push(embeddings.to_device(&Device::Cpu)?.to_dtype(DType::F32)?);
+        Ok(())
+    }
+
+    /// Adds a padded batch as returned by `ColBERT::encode`, removing the padding rows.
+    ///
+    /// `metadata` may be empty, in which case every document gets a `null` metadata value.
+    pub fn extend_from_batch(
+        &mut self,
+        ids: &[String],
+        embeddings: &Tensor,
+        metadata: &[serde_json::Value],
+    ) -> Result<(), ColbertError> {
+        let documents = unpad_embeddings(embeddings)?;
+        if ids.len() != documents.len() {
+            return Err(ColbertError::DimensionMismatch {
+                context: "number of document ids".into(),
+                expected: documents.len(),
+                found: ids.len(),
+            });
+        }
+        if !metadata.is_empty() && metadata.len() != documents.len() {
+            return Err(ColbertError::DimensionMismatch {
+                context: "number of metadata entries".into(),
+                expected: documents.len(),
+                found: metadata.len(),
+            });
+        }
+
+        for (i, (id, document)) in ids.iter().zip(documents).enumerate() {
+            let document_metadata = metadata.get(i).cloned().unwrap_or_default();
+            self.push(id.clone(), document, document_metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the collection to the bytes of a safetensors file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ColbertError> {
+        if self.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+
+        let embeddings = Tensor::cat(&self.embeddings, 0)?;
+        let lengths: Vec<u32> = self
+            .embeddings
+            .iter()
+            .map(|document| document.dim(0).map(|length| length as u32))
+            .collect::<Result<_, _>>()?;
+        let lengths = Tensor::new(lengths.as_slice(), &Device::Cpu)?;
+        let (ids, id_lengths) = concatenate(self.ids.iter().map(|id| id.as_bytes().to_vec()))?;
+        let (metadata, metadata_lengths) = concatenate(
+            self.metadata
+                .iter()
+                .map(serde_json::to_vec)
+                .collect::<Result<Vec<_>, _>>()?,
+        )?;
+
+        let header = HashMap::from([
+            ("format".to_string(), FORMAT.to_string()),
+            ("version".to_string(), VERSION.to_string()),
+        ]);
+
+        Ok(safetensors::serialize(
+            [
+                ("embeddings", &embeddings),
+                ("lengths", &lengths),
+                ("ids", &ids),
+                ("id_lengths", &id_lengths),
+                ("metadata", &metadata),
+                ("metadata_lengths", &metadata_lengths),
+            ],
+            Some(header),
+        )?)
+    }
+
+    /// Loads a collection from the bytes of a file written by `EmbeddingCollection::save`,
+    /// e.g. fetched by a browser.
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, ColbertError> {
+        let tensors = SafeTensors::deserialize(buffer)?;
+        let layout = Layout::read(buffer, &tensors, "buffer")?;
+
+        let embeddings = Tensor::from_raw_buffer(
+            tensors.tensor("embeddings")?.data(),
+            DType::F32,
+            &[layout.num_rows(), layout.embedding_dim],
+            &Device::Cpu,
+        )?;
+        let embeddings = layout
+            .offsets
+            .windows(2)
+            .map(|w| embeddings.narrow(0, w[0], w[1] - w[0]))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            ids: layout.ids,
+            metadata: layout.metadata,
+            embeddings,
+        })
+    }
+
+    /// Writes the collection to a safetensors file.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ColbertError> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Returns the ids of the documents, in insertion order.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    /// Returns the metadata of the document at `index`.
+    pub fn metadata(&self, index: usize) -> Option<&serde_json::Value> {
+        self.metadata.get(index)
+    }
+
+    /// Scores every document of the collection against each query with MaxSim and
+    /// returns the `top_k` documents per query, sorted by decreasing score.
+    ///
+    /// See `EmbeddingStore::search`; this variant avoids writing the collection to disk.
+    pub fn search(
+        &self,
+        queries_embeddings: &Tensor,
+        top_k: usize,
+    ) -> Result<Vec<Vec<RankedDocument>>, ColbertError> {
+        let embedding_dim = match self.embeddings.first() {
+            Some(first) => first.dim(1)?,
+            None => return Err(ColbertError::EmptyInput),
+        };
+        let device = queries_embeddings.device();
+        search(
+            queries_embeddings,
+            embedding_dim,
+            self.len(),
+            top_k,
+            |index| {
+                self.embeddings[index]
+                    .to_device(device)
+                    .map_err(ColbertError::from)
+            },
+        )
+    }
+}
+
+/// The ids, metadata and document offsets read from the header of a collection file.
+struct Layout {
+    ids: Vec<String>,
+    metadata: Vec<serde_json::Value>,
+    /// The first row of each document in the embeddings, followed by the number of rows.
+    offsets: Vec<usize>,
+    embedding_dim: usize,
+}
+
+impl Layout {
+    /// Parses the header of a collection file, `source` naming it in error messages.
+    ///
+    /// The `embeddings` and `lengths` tensors are checked against each other, so that
+    /// the rows of every document can then be read without further checks.
+    fn read(buffer: &[u8], tensors: &SafeTensors, source: &str) -> Result<Self, ColbertError> {
+        let (_, header) = SafeTensors::read_metadata(buffer)?;
+        let header = header.metadata().clone().unwrap_or_default();
+        if header.get("format").map(String::as_str) != Some(FORMAT) {
+            return Err(ColbertError::malformed_file(
+                source,
+                "format",
+                "not a pylate-rs embeddings file",
+            ));
+        }
+
+        let (ids, metadata) = if header.get("version").map(String::as_str) == Some(HEADER_VERSION) {
+            let field = |name: &str| {
+                header
+                    .get(name)
+                    .ok_or_else(|| ColbertError::missing_config_field("safetensors header", name))
+            };
+            let ids: Vec<String> = serde_json::from_str(field("ids")?)?;
+            let metadata: Vec<serde_json::Value> = serde_json::from_str(field("metadata")?)?;
+            (ids, metadata)
+        } else {
+            let ids = split(tensors, "ids", "id_lengths", source)?
+                .into_iter()
+                .map(|id| {
+                    String::from_utf8(id.to_vec())
+                        .map_err(|err| ColbertError::malformed_file(source, "ids", err.to_string()))
+                })
+                .collect::<Result<_, _>>()?;
+            let metadata = split(tensors, "metadata", "metadata_lengths", source)?
+                .into_iter()
+                .map(|metadata| {
+                    serde_json::from_slice(metadata).map_err(|err| {
+                        ColbertError::malformed_file(source, "metadata", err.to_string())
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            (ids, metadata)
+        };
+
+        let lengths = read_u32(tensors, "lengths", source)?;
+        if lengths.len() != ids.len() || metadata.len() != ids.len() {
+            return Err(ColbertError::malformed_file(
+                source,
+                "lengths",
+                format!(
+                    "{} ids, {} metadata entries and {} lengths",
+                    ids.len(),
+                    metadata.len(),
+                    lengths.len()
+                ),
+            ));
+        }
+
+        let mut offsets = Vec::with_capacity(lengths.len() + 1);
+        offsets.push(0);
+        for length in lengths {
+            offsets.push(offsets[offsets.len() - 1] + length as usize);
+        }
+
+        let embeddings = tensors.tensor("embeddings")?;
+        let (num_rows, embedding_dim) = match embeddings.shape() {
+            &[num_rows, embedding_dim] if embeddings.dtype() == Dtype::F32 => {
+                (num_rows, embedding_dim)
+            },
+            shape => {
+                return Err(ColbertError::malformed_file(
+                    source,
+                    "embeddings",
+                    format!(
+                        "expected F32 embeddings of rank 2, found {:?} of shape {:?}",
+                        embeddings.dtype(),
+                        shape
+                    ),
+                ))
+            },
+        };
+        let expected_rows = offsets[offsets.len() - 1];
+        if num_rows != expected_rows
+            || embeddings.data().len() != num_rows * embedding_dim * DType::F32.size_in_bytes()
+        {
+            return Err(ColbertError::malformed_file(
+                source,
+                "embeddings",
+                format!(
+                    "the lengths add up to {} rows, but the embeddings have {} rows",
+                    expected_rows, num_rows
+                ),
+            ));
+        }
+
+        Ok(Self {
+            ids,
+            metadata,
+            offsets,
+            embedding_dim,
+        })
+    }
+
+    /// Returns the number of rows of the embeddings.
+    fn num_rows(&self) -> usize {
+        self.offsets[self.offsets.len() - 1]
+    }
+}
+
+/// Concatenates `items` into a U8 tensor, returned with the U32 tensor of their lengths.
+fn concatenate(items: impl IntoIterator<Item = Vec<u8>>) -> Result<(Tensor, Tensor), ColbertError> {
+    let mut bytes = Vec::new();
+    let mut lengths = Vec::new();
+    for item in items {
+        lengths.push(u32::try_from(item.len()).map_err(|_| {
+            ColbertError::Operation(format!("Cannot store an entry of {} bytes", item.len()))
+        })?);
+        bytes.extend(item);
+    }
+    Ok((
+        Tensor::new(bytes.as_slice(), &Device::Cpu)?,
+        Tensor::new(lengths.as_slice(), &Device::Cpu)?,
+    ))
+}
+
+/// Reads the U32 tensor `name` of rank 1.
+fn read_u32(tensors: &SafeTensors, name: &str, source: &str) -> Result<Vec<u32>, ColbertError> {
+    let tensor = tensors.tensor(name)?;
+    if tensor.dtype() != Dtype::U32
+        || tensor.shape().len() != 1
+        || tensor.data().len() != tensor.shape()[0] * 4
+    {
+        return Err(ColbertError::malformed_file(
+            source,
+            name,
+            format!(
+                "expected U32 of rank 1, found {:?} of shape {:?}",
+                tensor.dtype(),
+                tensor.shape()
+            ),
+        ));
+    }
+    Ok(tensor
+        .data()
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect())
+}
+
+/// Splits the U8 tensor `name` into the entries whose lengths are stored in `lengths`.
+fn split<'a>(
+    tensors: &'a SafeTensors,
+    name: &str,
+    lengths: &str,
+    source: &str,
+) -> Result<Vec<&'a [u8]>, ColbertError> {
+    let lengths = read_u32(tensors, lengths, source)?;
+    let tensor = tensors.tensor(name)?;
+    let total: usize = lengths.iter().map(|&length| length as usize).sum();
+    if tensor.dtype() != Dtype::U8 || tensor.shape() != [total] || tensor.data().len() != total {
+        return Err(ColbertError::malformed_file(
+            source,
+            name,
+            format!(
+                "expected {} U8 bytes, found {:?} of shape {:?}",
+                total,
+                tensor.dtype(),
+                tensor.shape()
+            ),
+        ));
+    }
+
+    let mut data = tensor.data();
+    Ok(lengths
+        .into_iter()
+        .map(|length| {
+            let (entry, rest) = data.split_at(length as usize);
+            data = rest;
+            entry
+        })
+        .collect())
+}
+
+/// A read-only, memory-mapped view over a file written by `EmbeddingCollection::save`.
+///
+/// Only the header is parsed when opening the file; the embeddings of a document
+/// are read from the mapping when requested.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct EmbeddingStore {
+    mmap: Mmap,
+    ids: Vec<String>,
+    metadata: Vec<serde_json::Value>,
+    offsets: Vec<usize>,
+    embedding_dim: usize,
+    data_start: usize,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl EmbeddingStore {
+    /// Opens a collection file through a read-only memory map.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ColbertError> {
+        let file = File::open(path.as_ref())?;
+        // SAFETY: the mapping is read-only and the file is expected not to be
+        // modified while the store is alive, as for candle's memory-mapped weights.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let tensors = SafeTensors::deserialize(&mmap)?;
+        let layout = Layout::read(&mmap, &tensors, &path.as_ref().display().to_string())?;
+
+        let data_start =
+            tensors.tensor("embeddings")?.data().as_ptr() as usize - mmap.as_ptr() as usize;
+
+        Ok(Self {
+            mmap,
+            ids: layout.ids,
+            metadata: layout.metadata,
+            offsets: layout.offsets,
+            embedding_dim: layout.embedding_dim,
+            data_start,
+        })
+    }
+
+    /// Returns the number of documents in the store.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns `true` if the store contains no documents.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the dimension of the token embeddings.
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    /// Returns the ids of the documents, in storage order.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    /// Returns the metadata of the document at `index`.
+    pub fn metadata(&self, index: usize) -> Option<&serde_json::Value> {
+        self.metadata.get(index)
+    }
+
+    /// Loads the `[document_length, embedding_dim]` embeddings of the document at `index`.
+    pub fn embeddings(&self, index: usize, device: &Device) -> Result<Tensor, ColbertError> {
+        if index >= self.len() {
+            return Err(ColbertError::IndexOutOfRange {
+                index,
+                len: self.len(),
+            });
+        }
+
+        let (start, end) = (self.offsets[index], self.offsets[index + 1]);
+        let row_bytes = self.embedding_dim * DType::F32.size_in_bytes();
+        let bytes =
+            &self.mmap[self.data_start + start * row_bytes..self.data_start + end * row_bytes];
+        Tensor::from_raw_buffer(
+            bytes,
+            DType::F32,
+            &[end - start, self.embedding_dim],
+            device,
+        )
+        .map_err(ColbertError::from)
+    }
+
+    /// Scores every stored document against each query with MaxSim and returns the
+    /// `top_k` documents per query, sorted by decreasing score.
+    ///
+    /// `queries_embeddings` has shape `[num_queries, query_length, embedding_dim]`, as
+    /// returned by `ColBERT::encode`. Documents are read one at a time from the mapping.
+    pub fn search(
+        &self,
+        queries_embeddings: &Tensor,
+        top_k: usize,
+    ) -> Result<Vec<Vec<RankedDocument>>, ColbertError> {
+        let device = queries_embeddings.device();
+        search(
+            queries_embeddings,
+            self.embedding_dim,
+            self.len(),
+            top_k,
+            |index| self.embeddings(index, device),
+        )
+    }
+}
+
+/// Ranks `num_documents` documents, loaded one at a time, against every query with MaxSim.
+fn search<F>(
+    queries_embeddings: &Tensor,
+    embedding_dim: usize,
+    num_documents: usize,
+    top_k: usize,
+    document: F,
+) -> Result<Vec<Vec<RankedDocument>>, ColbertError>
+where
+    F: Fn(usize) -> Result<Tensor, ColbertError>,
+{
+    let (num_queries, _, dim) = queries_embeddings.dims3()?;
+    if dim != embedding_dim {
+        return Err(ColbertError::DimensionMismatch {
+            context: "query embedding dimension".into(),
+            expected: embedding_dim,
+            found: dim,
+        });
+    }
+
+    let mut scores: Vec<Vec<RankedDocument>> = vec![Vec::with_capacity(num_documents); num_queries];
+    for index in 0..num_documents {
+        let document_scores = queries_embeddings
+            .broadcast_matmul(&document(index)?.t()?)?
+            .max(2)?
+            .sum(1)?
+            .to_vec1::<f32>()?;
+        for (query_scores, score) in scores.iter_mut().zip(document_scores) {
+            query_scores.push(RankedDocument { index, score });
+        }
+    }
+
+    for query_scores in scores.iter_mut() {
+        query_scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+        query_scores.truncate(top_k);
+    }
+    Ok(scores)
+}
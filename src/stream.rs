@@ -0,0 +1,149 @@
+use crate::{
+    error::ColbertError,
+    model::{count_tokens, ColBERT, TokenizedBatch},
+    pooling::pool_documents,
+    progress::ProgressTracker,
+    utils::{pad_embeddings, unpad_embeddings},
+};
+use candle_core::Tensor;
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread::{self, JoinHandle},
+};
+
+/// The embeddings of a batch of sentences yielded by `EncodeStream`.
+pub struct EncodedBatch {
+    /// The ids of the sentences, in input order.
+    pub ids: Vec<String>,
+    /// The embeddings of the sentences, padded like the output of `ColBERT::encode`.
+    pub embeddings: Tensor,
+}
+
+type Tokenized = Result<(Vec<String>, TokenizedBatch), ColbertError>;
+
+/// An iterator encoding `(id, text)` pairs one batch at a time, returned by
+/// `ColBERT::encode_stream`.
+///
+/// A background thread reads the input and tokenizes the upcoming batches while the
+/// model encodes the current one, staying at most `prefetch` batches ahead. The thread
+/// stops when the input is exhausted, the stream is cancelled or the stream is dropped.
+pub struct EncodeStream<'a> {
+    model: &'a ColBERT,
+    is_query: bool,
+    /// The tokenized batches, until the stream is cancelled.
+    batches: Option<Receiver<Tokenized>>,
+    tokenizer: Option<JoinHandle<()>>,
+    tracker: ProgressTracker<'a>,
+}
+
+impl ColBERT {
+    /// Encodes a stream of `(id, text)` pairs in batches of `batch_size`, without
+    /// holding more than the batches being tokenized and encoded in memory.
+    ///
+    /// Each batch is encoded like `encode`, documents being pooled with the pool factor
+    /// of the model, so the batches can be written straight into an
+    /// `EmbeddingCollection` with `extend_from_batch`. Up to `prefetch` batches are
+    /// tokenized ahead of the model. The stream does not consult the `EmbeddingCache`.
+    ///
+    /// The progress callback of the model is called after each batch, with totals of
+    /// zero when the input does not know its length. Once the cancellation token of the
+    /// model is cancelled, the stream yields `ColbertError::Cancelled` and ends.
+    ///
+    /// The input is read on a thread that outlives this call, hence the `Send + 'static`
+    /// bound: collect borrowed inputs into owned pairs first.
+    pub fn encode_stream<I>(&self, items: I, is_query: bool, prefetch: usize) -> EncodeStream<'_>
+    where
+        I: IntoIterator<Item = (String, String)>,
+        I::IntoIter: Send + 'static,
+    {
+        let tokenization = self.tokenization(is_query);
+        let mut tokenizer = self.tokenizer.clone();
+        tokenization.configure(&mut tokenizer);
+        let batch_size = self.batch_size.max(1);
+        let (sender, batches) = mpsc::sync_channel(prefetch.max(1));
+
+        let items = items.into_iter();
+        let total_sentences = match items.size_hint() {
+            (lower, Some(upper)) if lower == upper => lower,
+            _ => 0,
+        };
+        let tracker = ProgressTracker::new(
+            self.progress.as_ref(),
+            self.cancellation.as_ref(),
+            total_sentences.div_ceil(batch_size),
+            total_sentences,
+        );
+
+        let handle = thread::spawn(move || {
+            let mut items = items.peekable();
+            while items.peek().is_some() {
+                let (ids, texts): (Vec<String>, Vec<String>) =
+                    items.by_ref().take(batch_size).unzip();
+                let batch = tokenization
+                    .tokenize(&tokenizer, &texts)
+                    .map(|batch| (ids, batch));
+                // The stream was dropped.
+                if sender.send(batch).is_err() {
+                    return;
+                }
+            }
+        });
+
+        EncodeStream {
+            model: self,
+            is_query,
+            batches: Some(batches),
+            tokenizer: Some(handle),
+            tracker,
+        }
+    }
+}
+
+impl EncodeStream<'_> {
+    fn encode(
+        &self,
+        ids: Vec<String>,
+        batch: TokenizedBatch,
+    ) -> Result<EncodedBatch, ColbertError> {
+        let (token_ids, attention_mask, token_type_ids) = batch.into_tensors(&self.model.device)?;
+        let num_sentences = ids.len();
+        let mut embeddings = self.model.forward_batch(
+            &token_ids,
+            &attention_mask,
+            &token_type_ids,
+            self.is_query,
+        )?;
+        if !self.is_query && self.model.pool_factor > 1 {
+            let documents =
+                pool_documents(&unpad_embeddings(&embeddings)?, self.model.pool_factor)?;
+            embeddings = pad_embeddings(&documents)?.to_device(&self.model.device)?;
+        }
+        self.tracker
+            .record(num_sentences, count_tokens(&attention_mask)?);
+        Ok(EncodedBatch { ids, embeddings })
+    }
+}
+
+impl Iterator for EncodeStream<'_> {
+    type Item = Result<EncodedBatch, ColbertError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batches = self.batches.as_ref()?;
+        if let Err(err) = self.tracker.check() {
+            // Dropping the receiver stops the tokenization thread at its next batch.
+            self.batches = None;
+            return Some(Err(err));
+        }
+        match batches.recv() {
+            Ok(Ok((ids, batch))) => Some(self.encode(ids, batch)),
+            Ok(Err(err)) => Some(Err(err)),
+            // The input is exhausted, unless the thread reading it panicked.
+            Err(_) => match self.tokenizer.take().map(JoinHandle::join) {
+                Some(Err(_)) => Some(Err(ColbertError::WorkerPanicked(
+                    "tokenization thread".into(),
+                ))),
+                _ => None,
+            },
+        }
+    }
+}
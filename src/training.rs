@@ -0,0 +1,307 @@
+use crate::{builder::ColbertBuilder, error::ColbertError, model::ColBERT};
+use candle_core::{DType, Device, Shape, Tensor, Var, D};
+use candle_nn::{loss, ops, var_builder::SimpleBackend, AdamW, Init, Optimizer, ParamsAdamW};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+/// Hyperparameters of a `ColbertTrainer`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrainingConfig {
+    /// The AdamW learning rate.
+    pub learning_rate: f64,
+    /// The AdamW weight decay.
+    pub weight_decay: f64,
+    /// The temperature dividing the MaxSim scores in the contrastive loss.
+    pub temperature: f64,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            learning_rate: 2e-5,
+            weight_decay: 0.01,
+            temperature: 1.0,
+        }
+    }
+}
+
+/// A query with one relevant document and optional hard negatives.
+///
+/// The positives and hard negatives of the other examples of a batch are used as
+/// additional in-batch negatives.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContrastiveExample {
+    /// The query text.
+    pub query: String,
+    /// The relevant document.
+    pub positive: String,
+    /// Hard negative documents, which may be empty.
+    #[serde(default)]
+    pub negatives: Vec<String>,
+}
+
+/// A query with documents scored by a teacher model, e.g. a cross-encoder.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistillationExample {
+    /// The query text.
+    pub query: String,
+    /// The documents scored by the teacher.
+    pub documents: Vec<String>,
+    /// The teacher score of each document.
+    pub scores: Vec<f32>,
+}
+
+/// The weights of a safetensors file, with the floating point tensors held as variables.
+struct TrainableWeights {
+    vars: HashMap<String, Var>,
+    constants: HashMap<String, Tensor>,
+}
+
+impl TrainableWeights {
+    /// Loads the tensors of a safetensors buffer, converting floating point tensors to `F32`.
+    fn load(buffer: &[u8], device: &Device) -> Result<Self, ColbertError> {
+        let mut vars = HashMap::new();
+        let mut constants = HashMap::new();
+        for (name, tensor) in candle_core::safetensors::load_buffer(buffer, device)? {
+            if tensor.dtype().is_float() {
+                vars.insert(name, Var::from_tensor(&tensor.to_dtype(DType::F32)?)?);
+            } else {
+                constants.insert(name, tensor);
+            }
+        }
+        Ok(Self { vars, constants })
+    }
+}
+
+impl SimpleBackend for TrainableWeights {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        _: Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let tensor = self.get_unchecked(name, dtype, dev)?;
+        if tensor.shape() != &s {
+            Err(candle_core::Error::UnexpectedShape {
+                msg: format!("shape mismatch for {name}"),
+                expected: s,
+                got: tensor.shape().clone(),
+            }
+            .bt())?
+        }
+        Ok(tensor)
+    }
+
+    fn get_unchecked(&self, name: &str, dtype: DType, dev: &Device) -> candle_core::Result<Tensor> {
+        let tensor = match (self.vars.get(name), self.constants.get(name)) {
+            (Some(var), _) => var.as_tensor(),
+            (None, Some(tensor)) => tensor,
+            (None, None) => Err(candle_core::Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt())?,
+        };
+        tensor.to_dtype(dtype)?.to_device(dev)
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.vars.contains_key(name) || self.constants.contains_key(name)
+    }
+}
+
+/// Fine-tunes the backbone and Dense layers of a ColBERT model.
+///
+/// The trainer loads the model described by a `ColbertBuilder` with trainable weights
+/// and updates them with AdamW, using either an in-batch-negative contrastive loss over
+/// MaxSim scores or a KL-divergence distillation loss from teacher scores. Checkpoints
+/// are written in the sentence-transformers layout read by `ColbertBuilder`.
+///
+/// Documents are never pooled during training, whatever the pool factor of the builder.
+pub struct ColbertTrainer {
+    model: ColBERT,
+    optimizer: AdamW,
+    config: TrainingConfig,
+}
+
+impl ColbertTrainer {
+    /// Loads the model described by `builder` with trainable weights.
+    pub fn new(builder: ColbertBuilder, config: TrainingConfig) -> Result<Self, ColbertError> {
+        let files = builder.resolve()?;
+
+        let device = files.device;
+        let weights = TrainableWeights::load(&files.weights, &device)?;
+        let dense_weights = TrainableWeights::load(&files.dense_weights, &device)?;
+        let dense2_weights = files
+            .dense2_weights
+            .as_deref()
+            .map(|buffer| TrainableWeights::load(buffer, &device))
+            .transpose()?;
+
+        let vars: Vec<Var> = [
+            Some(&weights),
+            Some(&dense_weights),
+            dense2_weights.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|weights| weights.vars.values().cloned())
+        .collect();
+
+        let mut model = ColBERT::from_backends(
+            Box::new(weights),
+            Box::new(dense_weights),
+            dense2_weights.map(|weights| -> Box<dyn SimpleBackend> { Box::new(weights) }),
+            &files.tokenizer,
+            &files.config,
+            &files.dense_config,
+            files.dense2_config.as_deref(),
+            files.query_prefix,
+            files.document_prefix,
+            files.mask_token,
+            files.skiplist_words,
+            files.do_query_expansion,
+            files.attend_to_expansion_tokens,
+            files.query_length,
+            files.document_length,
+            files.batch_size,
+            &device,
+        )?;
+        model.model_id = Some(files.model_id);
+
+        let optimizer = AdamW::new(
+            vars,
+            ParamsAdamW {
+                lr: config.learning_rate,
+                weight_decay: config.weight_decay,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            model,
+            optimizer,
+            config,
+        })
+    }
+
+    /// Returns the model being trained, e.g. to encode with the current weights.
+    pub fn model(&mut self) -> &mut ColBERT {
+        &mut self.model
+    }
+
+    /// Returns the hyperparameters of the trainer.
+    pub fn config(&self) -> &TrainingConfig {
+        &self.config
+    }
+
+    /// Sets the learning rate used by the next steps, e.g. to follow a schedule.
+    pub fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.config.learning_rate = learning_rate;
+        self.optimizer.set_learning_rate(learning_rate);
+    }
+
+    /// Runs one optimization step with the in-batch-negative contrastive loss.
+    ///
+    /// Each query is scored against the positives and hard negatives of the whole batch,
+    /// and the cross-entropy of its own positive is minimized. Returns the loss.
+    pub fn contrastive_step(
+        &mut self,
+        examples: &[ContrastiveExample],
+    ) -> Result<f32, ColbertError> {
+        if examples.is_empty() {
+            return Err(ColbertError::EmptyInput);
+        }
+        let queries: Vec<String> = examples.iter().map(|e| e.query.clone()).collect();
+        let documents: Vec<String> = examples
+            .iter()
+            .map(|e| e.positive.clone())
+            .chain(examples.iter().flat_map(|e| e.negatives.iter().cloned()))
+            .collect();
+
+        let queries_embeddings = self.model.encode(&queries, true)?;
+        let documents_embeddings = self.model.encode(&documents, false)?;
+
+        // [num_queries, num_documents] MaxSim scores, the positive of query i being document i.
+        let scores = queries_embeddings
+            .unsqueeze(1)?
+            .broadcast_matmul(&documents_embeddings.transpose(1, 2)?.unsqueeze(0)?)?
+            .max(3)?
+            .sum(2)?;
+        let scores = (scores / self.config.temperature)?;
+        let labels = Tensor::arange(0u32, examples.len() as u32, &self.model.device)?;
+        let loss = loss::cross_entropy(&scores, &labels)?;
+
+        self.optimizer.backward_step(&loss)?;
+        Ok(loss.to_scalar::<f32>()?)
+    }
+
+    /// Runs one optimization step distilling the teacher scores into the model.
+    ///
+    /// Minimizes the KL divergence between the softmax of the teacher scores and the
+    /// softmax of the MaxSim scores of each query, averaged over the batch. Every example
+    /// must have the same number of documents. Returns the loss.
+    pub fn distillation_step(
+        &mut self,
+        examples: &[DistillationExample],
+    ) -> Result<f32, ColbertError> {
+        let num_documents = examples
+            .first()
+            .map(|e| e.documents.len())
+            .ok_or(ColbertError::EmptyInput)?;
+        if num_documents == 0 {
+            return Err(ColbertError::EmptyInput);
+        }
+        for example in examples {
+            for found in [example.documents.len(), example.scores.len()] {
+                if found != num_documents {
+                    return Err(ColbertError::DimensionMismatch {
+                        context: "distillation documents and teacher scores".to_string(),
+                        expected: num_documents,
+                        found,
+                    });
+                }
+            }
+        }
+
+        let queries: Vec<String> = examples.iter().map(|e| e.query.clone()).collect();
+        let documents: Vec<String> = examples
+            .iter()
+            .flat_map(|e| e.documents.iter().cloned())
+            .collect();
+        let teacher_scores: Vec<f32> = examples.iter().flat_map(|e| e.scores.clone()).collect();
+
+        let queries_embeddings = self.model.encode(&queries, true)?;
+        let documents_embeddings = self.model.encode(&documents, false)?;
+        let (_, document_tokens, dim) = documents_embeddings.dims3()?;
+        let documents_embeddings =
+            documents_embeddings.reshape((examples.len(), num_documents, document_tokens, dim))?;
+
+        // [num_queries, num_documents] MaxSim scores of each query against its own documents.
+        let scores = queries_embeddings
+            .unsqueeze(1)?
+            .broadcast_matmul(&documents_embeddings.transpose(2, 3)?)?
+            .max(3)?
+            .sum(2)?;
+        let teacher_scores = Tensor::from_vec(
+            teacher_scores,
+            (examples.len(), num_documents),
+            &self.model.device,
+        )?;
+
+        let student = ops::log_softmax(&scores, D::Minus1)?;
+        let teacher = ops::log_softmax(&teacher_scores, D::Minus1)?;
+        let loss = (teacher.exp()? * (teacher - student)?)?
+            .sum(D::Minus1)?
+            .mean_all()?;
+
+        self.optimizer.backward_step(&loss)?;
+        Ok(loss.to_scalar::<f32>()?)
+    }
+
+    /// Writes a checkpoint of the current weights with `ColBERT::save_pretrained`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ColbertError> {
+        self.model.save_pretrained(path)
+    }
+}
@@ -21,6 +21,10 @@ pub struct EncodeInput {
     pub sentences: Vec<String>,
     /// An optional batch size to override the model's default.
     pub batch_size: Option<usize>,
+    /// An optional factor by which document embeddings are pooled, instead of the
+    /// model's pool factor.
+    #[serde(default)]
+    pub pool_factor: Option<usize>,
 }
 
 /// Output structure for the encoding process.
@@ -57,3 +61,83 @@ pub struct RawSimilarityOutput {
     /// The tokens corresponding to each document.
     pub document_tokens: Vec<Vec<String>>,
 }
+
+/// Request body of the server's `/encode` endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncodeRequest {
+    /// A list of sentences (queries or documents) to be encoded.
+    pub sentences: Vec<String>,
+    /// Whether the sentences are encoded as queries.
+    #[serde(default)]
+    pub is_query: bool,
+    /// An optional hierarchical pooling factor applied to document embeddings.
+    pub pool_factor: Option<usize>,
+}
+
+/// Request body of the server's `/rerank` endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RerankRequest {
+    /// The query the documents are scored against.
+    pub query: String,
+    /// The candidate documents to rerank.
+    pub documents: Vec<String>,
+    /// An optional number of documents to return, all of them by default.
+    pub top_k: Option<usize>,
+}
+
+/// Description of a loaded model, as returned by `ColBERT::info` and the server's
+/// `/info` endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    /// The repository or directory the model was loaded from, if known.
+    pub model_id: Option<String>,
+    /// The backbone architecture declared in `config.json`, e.g. `ModernBertModel`.
+    pub architecture: String,
+    /// The dimension of the token embeddings.
+    pub embedding_dim: usize,
+    /// The number of tokens in the tokenizer's vocabulary, including added tokens.
+    pub vocab_size: usize,
+    /// The maximum number of tokens of a query.
+    pub query_length: usize,
+    /// The maximum number of tokens of a document.
+    pub document_length: usize,
+    /// The prefix prepended to queries.
+    pub query_prefix: String,
+    /// The prefix prepended to documents.
+    pub document_prefix: String,
+    /// The token used to pad queries.
+    pub mask_token: String,
+    /// Whether queries are padded with mask tokens for query expansion.
+    pub do_query_expansion: bool,
+    /// Whether query tokens attend to the expansion tokens.
+    pub attend_to_expansion_tokens: bool,
+    /// The factor by which document embeddings are pooled, 1 when they are not pooled.
+    pub pool_factor: usize,
+    /// The device the model runs on: `cpu`, `cuda:<index>` or `mps`.
+    pub device: String,
+}
+
+/// A document together with the score assigned by a reranker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RankedDocument {
+    /// The position of the document in the input list.
+    pub index: usize,
+    /// The relevance score of the document for the query.
+    pub score: f32,
+}
+
+/// Output structure for a fill-mask prediction.
+///
+/// Contains the most likely vocabulary tokens for a single masked position,
+/// sorted by decreasing probability.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaskPrediction {
+    /// The position of the masked token in the tokenized sequence.
+    pub position: usize,
+    /// The predicted tokens.
+    pub tokens: Vec<String>,
+    /// The vocabulary ids of the predicted tokens.
+    pub token_ids: Vec<u32>,
+    /// The probability of each predicted token.
+    pub scores: Vec<f32>,
+}
@@ -1,8 +1,52 @@
 use crate::error::ColbertError;
-use candle_core::Tensor;
+use candle_core::{IndexOp, Tensor};
+
+/// The punctuation characters skipped by default in document embeddings,
+/// matching Python's `string.punctuation` used by PyLate.
+const PUNCTUATION: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
 
 /// Normalizes a tensor using L2 normalization along the last dimension.
 pub fn normalize_l2(v: &Tensor) -> Result<Tensor, ColbertError> {
     let norm_l2 = v.sqr()?.sum_keepdim(v.rank() - 1)?.sqrt()?;
     v.broadcast_div(&norm_l2).map_err(ColbertError::from)
 }
+
+/// Returns the default skiplist words, one entry per punctuation character.
+pub fn default_skiplist_words() -> Vec<String> {
+    PUNCTUATION.chars().map(|c| c.to_string()).collect()
+}
+
+/// Removes the trailing zero rows that `ColBERT::encode` uses to pad a batch.
+///
+/// Takes embeddings of shape `[batch_size, n_tokens, embedding_dim]` and returns one
+/// `[document_length, embedding_dim]` tensor per document. At least one row is kept
+/// per document, matching the single zero vector produced for empty documents.
+pub fn unpad_embeddings(embeddings: &Tensor) -> Result<Vec<Tensor>, ColbertError> {
+    let (batch_size, n_tokens, _) = embeddings.dims3()?;
+    let row_norms = embeddings.abs()?.sum(2)?.to_vec2::<f32>()?;
+
+    let mut documents = Vec::with_capacity(batch_size);
+    for (i, norms) in row_norms.iter().enumerate() {
+        let padding = norms.iter().rev().take_while(|&&norm| norm == 0.0).count();
+        let length = (n_tokens - padding).max(1);
+        documents.push(embeddings.i(i)?.narrow(0, 0, length)?);
+    }
+    Ok(documents)
+}
+
+/// Stacks `[document_length, embedding_dim]` tensors into a batch padded with zero rows,
+/// the inverse of `unpad_embeddings`.
+pub(crate) fn pad_embeddings(documents: &[Tensor]) -> Result<Tensor, ColbertError> {
+    let max_len = documents
+        .iter()
+        .map(|document| document.dim(0))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .max()
+        .ok_or(ColbertError::EmptyInput)?;
+    let padded = documents
+        .iter()
+        .map(|document| document.pad_with_zeros(0, 0, max_len - document.dim(0)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Tensor::stack(&padded, 0)?)
+}
@@ -1,15 +1,203 @@
 use crate::{
     error::ColbertError,
     model::ColBERT,
-    pooling::hierarchical_pooling,
-    types::{EncodeInput, EncodeOutput, RawSimilarityOutput, SimilarityInput, Similarities},
+    pooling::pool_documents,
+    progress::{CancellationToken, EncodeProgress, ProgressCallback},
+    settings::{dense_modules, ColbertOverrides},
+    storage::EmbeddingCollection,
+    types::{EncodeInput, SimilarityInput},
+    utils::unpad_embeddings,
 };
-use candle_core::{Device, IndexOp, Tensor};
+use candle_core::{DType, Device, Tensor};
+use js_sys::{Array, Float32Array, Function, Object, Reflect, Uint32Array};
+use serde::Serialize;
+use std::{cell::RefCell, sync::Arc};
 use wasm_bindgen::prelude::*;
 
+/// A JavaScript progress callback, which cancels encoding when it returns `false` or
+/// throws.
+struct JsProgress {
+    function: Function,
+    cancellation: CancellationToken,
+    /// The exception thrown by the callback, rethrown once encoding stops.
+    error: RefCell<Option<JsValue>>,
+}
+
+// wasm32-unknown-unknown is single-threaded, so the callback is only ever called from
+// the thread that created it.
+unsafe impl Send for JsProgress {}
+unsafe impl Sync for JsProgress {}
+
+impl JsProgress {
+    fn report(&self, progress: &EncodeProgress) {
+        let result = serde_wasm_bindgen::to_value(progress)
+            .map_err(JsValue::from)
+            .and_then(|progress| self.function.call1(&JsValue::NULL, &progress));
+        match result {
+            Ok(value) if value == JsValue::FALSE => self.cancellation.cancel(),
+            Ok(_) => {},
+            Err(err) => {
+                *self.error.borrow_mut() = Some(err);
+                self.cancellation.cancel();
+            },
+        }
+    }
+}
+
+/// Embeddings of several sentences without padding, as exchanged with JavaScript.
+///
+/// The rows of sentence `i` are the rows `offsets[i]..offsets[i + 1]` of the
+/// row-major `[rows, dim]` matrix `data`.
+#[derive(Clone)]
+struct FlatEmbeddings {
+    data: Vec<f32>,
+    offsets: Vec<u32>,
+    dim: usize,
+}
+
+impl FlatEmbeddings {
+    /// Checks that `offsets` start at 0, are non-decreasing and end at the number of
+    /// rows of `data`.
+    fn new(data: Vec<f32>, offsets: Vec<u32>, dim: usize) -> Result<Self, ColbertError> {
+        let rows = offsets.last().copied().unwrap_or(0) as usize;
+        if dim == 0
+            || offsets.first() != Some(&0)
+            || offsets.windows(2).any(|w| w[0] > w[1])
+            || rows * dim != data.len()
+        {
+            return Err(ColbertError::Operation(
+                "Embeddings `offsets` must start at 0, be non-decreasing and end at data.length / dim"
+                    .to_string(),
+            ));
+        }
+        Ok(Self { data, offsets, dim })
+    }
+
+    /// Flattens per-sentence `[length, dim]` tensors.
+    fn from_documents(documents: &[Tensor], dim: usize) -> Result<Self, ColbertError> {
+        let mut data = Vec::new();
+        let mut offsets = Vec::with_capacity(documents.len() + 1);
+        offsets.push(0);
+        for document in documents {
+            data.extend(document.flatten_all()?.to_vec1::<f32>()?);
+            offsets.push((data.len() / dim.max(1)) as u32);
+        }
+        Ok(Self { data, offsets, dim })
+    }
+
+    /// Flattens a padded `[batch_size, n_tokens, dim]` tensor, dropping the padding rows.
+    fn from_padded(embeddings: &Tensor) -> Result<Self, ColbertError> {
+        let (_, _, dim) = embeddings.dims3()?;
+        Self::from_documents(&unpad_embeddings(embeddings)?, dim)
+    }
+
+    /// Returns the `[length, dim]` tensor of each sentence.
+    fn documents(&self) -> Result<Vec<Tensor>, ColbertError> {
+        self.offsets
+            .windows(2)
+            .map(|w| {
+                let (start, end) = (w[0] as usize, w[1] as usize);
+                Ok(Tensor::from_slice(
+                    &self.data[start * self.dim..end * self.dim],
+                    (end - start, self.dim),
+                    &Device::Cpu,
+                )?)
+            })
+            .collect()
+    }
+
+    /// Stacks the sentences into a `[batch_size, n_tokens, dim]` tensor padded with zero rows.
+    fn to_padded(&self) -> Result<Tensor, ColbertError> {
+        let n_tokens = self
+            .offsets
+            .windows(2)
+            .map(|w| (w[1] - w[0]) as usize)
+            .max()
+            .unwrap_or(0);
+        let mut padded = vec![0f32; (self.offsets.len() - 1) * n_tokens * self.dim];
+        for (i, w) in self.offsets.windows(2).enumerate() {
+            let (start, end) = (w[0] as usize * self.dim, w[1] as usize * self.dim);
+            let target = i * n_tokens * self.dim;
+            padded[target..target + end - start].copy_from_slice(&self.data[start..end]);
+        }
+        Ok(Tensor::from_vec(
+            padded,
+            (self.offsets.len() - 1, n_tokens, self.dim),
+            &Device::Cpu,
+        )?)
+    }
+
+    /// Builds the `{ data: Float32Array, offsets: Uint32Array, dim }` JavaScript object.
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = Object::new();
+        Reflect::set(&object, &"data".into(), &Float32Array::from(&self.data[..]))?;
+        Reflect::set(
+            &object,
+            &"offsets".into(),
+            &Uint32Array::from(&self.offsets[..]),
+        )?;
+        Reflect::set(&object, &"dim".into(), &(self.dim as u32).into())?;
+        Ok(object.into())
+    }
+
+    /// Reads an object shaped like the output of `to_js`. Plain arrays are accepted in
+    /// place of the typed arrays.
+    fn from_js(value: &JsValue) -> Result<Self, JsValue> {
+        let data = Float32Array::new(&Reflect::get(value, &"data".into())?).to_vec();
+        let offsets = Uint32Array::new(&Reflect::get(value, &"offsets".into())?).to_vec();
+        let dim = Reflect::get(value, &"dim".into())?
+            .as_f64()
+            .ok_or_else(|| JsValue::from_str("Embeddings `dim` must be a number"))?
+            as usize;
+        Ok(Self::new(data, offsets, dim)?)
+    }
+}
+
+/// Converts an optional byte array, which may be `null` or `undefined`.
+fn optional_bytes(value: JsValue, name: &str) -> Result<Option<Vec<u8>>, JsValue> {
+    if value.is_null() || value.is_undefined() {
+        return Ok(None);
+    }
+    serde_wasm_bindgen::from_value(value)
+        .map(Some)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse {}: {}", name, e)))
+}
+
+/// Builds the `{ data: Float32Array, shape: Uint32Array }` JavaScript object of a tensor.
+fn tensor_to_js(tensor: &Tensor) -> Result<JsValue, JsValue> {
+    let data = tensor
+        .flatten_all()
+        .and_then(|t| t.to_vec1::<f32>())
+        .map_err(ColbertError::from)?;
+    let shape: Vec<u32> = tensor.dims().iter().map(|&d| d as u32).collect();
+    let object = Object::new();
+    Reflect::set(&object, &"data".into(), &Float32Array::from(&data[..]))?;
+    Reflect::set(&object, &"shape".into(), &Uint32Array::from(&shape[..]))?;
+    Ok(object.into())
+}
+
+/// Converts tokens to a JavaScript array of arrays of strings.
+fn tokens_to_js(tokens: &[Vec<String>]) -> JsValue {
+    tokens
+        .iter()
+        .map(|sentence| {
+            sentence
+                .iter()
+                .map(|token| JsValue::from_str(token))
+                .collect::<Array>()
+        })
+        .collect::<Array>()
+        .into()
+}
+
 #[wasm_bindgen]
 impl ColBERT {
     /// WASM-compatible constructor.
+    ///
+    /// `dense2_weights` and `dense2_config` are optional and may be `null` or
+    /// `undefined`. Settings come from the config files as with `ColbertBuilder`; use
+    /// `from_bytes_with_options` to pass `modules.json` or override them.
+    #[allow(clippy::too_many_arguments)]
     #[wasm_bindgen(constructor)]
     pub fn from_bytes(
         weights: Vec<u8>,
@@ -22,58 +210,82 @@ impl ColBERT {
         dense2_config: JsValue,
         special_tokens_map: Vec<u8>,
         batch_size: Option<usize>,
+    ) -> Result<ColBERT, JsValue> {
+        Self::from_bytes_with_options(
+            weights,
+            dense_weights,
+            dense2_weights,
+            tokenizer,
+            config,
+            sentence_transformers_config,
+            dense_config,
+            dense2_config,
+            special_tokens_map,
+            batch_size,
+            JsValue::UNDEFINED,
+            JsValue::UNDEFINED,
+        )
+    }
+
+    /// Creates a model like the constructor, with the `modules.json` of the model and
+    /// setting overrides.
+    ///
+    /// `modules` is optional and may be `null` or `undefined`. When given, it must list
+    /// as many Dense modules as were passed. `options` is an optional object overriding
+    /// `query_prefix`, `document_prefix`, `mask_token`, `skiplist_words`,
+    /// `do_query_expansion`, `attend_to_expansion_tokens`, `query_length` and
+    /// `document_length`, which otherwise come from the config files as with
+    /// `ColbertBuilder`, and setting the `pool_factor` of `encode`.
+    #[allow(clippy::too_many_arguments)]
+    #[wasm_bindgen(js_name = "from_bytes_with_options")]
+    pub fn from_bytes_with_options(
+        weights: Vec<u8>,
+        dense_weights: Vec<u8>,
+        dense2_weights: JsValue,
+        tokenizer: Vec<u8>,
+        config: Vec<u8>,
+        sentence_transformers_config: Vec<u8>,
+        dense_config: Vec<u8>,
+        dense2_config: JsValue,
+        special_tokens_map: Vec<u8>,
+        batch_size: Option<usize>,
+        modules: JsValue,
+        options: JsValue,
     ) -> Result<ColBERT, JsValue> {
         console_error_panic_hook::set_once();
 
-        // Convert optional 2_Dense weights from JsValue
-        let dense2_weights_opt: Option<Vec<u8>> =
-            if dense2_weights.is_null() || dense2_weights.is_undefined() {
-                None
-            } else {
-                Some(serde_wasm_bindgen::from_value(dense2_weights).map_err(|e| {
-                    JsValue::from_str(&format!("Failed to parse dense2_weights: {}", e))
-                })?)
-            };
-
-        // Convert optional 2_Dense config from JsValue
-        let dense2_config_opt: Option<Vec<u8>> =
-            if dense2_config.is_null() || dense2_config.is_undefined() {
-                None
-            } else {
-                Some(serde_wasm_bindgen::from_value(dense2_config).map_err(|e| {
-                    JsValue::from_str(&format!("Failed to parse dense2_config: {}", e))
-                })?)
-            };
-
-        let st_config: serde_json::Value =
-            serde_json::from_slice(&sentence_transformers_config).map_err(ColbertError::from)?;
-
-        let special_tokens_map: serde_json::Value =
-            serde_json::from_slice(&special_tokens_map).map_err(ColbertError::from)?;
-
-        let query_prefix = st_config["query_prefix"]
-            .as_str()
-            .unwrap_or("[Q]")
-            .to_string();
-        let document_prefix = st_config["document_prefix"]
-            .as_str()
-            .unwrap_or("[D]")
-            .to_string();
-        let do_query_expansion = st_config["do_query_expansion"].as_bool().unwrap_or(true);
-        let attend_to_expansion_tokens = st_config["attend_to_expansion_tokens"]
-            .as_bool()
-            .unwrap_or(false);
-        let query_length = st_config["query_length"].as_u64().map(|v| v as usize);
-        let document_length = st_config["document_length"].as_u64().map(|v| v as usize);
-
-        let mask_token = special_tokens_map["mask_token"]
-            .as_str()
-            .unwrap_or("[MASK]")
-            .to_string();
+        let dense2_weights_opt = optional_bytes(dense2_weights, "dense2_weights")?;
+        let dense2_config_opt = optional_bytes(dense2_config, "dense2_config")?;
+        let modules_opt = optional_bytes(modules, "modules")?;
+
+        let overrides: ColbertOverrides = if options.is_null() || options.is_undefined() {
+            ColbertOverrides::default()
+        } else {
+            serde_wasm_bindgen::from_value(options)
+                .map_err(|e| JsValue::from_str(&format!("Failed to parse options: {}", e)))?
+        };
+
+        if let Some(modules) = modules_opt {
+            let (_, dense2) = dense_modules(&modules)?;
+            let (expected, found) = (
+                1 + dense2.is_some() as usize,
+                1 + dense2_weights_opt.is_some() as usize,
+            );
+            if expected != found {
+                return Err(ColbertError::DimensionMismatch {
+                    context: "Dense modules listed in modules.json".to_string(),
+                    expected,
+                    found,
+                }
+                .into());
+            }
+        }
+
+        let settings = overrides.resolve(&sentence_transformers_config, &special_tokens_map)?;
 
         let batch_size = Some(batch_size.unwrap_or(32));
 
-        Self::new(
+        let mut model = Self::new_with_skiplist(
             weights,
             dense_weights,
             dense2_weights_opt,
@@ -81,159 +293,592 @@ impl ColBERT {
             config,
             dense_config,
             dense2_config_opt,
-            query_prefix,
-            document_prefix,
-            mask_token,
-            do_query_expansion,
-            attend_to_expansion_tokens,
-            query_length,
-            document_length,
+            settings.query_prefix,
+            settings.document_prefix,
+            settings.mask_token,
+            settings.skiplist_words,
+            settings.do_query_expansion,
+            settings.attend_to_expansion_tokens,
+            settings.query_length,
+            settings.document_length,
             batch_size,
             &Device::Cpu,
-        )
-        .map_err(Into::into)
+        )?;
+        model.pool_factor = settings.pool_factor;
+        Ok(model)
     }
 
     /// WASM-compatible version of the `encode` method.
+    ///
+    /// `progress`, if given, is called after each batch with `{ batches_done,
+    /// total_batches, sentences_done, total_sentences, tokens_processed }`. Returning
+    /// `false` from it cancels encoding, which then throws.
+    ///
+    /// Returns the embeddings without padding rows as
+    /// `{ data: Float32Array, offsets: Uint32Array, dim }`, the rows of sentence `i`
+    /// being the rows `offsets[i]..offsets[i + 1]` of `data`.
     #[wasm_bindgen(js_name = "encode")]
-    pub fn encode_wasm(&mut self, input: JsValue, is_query: bool) -> Result<String, JsValue> {
+    pub fn encode_wasm(
+        &mut self,
+        input: JsValue,
+        is_query: bool,
+        progress: Option<Function>,
+    ) -> Result<JsValue, JsValue> {
         let params: EncodeInput = serde_wasm_bindgen::from_value(input)?;
         // Override model's batch_size if provided in the input
         if let Some(batch_size) = params.batch_size {
             self.batch_size = batch_size;
         }
-        let embeddings_tensor = self.encode(&params.sentences, is_query)?;
-        let embeddings_data = embeddings_tensor
-            .to_vec3::<f32>()
-            .map_err(ColbertError::from)?;
-        let result = EncodeOutput {
-            embeddings: embeddings_data,
-        };
-        // Return as JSON string to avoid serde-wasm-bindgen issues
-        serde_json::to_string(&result)
+        let pool_factor = params.pool_factor.unwrap_or(self.pool_factor);
+
+        let progress = progress.map(|function| {
+            Arc::new(JsProgress {
+                function,
+                cancellation: CancellationToken::new(),
+                error: RefCell::new(None),
+            })
+        });
+        let default_progress = self.progress.take();
+        let default_cancellation = self.cancellation.take();
+        if let Some(progress) = &progress {
+            let callback = progress.clone();
+            self.progress = Some(
+                Arc::new(move |state: &EncodeProgress| callback.report(state)) as ProgressCallback,
+            );
+            self.cancellation = Some(progress.cancellation.clone());
+        }
+        let embeddings_tensor =
+            self.encode_with_pool_factor(&params.sentences, is_query, pool_factor);
+        self.progress = default_progress;
+        self.cancellation = default_cancellation;
+
+        if let Some(err) = progress.and_then(|progress| progress.error.take()) {
+            return Err(err);
+        }
+        FlatEmbeddings::from_padded(&embeddings_tensor?)?.to_js()
+    }
+
+    /// Returns the `ModelInfo` describing the loaded model as a JSON string.
+    #[wasm_bindgen(js_name = "info")]
+    pub fn info_wasm(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.info())
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
 
     /// WASM-compatible version of the `similarity` method.
+    ///
+    /// Returns the scores as `{ data: Float32Array, shape: Uint32Array }` with shape
+    /// `[num_queries, num_documents]`.
     #[wasm_bindgen(js_name = "similarity")]
-    pub fn similarity_wasm(&mut self, input: JsValue) -> Result<String, JsValue> {
+    pub fn similarity_wasm(&mut self, input: JsValue) -> Result<JsValue, JsValue> {
         let params: SimilarityInput = serde_wasm_bindgen::from_value(input)?;
-        let queries_embeddings = self.encode(&params.queries, true)?;
-        let documents_embeddings = self.encode(&params.documents, false)?;
-        let result = self.similarity(&queries_embeddings, &documents_embeddings)?;
-
-        // Return as JSON string to avoid serde-wasm-bindgen issues
-        serde_json::to_string(&result)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+        let scores = self.similarity_from_sides(
+            ScoringSide::Texts(params.queries),
+            ScoringSide::Texts(params.documents),
+        )?;
+        tensor_to_js(&scores)
     }
 
     /// WASM-compatible method to get the raw similarity matrix and tokens.
+    ///
+    /// Returns `{ data: Float32Array, shape: Uint32Array, query_tokens, document_tokens }`,
+    /// `data` being the row-major matrix of shape
+    /// `[num_queries, num_documents, query_length, document_length]`.
     #[wasm_bindgen(js_name = "raw_similarity_matrix")]
-    pub fn raw_similarity_matrix_wasm(&mut self, input: JsValue) -> Result<String, JsValue> {
+    pub fn raw_similarity_matrix_wasm(&mut self, input: JsValue) -> Result<JsValue, JsValue> {
         let params: SimilarityInput = serde_wasm_bindgen::from_value(input)?;
 
-        let (query_ids_tensor, _, _) = self.tokenize(&params.queries, true)?;
-        let query_ids_vec: Vec<Vec<u32>> =
-            query_ids_tensor.to_vec2().map_err(ColbertError::from)?;
-        let query_tokens: Vec<Vec<String>> = query_ids_vec
-            .iter()
-            .map(|ids| {
-                ids.iter()
-                    .map(|&id| self.tokenizer.id_to_token(id).unwrap_or_default())
-                    .collect()
-            })
-            .collect();
-
-        let (doc_ids_tensor, _, _) = self.tokenize(&params.documents, false)?;
-        let doc_ids_vec: Vec<Vec<u32>> = doc_ids_tensor.to_vec2().map_err(ColbertError::from)?;
-        let document_tokens: Vec<Vec<String>> = doc_ids_vec
-            .iter()
-            .map(|ids| {
-                ids.iter()
-                    .map(|&id| self.tokenizer.id_to_token(id).unwrap_or_default())
-                    .collect()
-            })
-            .collect();
+        // The tokens are aligned with the rows and columns of the similarity matrix.
+        let query_tokens = self.tokens(&params.queries, true)?;
+        let document_tokens = self.tokens(&params.documents, false)?;
 
         let queries_embeddings = self.encode(&params.queries, true)?;
-        let documents_embeddings = self.encode(&params.documents, false)?;
+        // Documents are not pooled, to stay aligned with their tokens.
+        let documents_embeddings = self.encode_with_pool_factor(&params.documents, false, 1)?;
 
         let scores_tensor = self.raw_similarity(&queries_embeddings, &documents_embeddings)?;
 
-        let (dim_q, dim_d, _, _) = scores_tensor.dims4().map_err(ColbertError::from)?;
-        let mut scores_vec: Vec<Vec<Vec<Vec<f32>>>> = Vec::with_capacity(dim_q);
-        for i in 0..dim_q {
-            let mut docs_vec: Vec<Vec<Vec<f32>>> = Vec::with_capacity(dim_d);
-            for j in 0..dim_d {
-                let matrix_2d = scores_tensor.i((i, j)).map_err(ColbertError::from)?;
-                let matrix_vec = matrix_2d.to_vec2::<f32>().map_err(ColbertError::from)?;
-                docs_vec.push(matrix_vec);
-            }
-            scores_vec.push(docs_vec);
+        let result = tensor_to_js(&scores_tensor)?;
+        Reflect::set(
+            &result,
+            &"query_tokens".into(),
+            &tokens_to_js(&query_tokens),
+        )?;
+        Reflect::set(
+            &result,
+            &"document_tokens".into(),
+            &tokens_to_js(&document_tokens),
+        )?;
+        Ok(result)
+    }
+
+    /// Scores queries against documents without encoding the sides given as embeddings.
+    ///
+    /// `input` sets either `queries` or `queries_embeddings`, and either `documents` or
+    /// `documents_embeddings`, the embeddings being shaped like the output of `encode`.
+    /// Returns the scores like `similarity`.
+    ///
+    /// Documents given as texts are pooled with the `pool_factor` of the model, as by
+    /// `similarity`, whereas embeddings are scored as given: encode them with the same
+    /// pool factor to get the scores of `similarity`.
+    #[wasm_bindgen(js_name = "similarity_from_embeddings")]
+    pub fn similarity_from_embeddings_wasm(&mut self, input: JsValue) -> Result<JsValue, JsValue> {
+        let queries = ScoringSide::from_js(&input, "queries", "queries_embeddings")?;
+        let documents = ScoringSide::from_js(&input, "documents", "documents_embeddings")?;
+        let scores = self.similarity_from_sides(queries, documents)?;
+        tensor_to_js(&scores)
+    }
+
+    /// Computes the raw similarity matrix without encoding the sides given as embeddings.
+    ///
+    /// Takes the same input as `similarity_from_embeddings` and returns the matrix like
+    /// `raw_similarity_matrix`, with `query_tokens` and `document_tokens` only for the
+    /// sides given as texts. Documents given as texts are not pooled, to stay aligned
+    /// with their tokens. Embeddings are zero-padded to the longest sentence.
+    #[wasm_bindgen(js_name = "raw_similarity_matrix_from_embeddings")]
+    pub fn raw_similarity_matrix_from_embeddings_wasm(
+        &mut self,
+        input: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let queries = ScoringSide::from_js(&input, "queries", "queries_embeddings")?;
+        let documents = ScoringSide::from_js(&input, "documents", "documents_embeddings")?;
+        let (queries_embeddings, queries) = self.side_embeddings(queries, true, 1)?;
+        let (documents_embeddings, documents) = self.side_embeddings(documents, false, 1)?;
+        let (queries_embeddings, documents_embeddings) = (
+            queries_embeddings.to_padded()?,
+            documents_embeddings.to_padded()?,
+        );
+        check_embedding_dims(&queries_embeddings, &documents_embeddings)?;
+
+        let scores_tensor = self.raw_similarity(&queries_embeddings, &documents_embeddings)?;
+        let result = tensor_to_js(&scores_tensor)?;
+        if let Some(queries) = queries {
+            let query_tokens = self.tokens(&queries, true)?;
+            Reflect::set(
+                &result,
+                &"query_tokens".into(),
+                &tokens_to_js(&query_tokens),
+            )?;
+        }
+        if let Some(documents) = documents {
+            let document_tokens = self.tokens(&documents, false)?;
+            Reflect::set(
+                &result,
+                &"document_tokens".into(),
+                &tokens_to_js(&document_tokens),
+            )?;
         }
+        Ok(result)
+    }
+}
 
-        let result = RawSimilarityOutput {
-            similarity_matrix: scores_vec,
-            query_tokens,
-            document_tokens,
+/// One side of a scoring input: texts to encode, or embeddings shaped like the output
+/// of `encode`.
+enum ScoringSide {
+    Texts(Vec<String>),
+    Embeddings(FlatEmbeddings),
+}
+
+impl ScoringSide {
+    /// Reads `texts_key` or `embeddings_key` of `input`, exactly one of which must be set.
+    fn from_js(input: &JsValue, texts_key: &str, embeddings_key: &str) -> Result<Self, JsValue> {
+        let is_set = |value: &JsValue| !value.is_null() && !value.is_undefined();
+        let texts = Reflect::get(input, &texts_key.into())?;
+        let texts = if is_set(&texts) {
+            Some(serde_wasm_bindgen::from_value(texts)?)
+        } else {
+            None
+        };
+        let embeddings = Reflect::get(input, &embeddings_key.into())?;
+        let embeddings = if is_set(&embeddings) {
+            Some(FlatEmbeddings::from_js(&embeddings)?)
+        } else {
+            None
         };
+        Ok(Self::new(texts, embeddings, texts_key, embeddings_key)?)
+    }
 
-        // Return as JSON string to avoid serde-wasm-bindgen issues
-        serde_json::to_string(&result)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    /// Returns the side holding `texts` or `embeddings`, exactly one of which must be set.
+    fn new(
+        texts: Option<Vec<String>>,
+        embeddings: Option<FlatEmbeddings>,
+        texts_key: &str,
+        embeddings_key: &str,
+    ) -> Result<Self, ColbertError> {
+        match (texts, embeddings) {
+            (Some(texts), None) => Ok(Self::Texts(texts)),
+            (None, Some(embeddings)) => Ok(Self::Embeddings(embeddings)),
+            _ => Err(ColbertError::Operation(format!(
+                "Exactly one of `{}` and `{}` must be set",
+                texts_key, embeddings_key
+            ))),
+        }
     }
 }
 
-#[cfg(feature = "wasm")]
-#[derive(serde::Deserialize)]
-struct PoolingInput {
-    embeddings: Vec<Vec<Vec<f32>>>,
-    pool_factor: usize,
+impl ColBERT {
+    /// Returns the embeddings of one side of a scoring input, texts being encoded with
+    /// `pool_factor`, together with the texts when they were encoded.
+    fn side_embeddings(
+        &mut self,
+        side: ScoringSide,
+        is_query: bool,
+        pool_factor: usize,
+    ) -> Result<(FlatEmbeddings, Option<Vec<String>>), ColbertError> {
+        match side {
+            ScoringSide::Embeddings(embeddings) => Ok((embeddings, None)),
+            ScoringSide::Texts(texts) => {
+                let embeddings = self.encode_with_pool_factor(&texts, is_query, pool_factor)?;
+                Ok((FlatEmbeddings::from_padded(&embeddings)?, Some(texts)))
+            },
+        }
+    }
+
+    /// Computes the `[num_queries, num_documents]` MaxSim scores, documents given as
+    /// texts being pooled with the `pool_factor` of the model.
+    fn similarity_from_sides(
+        &mut self,
+        queries: ScoringSide,
+        documents: ScoringSide,
+    ) -> Result<Tensor, ColbertError> {
+        let (queries, _) = self.side_embeddings(queries, true, 1)?;
+        let (documents, _) = self.side_embeddings(documents, false, self.pool_factor)?;
+        maxsim(&queries, &documents)
+    }
+}
+
+/// A search result returned to JavaScript by `EmbeddingCollection::search`.
+#[derive(serde::Serialize)]
+struct SearchHit<'a> {
+    index: usize,
+    id: &'a str,
+    score: f32,
+    metadata: &'a serde_json::Value,
+}
+
+#[wasm_bindgen]
+impl EmbeddingCollection {
+    /// Loads a collection from the bytes of a file written by `EmbeddingCollection::save`.
+    #[wasm_bindgen(js_name = "from_bytes")]
+    pub fn from_bytes_wasm(buffer: &[u8]) -> Result<EmbeddingCollection, JsValue> {
+        console_error_panic_hook::set_once();
+        Self::from_bytes(buffer).map_err(Into::into)
+    }
+
+    /// Returns the number of documents in the collection.
+    #[wasm_bindgen(getter, js_name = "length")]
+    pub fn length_wasm(&self) -> usize {
+        self.len()
+    }
+
+    /// Encodes `query` with `model` and returns the `k` documents with the highest
+    /// MaxSim scores as `{ index, id, score, metadata }` objects, sorted by decreasing
+    /// score.
+    #[wasm_bindgen(js_name = "search")]
+    pub fn search_wasm(
+        &self,
+        model: &mut ColBERT,
+        query: String,
+        k: usize,
+    ) -> Result<JsValue, JsValue> {
+        let query_embeddings = model.encode(&[query], true)?;
+        let ranked = self.search(&query_embeddings, k)?;
+
+        let hits: Vec<SearchHit> = ranked[0]
+            .iter()
+            .map(|document| SearchHit {
+                index: document.index,
+                id: &self.ids()[document.index],
+                score: document.score,
+                metadata: self
+                    .metadata(document.index)
+                    .unwrap_or(&serde_json::Value::Null),
+            })
+            .collect();
+        hits.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(Into::into)
+    }
 }
 
 /// WASM-compatible version of the `hierarchical_pooling` function.
+///
+/// Takes embeddings shaped like the output of `encode` and pools each sentence
+/// separately, returning embeddings in the same format.
 #[cfg(feature = "wasm")]
 #[wasm_bindgen(js_name = hierarchical_pooling)]
-pub fn hierarchical_pooling_wasm(input: JsValue) -> Result<String, JsValue> {
+pub fn hierarchical_pooling_wasm(
+    embeddings: JsValue,
+    pool_factor: usize,
+) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    let embeddings = FlatEmbeddings::from_js(&embeddings)?;
+
+    let pooled = pool_documents(&embeddings.documents()?, pool_factor)?;
+
+    FlatEmbeddings::from_documents(&pooled, embeddings.dim)?.to_js()
+}
+
+/// Computes the MaxSim scores of query and document embeddings shaped like the output
+/// of `encode`, returning `{ data: Float32Array, shape: Uint32Array }` with shape
+/// `[num_queries, num_documents]`.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen(js_name = maxsim)]
+pub fn maxsim_wasm(
+    queries_embeddings: JsValue,
+    documents_embeddings: JsValue,
+) -> Result<JsValue, JsValue> {
     console_error_panic_hook::set_once();
-    let params: PoolingInput = serde_wasm_bindgen::from_value(input)?;
+    let queries = FlatEmbeddings::from_js(&queries_embeddings)?;
+    let documents = FlatEmbeddings::from_js(&documents_embeddings)?;
+    let scores = maxsim(&queries, &documents)?;
+    tensor_to_js(&scores)
+}
 
-    if params.embeddings.is_empty() {
-        let result = EncodeOutput { embeddings: vec![] };
-        return serde_json::to_string(&result)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)));
+/// Checks that query and document embeddings have the same dimension.
+fn check_embedding_dims(queries: &Tensor, documents: &Tensor) -> Result<(), ColbertError> {
+    let (expected, found) = (queries.dim(2)?, documents.dim(2)?);
+    if expected != found {
+        return Err(ColbertError::DimensionMismatch {
+            context: "query and document embedding dimensions".to_string(),
+            expected,
+            found,
+        });
     }
+    Ok(())
+}
 
-    let batch_size = params.embeddings.len();
-    let n_tokens = params.embeddings[0].len();
+/// Computes the `[num_queries, num_documents]` MaxSim scores, each document being scored
+/// over its own rows only, as with `colbert_maxsim` in the C API.
+///
+/// Padding query rows are zero and add nothing to the sum, whereas a padding document
+/// row would win the maximum of a query token whose similarities are all negative.
+fn maxsim(queries: &FlatEmbeddings, documents: &FlatEmbeddings) -> Result<Tensor, ColbertError> {
+    if queries.dim != documents.dim {
+        return Err(ColbertError::DimensionMismatch {
+            context: "query and document embedding dimensions".to_string(),
+            expected: queries.dim,
+            found: documents.dim,
+        });
+    }
+    let queries = queries.to_padded()?;
+    let num_queries = queries.dim(0)?;
+    let scores = documents
+        .documents()?
+        .iter()
+        .map(|document| {
+            if document.dim(0)? == 0 {
+                return Tensor::zeros(num_queries, DType::F32, &Device::Cpu);
+            }
+            queries.broadcast_matmul(&document.t()?)?.max(2)?.sum(1)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if scores.is_empty() {
+        return Ok(Tensor::zeros((num_queries, 0), DType::F32, &Device::Cpu)?);
+    }
+    Ok(Tensor::stack(&scores, 1)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Architecture};
 
-    if n_tokens == 0 {
-        let result = EncodeOutput {
-            embeddings: params.embeddings,
+    fn embeddings(lengths: &[usize], dim: usize) -> Vec<Tensor> {
+        let mut value = 0f32;
+        lengths
+            .iter()
+            .map(|&length| {
+                let data: Vec<f32> = (0..length * dim)
+                    .map(|_| {
+                        value += 1.0;
+                        value
+                    })
+                    .collect();
+                Tensor::from_vec(data, (length, dim), &Device::Cpu).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flat_embeddings_offsets() {
+        assert!(FlatEmbeddings::new(vec![0.0; 6], vec![0, 1, 1, 3], 2).is_ok());
+        assert!(FlatEmbeddings::new(vec![], vec![0], 2).is_ok());
+        for (data, offsets, dim) in [
+            (vec![0.0; 6], vec![1, 3], 2),
+            (vec![0.0; 6], vec![0, 2, 1, 3], 2),
+            (vec![0.0; 6], vec![0, 2], 2),
+            (vec![0.0; 6], vec![0, 4], 2),
+            (vec![0.0; 6], vec![], 2),
+            (vec![], vec![0, 0], 0),
+        ] {
+            assert!(matches!(
+                FlatEmbeddings::new(data, offsets, dim),
+                Err(ColbertError::Operation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn flat_embeddings_round_trip() {
+        let documents = embeddings(&[2, 0, 3], 2);
+        let flat = FlatEmbeddings::from_documents(&documents, 2).unwrap();
+        assert_eq!(flat.offsets, vec![0, 2, 2, 5]);
+        assert_eq!(flat.data.len(), 10);
+
+        let padded = flat.to_padded().unwrap();
+        assert_eq!(padded.dims(), &[3, 3, 2]);
+        let padded = padded.to_vec3::<f32>().unwrap();
+        assert_eq!(padded[0][2], vec![0.0, 0.0]);
+        assert_eq!(padded[1], vec![vec![0.0, 0.0]; 3]);
+        assert_eq!(padded[2][2], vec![9.0, 10.0]);
+
+        for (document, expected) in flat.documents().unwrap().iter().zip(&documents) {
+            assert_eq!(
+                document.to_vec2::<f32>().unwrap(),
+                expected.to_vec2::<f32>().unwrap()
+            );
+        }
+
+        // Non-empty sentences survive padding and unpadding.
+        let flat = FlatEmbeddings::from_documents(&embeddings(&[2, 3], 2), 2).unwrap();
+        let unpadded = FlatEmbeddings::from_padded(&flat.to_padded().unwrap()).unwrap();
+        assert_eq!(unpadded.offsets, flat.offsets);
+        assert_eq!(unpadded.data, flat.data);
+    }
+
+    #[test]
+    fn maxsim_ignores_padding() {
+        let queries =
+            FlatEmbeddings::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 0.0], vec![0, 2, 3], 2).unwrap();
+        // The first document is shorter and all its similarities are negative.
+        let documents = FlatEmbeddings::new(
+            vec![-1.0, 0.0, -0.5, -0.5, 0.5, 0.5, 1.0, 0.0],
+            vec![0, 1, 4],
+            2,
+        )
+        .unwrap();
+
+        let scores = maxsim(&queries, &documents)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        assert_eq!(scores, vec![vec![-1.0, 1.5], vec![-1.0, 1.0]]);
+
+        // Each score matches the query scored against the document alone.
+        for (query, row) in queries.documents().unwrap().iter().zip(&scores) {
+            for (document, score) in documents.documents().unwrap().iter().zip(row) {
+                let expected = query
+                    .matmul(&document.t().unwrap())
+                    .unwrap()
+                    .max(1)
+                    .unwrap()
+                    .sum_all()
+                    .unwrap()
+                    .to_scalar::<f32>()
+                    .unwrap();
+                assert_eq!(*score, expected);
+            }
+        }
+
+        let other = FlatEmbeddings::new(vec![1.0; 3], vec![0, 1], 3).unwrap();
+        assert!(matches!(
+            maxsim(&queries, &other),
+            Err(ColbertError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn similarity_from_embeddings() {
+        let path = fixtures::write_colbert("wasm-similarity", Architecture::Bert, false).unwrap();
+        let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+            .with_pool_factor(2)
+            .try_into()
+            .unwrap();
+        let queries = fixtures::sentences(&["capital of france", "a star"]);
+        let documents = fixtures::sentences(&[
+            "paris is the capital of france, this is a test",
+            "the sun is a star",
+        ]);
+        let texts = || {
+            (
+                ScoringSide::Texts(queries.clone()),
+                ScoringSide::Texts(documents.clone()),
+            )
         };
-        return serde_json::to_string(&result)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)));
-    }
-    let embedding_dim = params.embeddings[0][0].len();
-
-    let flat_embeddings: Vec<f32> = params.embeddings.into_iter().flatten().flatten().collect();
-    let documents_embeddings = Tensor::from_vec(
-        flat_embeddings,
-        (batch_size, n_tokens, embedding_dim),
-        &Device::Cpu,
-    )
-    .map_err(ColbertError::from)?;
-
-    // Call the original Rust function, mapping the anyhow::Error to a ColbertError.
-    let pooled_tensor = hierarchical_pooling(&documents_embeddings, params.pool_factor)
-        .map_err(|e| ColbertError::Operation(e.to_string()))?;
-
-    let embeddings_data = pooled_tensor.to_vec3::<f32>().map_err(ColbertError::from)?;
-    let result = EncodeOutput {
-        embeddings: embeddings_data,
-    };
-
-    // Return as JSON string to avoid serde-wasm-bindgen issues
-    serde_json::to_string(&result)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+        let (query_texts, document_texts) = texts();
+        let expected = model
+            .similarity_from_sides(query_texts, document_texts)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+
+        // The embeddings returned by `encode`, documents being pooled by default.
+        let pool_factor = model.pool_factor;
+        let queries_embeddings =
+            FlatEmbeddings::from_padded(&model.encode_with_pool_factor(&queries, true, 1).unwrap())
+                .unwrap();
+        let documents_embeddings = FlatEmbeddings::from_padded(
+            &model
+                .encode_with_pool_factor(&documents, false, pool_factor)
+                .unwrap(),
+        )
+        .unwrap();
+
+        let (query_texts, document_texts) = texts();
+        for (queries, documents) in [
+            (
+                ScoringSide::Embeddings(queries_embeddings.clone()),
+                ScoringSide::Embeddings(documents_embeddings.clone()),
+            ),
+            (
+                query_texts,
+                ScoringSide::Embeddings(documents_embeddings.clone()),
+            ),
+            (ScoringSide::Embeddings(queries_embeddings), document_texts),
+        ] {
+            let scores = model
+                .similarity_from_sides(queries, documents)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap();
+            for (row, expected_row) in scores.iter().zip(&expected) {
+                for (score, expected) in row.iter().zip(expected_row) {
+                    assert!((score - expected).abs() < 1e-5);
+                }
+            }
+        }
+
+        // Unpooled document embeddings give different scores.
+        let unpooled = FlatEmbeddings::from_padded(
+            &model.encode_with_pool_factor(&documents, false, 1).unwrap(),
+        )
+        .unwrap();
+        assert_ne!(unpooled.offsets, documents_embeddings.offsets);
+    }
+
+    #[test]
+    fn scoring_side_requires_one_input() {
+        let embeddings = FlatEmbeddings::new(vec![1.0, 0.0], vec![0, 1], 2).unwrap();
+        let texts = || Some(vec!["paris".to_string()]);
+        assert!(matches!(
+            ScoringSide::new(texts(), None, "queries", "queries_embeddings"),
+            Ok(ScoringSide::Texts(_))
+        ));
+        assert!(matches!(
+            ScoringSide::new(
+                None,
+                Some(embeddings.clone()),
+                "queries",
+                "queries_embeddings"
+            ),
+            Ok(ScoringSide::Embeddings(_))
+        ));
+        for (texts, embeddings) in [(texts(), Some(embeddings)), (None, None)] {
+            assert!(matches!(
+                ScoringSide::new(texts, embeddings, "queries", "queries_embeddings"),
+                Err(ColbertError::Operation(message))
+                    if message == "Exactly one of `queries` and `queries_embeddings` must be set"
+            ));
+        }
+    }
 }
@@ -0,0 +1,138 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::Tensor;
+use common::{sentences, Architecture, EMBEDDING_DIM, QUERY_LENGTH};
+use pylate_rs::{ColBERT, EmbeddingCache};
+use std::fs;
+
+/// Checks that cached embeddings match fresh ones and that only misses are encoded.
+#[test]
+fn local_cache_test() -> Result<()> {
+    let path = common::write_colbert("cache", Architecture::Bert, false)?;
+    let cache = EmbeddingCache::new(2).with_directory(path.join("cache"))?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_cache(cache.clone())
+        .try_into()?;
+    let mut reference: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    let max_difference = |a: &Tensor, b: &Tensor| -> Result<f32> {
+        Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+    };
+
+    let documents = sentences(&["paris is the capital of france", "the sun is a star"]);
+    let first = model.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+    // Repeated sentences are encoded once and the output keeps the layout of `encode`.
+    let batch = sentences(&[
+        "the sun is a star",
+        "berlin is the capital of germany",
+        "the sun is a star",
+    ]);
+    let cached = model.encode(&batch, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+    let expected = reference.encode(&batch, false)?;
+    assert_eq!(cached.dims(), expected.dims());
+    assert!(max_difference(&cached, &expected)? < 1e-4);
+    assert_eq!(cache.len(), 2);
+
+    let queries = sentences(&["what is the capital of france"]);
+    let cached_queries = model.encode(&queries, true)?;
+    assert_eq!(
+        model.encode(&queries, true)?.dims(),
+        &[1, QUERY_LENGTH, EMBEDDING_DIM]
+    );
+    assert_eq!((cache.hits(), cache.misses()), (2, 4));
+    assert!(max_difference(&cached_queries, &reference.encode(&queries, true)?)? < 1e-4);
+
+    // Entries evicted from memory, or written by another process, are read from disk.
+    let disk_cache = EmbeddingCache::new(0).with_directory(path.join("cache"))?;
+    model.set_cache(Some(disk_cache.clone()));
+    let from_disk = model.encode(&documents[..1], false)?;
+    assert_eq!((disk_cache.hits(), disk_cache.misses()), (1, 0));
+    assert!(disk_cache.is_empty());
+    let length = from_disk.dim(1)?;
+    assert!(max_difference(&from_disk.get(0)?, &first.get(0)?.narrow(0, 0, length)?)? < 1e-6);
+
+    // Settings that change the embeddings, or another revision, change the keys.
+    let mut prefixed: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_document_prefix("[Q] ".to_string())
+        .with_cache(disk_cache.clone())
+        .try_into()?;
+    prefixed.encode(&documents[..1], false)?;
+    model.encode_with_pool_factor(&documents[..1], false, 2)?;
+    model.set_cache(Some(disk_cache.clone().with_revision("fine-tuned")));
+    model.encode(&documents[..1], false)?;
+    assert_eq!((disk_cache.hits(), disk_cache.misses()), (1, 3));
+    Ok(())
+}
+
+/// Checks that new weights behind the same model directory do not hit older entries.
+#[test]
+fn local_cache_revision_test() -> Result<()> {
+    let path = common::write_colbert("cache-revision", Architecture::Bert, false)?;
+    let cache =
+        EmbeddingCache::new(0).with_directory(common::fixture_dir("cache-revision-entries"))?;
+    let documents = sentences(&["paris is the capital of france"]);
+    let load = |cache: &EmbeddingCache| -> Result<ColBERT> {
+        Ok(ColBERT::from(path.to_str().unwrap())
+            .with_cache(cache.clone())
+            .try_into()?)
+    };
+
+    load(&cache)?.encode(&documents, false)?;
+    load(&cache)?.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    // The same directory with new weights, as after saving a fine-tuned model.
+    common::write_colbert("cache-revision", Architecture::Bert, false)?;
+    load(&cache)?.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+    // An explicit revision takes precedence over the weights.
+    let pinned = cache.clone().with_revision("v1");
+    load(&pinned)?.encode(&documents, false)?;
+    common::write_colbert("cache-revision", Architecture::Bert, false)?;
+    load(&pinned)?.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (2, 3));
+    Ok(())
+}
+
+/// Checks that corrupt or truncated disk entries count as misses and are rewritten.
+#[test]
+fn local_cache_corrupt_entry_test() -> Result<()> {
+    let path = common::write_colbert("cache-corrupt", Architecture::Bert, false)?;
+    let directory = path.join("cache");
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_cache(EmbeddingCache::new(0).with_directory(&directory)?)
+        .try_into()?;
+    let documents = sentences(&["paris is the capital of france", "the sun is a star"]);
+    let expected = model.encode(&documents, false)?;
+
+    let mut entries: Vec<_> = fs::read_dir(&directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    assert_eq!(entries.len(), 2);
+    fs::write(&entries[0], b"not a safetensors file")?;
+    let bytes = fs::read(&entries[1])?;
+    fs::write(&entries[1], &bytes[..bytes.len() / 2])?;
+
+    let cache = EmbeddingCache::new(0).with_directory(&directory)?;
+    model.set_cache(Some(cache.clone()));
+    let encoded = model.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    let difference = (encoded - &expected)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    assert!(difference < 1e-6);
+
+    // The entries were written again.
+    let cache = EmbeddingCache::new(0).with_directory(&directory)?;
+    model.set_cache(Some(cache.clone()));
+    model.encode(&documents, false)?;
+    assert_eq!((cache.hits(), cache.misses()), (2, 0));
+    Ok(())
+}
@@ -0,0 +1,110 @@
+#[path = "../cli/corpus.rs"]
+mod corpus;
+
+mod common;
+
+use anyhow::Result;
+use corpus::read_records;
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Writes `contents` to the file `name` of `dir`.
+fn write_input(dir: &Path, name: &str, contents: &str) -> Result<PathBuf> {
+    let path = dir.join(name);
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Checks that JSONL records keep their extra fields as metadata and that fallback ids
+/// number the records, not the lines.
+#[test]
+fn local_read_jsonl_test() -> Result<()> {
+    let dir = common::fixture_dir("cli-jsonl");
+    let path = write_input(
+        &dir,
+        "corpus.jsonl",
+        "\n{\"id\": \"a\", \"text\": \"paris\", \"title\": \"France\"}\n\n{\"text\": \"berlin\"}\n{\"id\": 7, \"text\": \"rome\"}\n",
+    )?;
+    let records = read_records(&path, "id", "text")?;
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    let texts: Vec<&str> = records.iter().map(|record| record.text.as_str()).collect();
+    assert_eq!(ids, ["a", "1", "7"]);
+    assert_eq!(texts, ["paris", "berlin", "rome"]);
+    assert_eq!(records[0].metadata, json!({ "title": "France" }));
+    assert_eq!(records[1].metadata, serde_json::Value::Null);
+
+    // Custom field names.
+    let path = write_input(
+        &dir,
+        "fields.jsonl",
+        "{\"_id\": \"x\", \"body\": \"paris\"}\n",
+    )?;
+    let records = read_records(&path, "_id", "body")?;
+    assert_eq!(
+        (records[0].id.as_str(), records[0].text.as_str()),
+        ("x", "paris")
+    );
+
+    // Errors name the line of the file.
+    let path = write_input(
+        &dir,
+        "invalid.jsonl",
+        "{\"text\": \"paris\"}\n\n{\"id\": \"b\"}\n",
+    )?;
+    let error = read_records(&path, "id", "text").err().unwrap();
+    assert!(error.to_string().starts_with("Invalid record on line 3"));
+    Ok(())
+}
+
+/// Checks that JSON files are read as an array of records.
+#[test]
+fn local_read_json_test() -> Result<()> {
+    let dir = common::fixture_dir("cli-json");
+    let path = write_input(
+        &dir,
+        "corpus.json",
+        r#"[{"id": "a", "text": "paris"}, {"text": "berlin", "lang": "de"}]"#,
+    )?;
+    let records = read_records(&path, "id", "text")?;
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    assert_eq!(ids, ["a", "1"]);
+    assert_eq!(records[1].text, "berlin");
+    assert_eq!(records[1].metadata, json!({ "lang": "de" }));
+
+    // A JSON file holding one object per line is not a JSON array.
+    let path = write_input(
+        &dir,
+        "lines.json",
+        "{\"id\": \"a\", \"text\": \"paris\"}\n{\"id\": \"b\", \"text\": \"berlin\"}\n",
+    )?;
+    assert!(read_records(&path, "id", "text").is_err());
+
+    let path = write_input(&dir, "invalid.json", r#"[{"text": "paris"}, {"id": "b"}]"#)?;
+    let error = read_records(&path, "id", "text").err().unwrap();
+    assert!(error.to_string().starts_with("Invalid record 2"));
+    Ok(())
+}
+
+/// Checks that TSV lines are split into an id and a text, with fallback ids numbering
+/// the records.
+#[test]
+fn local_read_tsv_test() -> Result<()> {
+    let dir = common::fixture_dir("cli-tsv");
+    let path = write_input(&dir, "corpus.tsv", "a\tparis\textra\n\nberlin\nc\trome\n")?;
+    let records = read_records(&path, "id", "text")?;
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    let texts: Vec<&str> = records.iter().map(|record| record.text.as_str()).collect();
+    assert_eq!(ids, ["a", "1", "c"]);
+    assert_eq!(texts, ["paris", "berlin", "rome"]);
+    assert!(records
+        .iter()
+        .all(|record| record.metadata == serde_json::Value::Null));
+
+    let path = write_input(&dir, "corpus.csv", "a,paris\n")?;
+    let error = read_records(&path, "id", "text").err().unwrap();
+    assert!(error.to_string().starts_with("Unsupported input format"));
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! Fixture generator writing tiny, randomly initialized ColBERT models to disk.
+//!
+//! The fixtures follow the PyLate directory layout so that they can be loaded
+//! through `ColbertBuilder` without any network access.
+
+#![allow(dead_code)]
+
+use anyhow::Result;
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::bert::{BertForMaskedLM, BertModel};
+use pylate_rs::{
+    cross_encoder::BertForSequenceClassification,
+    modernbert::{ModernBert, ModernBertForMaskedLM, ModernBertForSequenceClassification},
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tokenizers::{
+    models::wordlevel::WordLevel, normalizers::BertNormalizer,
+    pre_tokenizers::bert::BertPreTokenizer, processors::bert::BertProcessing, AddedToken,
+    Tokenizer,
+};
+
+/// The hidden size of the fixture backbones.
+pub const HIDDEN_SIZE: usize = 16;
+/// The output dimension of the fixture `1_Dense` layer.
+pub const EMBEDDING_DIM: usize = 8;
+/// The output dimension of the optional fixture `2_Dense` layer.
+pub const EMBEDDING_DIM_2: usize = 4;
+/// The query length written to `config_sentence_transformers.json`.
+pub const QUERY_LENGTH: usize = 12;
+/// The document length written to `config_sentence_transformers.json`.
+pub const DOCUMENT_LENGTH: usize = 24;
+
+const SPECIAL_TOKENS: [&str; 7] = ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "[MASK]", "[Q]", "[D]"];
+/// The regular words of the fixture vocabulary.
+pub const WORDS: [&str; 21] = [
+    "what", "is", "the", "capital", "of", "france", "paris", "berlin", "germany", "how", "big",
+    "sun", "a", "star", "this", "test", ".", ",", "?", "!", "-",
+];
+
+/// The backbone architecture of a fixture model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Architecture {
+    Bert,
+    ModernBert,
+}
+
+/// Collects string literals into the owned sentences taken by `encode`.
+pub fn sentences(texts: &[&str]) -> Vec<String> {
+    texts.iter().map(|text| text.to_string()).collect()
+}
+
+/// Returns a fresh directory under Cargo's temporary directory for integration tests.
+///
+/// Examples, which have no such directory, write under `target/tmp` instead.
+pub fn fixture_dir(name: &str) -> PathBuf {
+    let tmp_dir = option_env!("CARGO_TARGET_TMPDIR")
+        .unwrap_or(concat!(env!("CARGO_MANIFEST_DIR"), "/target/tmp"));
+    let dir = Path::new(tmp_dir).join("fixtures").join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).expect("failed to clean fixture directory");
+    }
+    fs::create_dir_all(&dir).expect("failed to create fixture directory");
+    dir
+}
+
+/// Writes a complete PyLate ColBERT directory named `name` and returns its path.
+pub fn write_colbert(name: &str, architecture: Architecture, with_dense2: bool) -> Result<PathBuf> {
+    let dir = fixture_dir(name);
+    let vocab_size = write_tokenizer(&dir)?;
+
+    let config = match architecture {
+        Architecture::Bert => bert_config(vocab_size, "BertModel"),
+        Architecture::ModernBert => modernbert_config(vocab_size, "ModernBertModel"),
+    };
+    fs::write(dir.join("config.json"), serde_json::to_vec_pretty(&config)?)?;
+    write_weights(&dir, &config)?;
+
+    write_dense(&dir.join("1_Dense"), HIDDEN_SIZE, EMBEDDING_DIM)?;
+    if with_dense2 {
+        write_dense(&dir.join("2_Dense"), EMBEDDING_DIM, EMBEDDING_DIM_2)?;
+    }
+
+    let st_config = json!({
+        "query_prefix": "[Q] ",
+        "document_prefix": "[D] ",
+        "query_length": QUERY_LENGTH,
+        "document_length": DOCUMENT_LENGTH,
+        "do_query_expansion": true,
+        "attend_to_expansion_tokens": false,
+    });
+    fs::write(
+        dir.join("config_sentence_transformers.json"),
+        serde_json::to_vec_pretty(&st_config)?,
+    )?;
+    fs::write(
+        dir.join("special_tokens_map.json"),
+        serde_json::to_vec_pretty(&json!({ "mask_token": "[MASK]" }))?,
+    )?;
+
+    Ok(dir)
+}
+
+/// Writes a word-level tokenizer with BERT-style special tokens and returns its vocabulary size.
+pub fn write_tokenizer(dir: &Path) -> Result<usize> {
+    let vocab: HashMap<String, u32> = SPECIAL_TOKENS
+        .iter()
+        .chain(WORDS.iter())
+        .enumerate()
+        .map(|(id, token)| (token.to_string(), id as u32))
+        .collect();
+    let vocab_size = vocab.len();
+
+    let model = WordLevel::builder()
+        .vocab(vocab.into_iter().collect())
+        .unk_token("[UNK]".to_string())
+        .build()
+        .map_err(anyhow::Error::msg)?;
+    let mut tokenizer = Tokenizer::new(model);
+    tokenizer
+        .with_normalizer(Some(BertNormalizer::default()))
+        .with_pre_tokenizer(Some(BertPreTokenizer))
+        .with_post_processor(Some(BertProcessing::new(
+            ("[SEP]".to_string(), 3),
+            ("[CLS]".to_string(), 2),
+        )));
+    let special_tokens: Vec<AddedToken> = SPECIAL_TOKENS
+        .iter()
+        .map(|token| AddedToken::from(token.to_string(), true))
+        .collect();
+    tokenizer.add_special_tokens(&special_tokens);
+    tokenizer
+        .save(dir.join("tokenizer.json"), false)
+        .map_err(anyhow::Error::msg)?;
+
+    Ok(vocab_size)
+}
+
+/// Returns a tiny BERT `config.json` for the given architecture name.
+pub fn bert_config(vocab_size: usize, architecture: &str) -> Value {
+    json!({
+        "architectures": [architecture],
+        "model_type": "bert",
+        "vocab_size": vocab_size,
+        "hidden_size": HIDDEN_SIZE,
+        "num_hidden_layers": 2,
+        "num_attention_heads": 2,
+        "intermediate_size": 32,
+        "hidden_act": "gelu",
+        "hidden_dropout_prob": 0.0,
+        "max_position_embeddings": 64,
+        "type_vocab_size": 2,
+        "initializer_range": 0.02,
+        "layer_norm_eps": 1e-12,
+        "pad_token_id": 0,
+        "classifier_dropout": null,
+    })
+}
+
+/// Returns a tiny ModernBERT `config.json` for the given architecture name.
+pub fn modernbert_config(vocab_size: usize, architecture: &str) -> Value {
+    json!({
+        "architectures": [architecture],
+        "model_type": "modernbert",
+        "vocab_size": vocab_size,
+        "hidden_size": HIDDEN_SIZE,
+        "num_hidden_layers": 2,
+        "num_attention_heads": 2,
+        "intermediate_size": 32,
+        "max_position_embeddings": 64,
+        "layer_norm_eps": 1e-5,
+        "pad_token_id": 0,
+        "global_attn_every_n_layers": 2,
+        "global_rope_theta": 160000.0,
+        "local_attention": 8,
+        "local_rope_theta": 10000.0,
+    })
+}
+
+/// Writes randomly initialized weights for the architecture named in `config` to
+/// `model.safetensors`.
+pub fn write_weights(dir: &Path, config: &Value) -> Result<()> {
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    match config["architectures"][0].as_str() {
+        Some("BertModel") => {
+            BertModel::load(vb, &serde_json::from_value(config.clone())?)?;
+        },
+        Some("BertForSequenceClassification") => {
+            let num_labels = config["id2label"]
+                .as_object()
+                .map_or(1, |labels| labels.len());
+            BertForSequenceClassification::load(
+                vb,
+                &serde_json::from_value(config.clone())?,
+                num_labels,
+            )?;
+        },
+        Some("BertForMaskedLM") => {
+            BertForMaskedLM::load(vb, &serde_json::from_value(config.clone())?)?;
+        },
+        Some("ModernBertModel") => {
+            ModernBert::load(vb, &serde_json::from_value(config.clone())?)?;
+        },
+        Some("ModernBertForSequenceClassification") => {
+            ModernBertForSequenceClassification::load(
+                vb,
+                &serde_json::from_value(config.clone())?,
+            )?;
+        },
+        Some("ModernBertForMaskedLM") => {
+            ModernBertForMaskedLM::load(vb, &serde_json::from_value(config.clone())?)?;
+        },
+        architecture => anyhow::bail!("Unsupported fixture architecture: {:?}", architecture),
+    }
+    varmap.save(dir.join("model.safetensors"))?;
+    Ok(())
+}
+
+/// Writes a sequence-classification checkpoint with `num_labels` labels named `name` and
+/// returns its path.
+pub fn write_cross_encoder(
+    name: &str,
+    architecture: Architecture,
+    num_labels: usize,
+) -> Result<PathBuf> {
+    let dir = fixture_dir(name);
+    let vocab_size = write_tokenizer(&dir)?;
+
+    let mut config = match architecture {
+        Architecture::Bert => bert_config(vocab_size, "BertForSequenceClassification"),
+        Architecture::ModernBert => {
+            let mut config = modernbert_config(vocab_size, "ModernBertForSequenceClassification");
+            config["classifier_pooling"] = json!("mean");
+            config
+        },
+    };
+    config["id2label"] = (0..num_labels)
+        .map(|label| (label.to_string(), json!(format!("LABEL_{label}"))))
+        .collect();
+    config["label2id"] = (0..num_labels)
+        .map(|label| (format!("LABEL_{label}"), json!(label)))
+        .collect();
+    fs::write(dir.join("config.json"), serde_json::to_vec_pretty(&config)?)?;
+    write_weights(&dir, &config)?;
+
+    Ok(dir)
+}
+
+/// Writes a masked language model checkpoint named `name` and returns its path.
+pub fn write_masked_lm(name: &str, architecture: Architecture) -> Result<PathBuf> {
+    let dir = fixture_dir(name);
+    let vocab_size = write_tokenizer(&dir)?;
+
+    let config = match architecture {
+        Architecture::Bert => bert_config(vocab_size, "BertForMaskedLM"),
+        Architecture::ModernBert => modernbert_config(vocab_size, "ModernBertForMaskedLM"),
+    };
+    fs::write(dir.join("config.json"), serde_json::to_vec_pretty(&config)?)?;
+    write_weights(&dir, &config)?;
+
+    Ok(dir)
+}
+
+/// Writes a randomly initialized Dense module without bias.
+pub fn write_dense(dir: &Path, in_features: usize, out_features: usize) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    candle_nn::linear_no_bias(in_features, out_features, vb.pp("linear"))?;
+    varmap.save(dir.join("model.safetensors"))?;
+
+    let config = json!({
+        "in_features": in_features,
+        "out_features": out_features,
+        "bias": false,
+        "activation_function": "torch.nn.modules.linear.Identity",
+    });
+    fs::write(dir.join("config.json"), serde_json::to_vec_pretty(&config)?)?;
+    Ok(())
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use anyhow::Result;
+use common::{sentences, Architecture};
+use pylate_rs::{ColbertError, CrossEncoder};
+
+/// Checks that the cross-encoder scores and ranks every document.
+#[test]
+fn local_cross_encoder_test() -> Result<()> {
+    for (name, architecture) in [
+        ("cross-encoder-bert", Architecture::Bert),
+        ("cross-encoder-modernbert", Architecture::ModernBert),
+    ] {
+        let path = common::write_cross_encoder(name, architecture, 1)?;
+        let mut model: CrossEncoder = CrossEncoder::from(path.to_str().unwrap())
+            .with_batch_size(2)
+            .try_into()?;
+
+        let documents = sentences(&[
+            "paris is the capital of france",
+            "berlin is the capital of germany",
+            "the sun is a star",
+        ]);
+        let ranked = model.rank("what is the capital of france", &documents)?;
+        assert_eq!(ranked.len(), 3);
+        assert!(ranked.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        let mut indices: Vec<usize> = ranked.iter().map(|document| document.index).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2]);
+
+        // Scores do not depend on the batch a pair is padded with.
+        for document in &ranked {
+            let pair = (
+                "what is the capital of france".to_string(),
+                documents[document.index].clone(),
+            );
+            let score = model.predict(std::slice::from_ref(&pair))?[0];
+            assert!((score - document.score).abs() < 1e-4);
+            // A single-label head scores pairs with its raw logit.
+            assert!((model.predict_logits(&[pair])?[0][0] - score).abs() < 1e-4);
+        }
+        assert!(matches!(
+            model.rank("what is the capital of france", &[]),
+            Err(ColbertError::EmptyInput)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that multi-label heads expose every logit and score the chosen label.
+#[test]
+fn local_cross_encoder_labels_test() -> Result<()> {
+    for (name, architecture) in [
+        ("cross-encoder-labels-bert", Architecture::Bert),
+        ("cross-encoder-labels-modernbert", Architecture::ModernBert),
+    ] {
+        let path = common::write_cross_encoder(name, architecture, 3)?;
+        let pairs = vec![
+            (
+                "what is the capital of france".to_string(),
+                "paris".to_string(),
+            ),
+            (
+                "what is the capital of france".to_string(),
+                "the sun is a star".to_string(),
+            ),
+        ];
+
+        let mut model: CrossEncoder = CrossEncoder::from(path.to_str().unwrap()).try_into()?;
+        let logits = model.predict_logits(&pairs)?;
+        assert_eq!(logits.len(), 2);
+        assert!(logits.iter().all(|logits| logits.len() == 3));
+
+        for label in 0..3 {
+            let mut model: CrossEncoder = CrossEncoder::from(path.to_str().unwrap())
+                .with_score_label(label)
+                .try_into()?;
+            let scores = model.predict(&pairs)?;
+            for (logits, score) in logits.iter().zip(scores) {
+                let total: f32 = logits.iter().map(|logit| logit.exp()).sum();
+                assert!((logits[label].exp() / total - score).abs() < 1e-5);
+            }
+        }
+
+        // The last label is scored by default.
+        let scores = model.predict(&pairs)?;
+        let total: f32 = logits[0].iter().map(|logit| logit.exp()).sum();
+        assert!((logits[0][2].exp() / total - scores[0]).abs() < 1e-5);
+
+        assert!(matches!(
+            CrossEncoder::try_from(CrossEncoder::from(path.to_str().unwrap()).with_score_label(3)),
+            Err(ColbertError::IndexOutOfRange { index: 3, len: 3 })
+        ));
+    }
+    Ok(())
+}
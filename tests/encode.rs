@@ -0,0 +1,410 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor, Var, D};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::Config as BertConfig;
+use common::{
+    sentences, Architecture, DOCUMENT_LENGTH, EMBEDDING_DIM, EMBEDDING_DIM_2, QUERY_LENGTH,
+};
+use pylate_rs::{ColBERT, ColbertError};
+use std::{collections::HashMap, fs};
+
+/// Loads both fixture architectures through the builder and checks embedding shapes.
+#[test]
+fn local_encode_shapes_test() -> Result<()> {
+    for (name, architecture) in [
+        ("encode-bert", Architecture::Bert),
+        ("encode-modernbert", Architecture::ModernBert),
+    ] {
+        let path = common::write_colbert(name, architecture, false)?;
+        let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+            .with_device(Device::Cpu)
+            .try_into()?;
+
+        let queries = sentences(&["what is the capital of france", "how big is the sun"]);
+        let documents = sentences(&["paris is the capital of france", "the sun is a star"]);
+
+        let query_embeddings = model.encode(&queries, true)?;
+        assert_eq!(query_embeddings.dims(), &[2, QUERY_LENGTH, EMBEDDING_DIM]);
+
+        // [CLS] [D] + six words + [SEP], padded to the longest document.
+        let document_embeddings = model.encode(&documents, false)?;
+        assert_eq!(document_embeddings.dims(), &[2, 9, EMBEDDING_DIM]);
+
+        // Every query token embedding is L2-normalized.
+        let norms = query_embeddings.sqr()?.sum(D::Minus1)?.sqrt()?;
+        for norm in norms.flatten_all()?.to_vec1::<f32>()? {
+            assert!((norm - 1.0).abs() < 1e-4, "Norm {} is not 1", norm);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the optional `2_Dense` module is applied after `1_Dense`.
+#[test]
+fn local_dense2_test() -> Result<()> {
+    let path = common::write_colbert("dense2", Architecture::ModernBert, true)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+
+    let embeddings = model.encode(&sentences(&["paris is the capital of france"]), false)?;
+    assert_eq!(embeddings.dim(2)?, EMBEDDING_DIM_2);
+    assert_eq!(model.info().embedding_dim, EMBEDDING_DIM_2);
+
+    // When present, modules.json decides which Dense modules are loaded.
+    fs::write(
+        path.join("modules.json"),
+        serde_json::to_vec(&serde_json::json!([
+            { "idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer" },
+            { "idx": 1, "name": "1", "path": "1_Dense", "type": "pylate.models.Dense.Dense" },
+        ]))?,
+    )?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    let embeddings = model.encode(&sentences(&["paris is the capital of france"]), false)?;
+    assert_eq!(embeddings.dim(2)?, EMBEDDING_DIM);
+    Ok(())
+}
+
+/// Checks that builder overrides take precedence over the config files.
+#[test]
+fn local_builder_overrides_test() -> Result<()> {
+    let path = common::write_colbert("overrides", Architecture::Bert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_query_length(6)
+        .with_document_length(5)
+        .with_batch_size(1)
+        .try_into()?;
+
+    let query_embeddings = model.encode(&sentences(&["what is the capital of france"]), true)?;
+    assert_eq!(query_embeddings.dims(), &[1, 6, EMBEDDING_DIM]);
+
+    let documents = sentences(&["paris is the capital of france", "the sun"]);
+    let document_embeddings = model.encode(&documents, false)?;
+    assert_eq!(document_embeddings.dims(), &[2, 5, EMBEDDING_DIM]);
+
+    // The model info reports the effective settings.
+    let info = model.info();
+    assert_eq!(info.model_id.as_deref(), path.to_str());
+    assert_eq!(info.architecture, "BertModel");
+    assert_eq!(info.embedding_dim, EMBEDDING_DIM);
+    assert_eq!(info.vocab_size, common::write_tokenizer(&path)?);
+    assert_eq!((info.query_length, info.document_length), (6, 5));
+    assert_eq!(info.query_prefix, "[Q] ");
+    assert_eq!(info.mask_token, "[MASK]");
+    assert!(info.do_query_expansion);
+    assert_eq!(info.device, "cpu");
+
+    // Expansion tokens are part of the query, while zero rows pad a batch.
+    let queries = sentences(&["what is the capital of france", "the sun"]);
+    let expanded = model.encode(&queries, true)?;
+    let split = model.split_embeddings(&expanded, true)?;
+    assert!(split.iter().all(|query| query.dim(0).ok() == Some(6)));
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_query_length(6)
+        .with_do_query_expansion(false)
+        .try_into()?;
+    let padded = model.encode(&queries, true)?;
+    let lengths: Vec<usize> = model
+        .split_embeddings(&padded, true)?
+        .iter()
+        .map(|query| query.dim(0))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lengths, vec![6, 5]);
+    Ok(())
+}
+
+/// Checks that punctuation is removed from documents, but not from queries, unless the
+/// skiplist is overridden.
+#[test]
+fn local_skiplist_test() -> Result<()> {
+    let path = common::write_colbert("skiplist", Architecture::Bert, false)?;
+    let documents = sentences(&["paris, france."]);
+
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    let embeddings = model.encode(&documents, false)?;
+    assert_eq!(embeddings.dim(1)?, 5);
+    // The tokens are aligned with the embeddings that are kept.
+    assert_eq!(
+        model.tokens(&documents, false)?,
+        vec![vec!["[CLS]", "[D]", "paris", "france", "[SEP]"]]
+    );
+
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_skiplist_words(vec![])
+        .try_into()?;
+    let embeddings = model.encode(&documents, false)?;
+    assert_eq!(embeddings.dim(1)?, 7);
+
+    // Queries are never filtered, even with the default skiplist.
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    let embeddings = model.encode(&documents, true)?;
+    assert_eq!(embeddings.dim(1)?, QUERY_LENGTH);
+    assert_eq!(
+        model.tokens(&documents, true)?[0][..7],
+        ["[CLS]", "[Q]", "paris", ",", "france", ".", "[SEP]"]
+    );
+
+    // The byte-buffer constructor applies the default skiplist.
+    let mut model = ColBERT::new(
+        fs::read(path.join("model.safetensors"))?,
+        fs::read(path.join("1_Dense/model.safetensors"))?,
+        None,
+        fs::read(path.join("tokenizer.json"))?,
+        fs::read(path.join("config.json"))?,
+        fs::read(path.join("1_Dense/config.json"))?,
+        None,
+        "[Q] ".to_string(),
+        "[D] ".to_string(),
+        "[MASK]".to_string(),
+        true,
+        false,
+        Some(QUERY_LENGTH),
+        Some(DOCUMENT_LENGTH),
+        None,
+        &Device::Cpu,
+    )?;
+    assert_eq!(model.encode(&documents, false)?.dim(1)?, 5);
+    assert_eq!(model.encode(&documents, true)?.dim(1)?, QUERY_LENGTH);
+    Ok(())
+}
+
+/// Checks that `similarity` is the MaxSim reduction of `raw_similarity`.
+#[test]
+fn local_similarity_test() -> Result<()> {
+    let path = common::write_colbert("similarity", Architecture::ModernBert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+
+    let queries = sentences(&["what is the capital of france", "how big is the sun"]);
+    let documents = sentences(&[
+        "paris is the capital of france",
+        "berlin is the capital of germany",
+        "the sun is a star",
+    ]);
+
+    let query_embeddings = model.encode(&queries, true)?;
+    let document_embeddings = model.encode(&documents, false)?;
+
+    let similarities = model.similarity(&query_embeddings, &document_embeddings)?;
+    assert_eq!(similarities.data.len(), 2);
+    assert!(similarities.data.iter().all(|row| row.len() == 3));
+
+    let raw = model.raw_similarity(&query_embeddings, &document_embeddings)?;
+    assert_eq!(
+        raw.dims(),
+        &[2, 3, QUERY_LENGTH, document_embeddings.dim(1)?]
+    );
+    let expected = raw.max(3)?.sum(2)?.to_vec2::<f32>()?;
+    for (row, expected_row) in similarities.data.iter().zip(expected.iter()) {
+        for (score, expected_score) in row.iter().zip(expected_row.iter()) {
+            assert!((score - expected_score).abs() < 1e-5);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that encoding queries and documents is independent of the batch size.
+#[test]
+fn local_batching_test() -> Result<()> {
+    let path = common::write_colbert("batching", Architecture::Bert, false)?;
+    let texts = sentences(&[
+        "paris is the capital of france",
+        "berlin is the capital of germany, this is a test",
+        "the sun is a star",
+    ]);
+
+    let mut single_model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    let mut batched_model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(2)
+        .try_into()?;
+    for is_query in [true, false] {
+        let single_batch = single_model.encode(&texts, is_query)?;
+        let multiple_batches = batched_model.encode(&texts, is_query)?;
+
+        let difference = (single_batch - multiple_batches)?
+            .abs()?
+            .max_keepdim(D::Minus1)?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(
+            difference < 1e-4,
+            "Batched encodings differ by {} (is_query: {})",
+            difference,
+            is_query
+        );
+    }
+    Ok(())
+}
+
+/// Checks that documents encoded in separate batches are padded to the longest one
+/// across all batches, with zero rows only.
+#[test]
+fn local_cross_batch_padding_test() -> Result<()> {
+    let path = common::write_colbert("cross-batch-padding", Architecture::Bert, false)?;
+    let documents = sentences(&[
+        "the sun",
+        "berlin is the capital of germany this is a test",
+        "paris is the capital of france",
+    ]);
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(1)
+        .try_into()?;
+
+    // [CLS] [D] + ten words + [SEP].
+    let embeddings = model.encode(&documents, false)?;
+    assert_eq!(embeddings.dims(), &[3, 13, EMBEDDING_DIM]);
+    for (i, document) in documents.iter().enumerate() {
+        let alone = model
+            .encode(std::slice::from_ref(document), false)?
+            .get(0)?;
+        let length = alone.dim(0)?;
+        let padded = embeddings.get(i)?;
+        let difference = (padded.narrow(0, 0, length)? - alone)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(
+            difference < 1e-4,
+            "Document {} differs by {}",
+            i,
+            difference
+        );
+        let padding = padded.narrow(0, length, 13 - length)?.abs()?.sum_all()?;
+        assert_eq!(padding.to_scalar::<f32>()?, 0.0);
+    }
+    Ok(())
+}
+
+/// Checks that the document length from the config is used for truncation.
+#[test]
+fn local_document_truncation_test() -> Result<()> {
+    let path = common::write_colbert("truncation", Architecture::Bert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+
+    let long_document = ["paris is the capital of france"; 10].join(" ");
+    let embeddings = model.encode(&[long_document], false)?;
+    assert_eq!(embeddings.dim(1)?, DOCUMENT_LENGTH);
+    Ok(())
+}
+
+/// Checks the errors returned for invalid inputs and model directories.
+#[test]
+fn local_error_paths_test() -> Result<()> {
+    let path = common::write_colbert("errors", Architecture::Bert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+    assert!(matches!(
+        model.encode(&[], false),
+        Err(ColbertError::EmptyInput)
+    ));
+
+    // Missing file in a local directory.
+    let path = common::write_colbert("errors-missing-file", Architecture::Bert, false)?;
+    fs::remove_file(path.join("special_tokens_map.json"))?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::MissingFile(file)) if file.ends_with("special_tokens_map.json")
+    ));
+
+    // Unsupported architecture.
+    let path = common::write_colbert("errors-architecture", Architecture::Bert, false)?;
+    let mut config: serde_json::Value =
+        serde_json::from_slice(&fs::read(path.join("config.json"))?)?;
+    config["architectures"] = serde_json::json!(["GPT2Model"]);
+    fs::write(path.join("config.json"), serde_json::to_vec(&config)?)?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::UnsupportedArchitecture(arch)) if arch == "GPT2Model"
+    ));
+
+    // modules.json without any Dense module.
+    let path = common::write_colbert("errors-modules", Architecture::Bert, false)?;
+    fs::write(
+        path.join("modules.json"),
+        serde_json::to_vec(&serde_json::json!([
+            { "idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer" },
+        ]))?,
+    )?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::UnsupportedArchitecture(_))
+    ));
+
+    // An unreadable modules.json is an error rather than a missing file.
+    let path = common::write_colbert("errors-modules-unreadable", Architecture::Bert, false)?;
+    fs::create_dir(path.join("modules.json"))?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::Io(_))
+    ));
+
+    // 2_Dense input does not match 1_Dense output.
+    let path = common::write_colbert("errors-dense2", Architecture::Bert, false)?;
+    common::write_dense(&path.join("2_Dense"), EMBEDDING_DIM + 1, EMBEDDING_DIM_2)?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::DimensionMismatch { expected, found, .. })
+            if expected == EMBEDDING_DIM && found == EMBEDDING_DIM + 1
+    ));
+
+    // Dense config without `out_features`.
+    let path = common::write_colbert("errors-dense-config", Architecture::Bert, false)?;
+    fs::write(
+        path.join("1_Dense/config.json"),
+        serde_json::to_vec(&serde_json::json!({ "in_features": 16 }))?,
+    )?;
+    assert!(matches!(
+        ColBERT::try_from(ColBERT::from(path.to_str().unwrap())),
+        Err(ColbertError::MissingConfigField { file, field })
+            if file == "1_Dense/config.json" && field == "out_features"
+    ));
+
+    // Unknown mask token.
+    let path = common::write_colbert("errors-mask-token", Architecture::Bert, false)?;
+    let builder = ColBERT::from(path.to_str().unwrap()).with_mask_token("<mask>".to_string());
+    assert!(matches!(
+        ColBERT::try_from(builder),
+        Err(ColbertError::UnknownToken(token)) if token == "<mask>"
+    ));
+    Ok(())
+}
+
+/// Checks the local BERT encoder against candle's, with and without gradient tracking.
+#[test]
+fn local_bert_parity_test() -> Result<()> {
+    let path = common::write_colbert("bert-parity", Architecture::Bert, false)?;
+    let config: BertConfig = serde_json::from_slice(&fs::read(path.join("config.json"))?)?;
+    let weights = candle_core::safetensors::load(path.join("model.safetensors"), &Device::Cpu)?;
+
+    let input_ids = Tensor::new(&[[1u32, 5, 6, 7, 2, 0], [1, 8, 9, 2, 0, 0]], &Device::Cpu)?;
+    let token_type_ids = input_ids.zeros_like()?;
+    let attention_mask = input_ids.ne(0u32)?.to_dtype(DType::U32)?;
+
+    let vb = VarBuilder::from_tensors(weights.clone(), DType::F32, &Device::Cpu);
+    let expected = candle_transformers::models::bert::BertModel::load(vb.clone(), &config)?
+        .forward(&input_ids, &token_type_ids, Some(&attention_mask))?;
+    let found = pylate_rs::bert::BertModel::load(vb, &config)?.forward(
+        &input_ids,
+        &token_type_ids,
+        Some(&attention_mask),
+    )?;
+    let difference = (&expected - &found)?.abs()?.max_all()?.to_scalar::<f32>()?;
+    assert!(difference < 1e-5, "inference differs by {difference}");
+
+    // Variables make every layer normalization take the unfused path used for training.
+    let variables = weights
+        .into_iter()
+        .map(|(name, tensor)| Ok((name, Var::from_tensor(&tensor)?.as_tensor().clone())))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let vb = VarBuilder::from_tensors(variables, DType::F32, &Device::Cpu);
+    let found = pylate_rs::bert::BertModel::load(vb, &config)?.forward(
+        &input_ids,
+        &token_type_ids,
+        Some(&attention_mask),
+    )?;
+    let difference = (&expected - &found)?.abs()?.max_all()?.to_scalar::<f32>()?;
+    assert!(
+        difference < 1e-5,
+        "training forward differs by {difference}"
+    );
+    Ok(())
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use anyhow::Result;
+use pylate_rs::{evaluate, read_qrels, ColbertError, Run};
+use std::fs;
+
+/// Checks the retrieval metrics against hand-computed values and both qrels formats.
+#[test]
+fn local_evaluation_metrics_test() -> Result<()> {
+    let dir = common::fixture_dir("evaluation");
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("test.tsv"),
+        "query-id\tcorpus-id\tscore\nq1\td1\t2\nq1\td3\t1\nq2\td2\t1\nq3\td1\t0\n",
+    )?;
+    fs::write(
+        dir.join("test.trec"),
+        "q1 0 d1 2\nq1 0 d3 1\nq2 0 d2 1\nq3 0 d1 0\n",
+    )?;
+    let qrels = read_qrels(dir.join("test.tsv"))?;
+    assert_eq!(qrels, read_qrels(dir.join("test.trec"))?);
+
+    fs::write(dir.join("invalid.trec"), "q1 0 d1 2\nq1 0 d3\tx\n")?;
+    assert!(matches!(
+        read_qrels(dir.join("invalid.trec")),
+        Err(ColbertError::Parse { line: 2, .. })
+    ));
+
+    let ranking = |ids: &[&str]| -> Vec<(String, f32)> {
+        ids.iter().map(|id| (id.to_string(), 0.0)).collect()
+    };
+    let run: Run = [
+        ("q1".to_string(), ranking(&["d3", "d2", "d1"])),
+        ("q2".to_string(), ranking(&["d2", "d1"])),
+    ]
+    .into_iter()
+    .collect();
+
+    // q3 has no relevant document and is skipped.
+    let report = evaluate(&run, &qrels, &[1, 3]);
+    assert_eq!(report.num_queries, 2);
+
+    // q1: DCG@3 = 1 + 2 / log2(4) = 2, IDCG@3 = 2 + 1 / log2(3).
+    let q1_ndcg = 2.0 / (2.0 + 1.0 / 3f64.log2());
+    assert!((report.ndcg[&3] - (q1_ndcg + 1.0) / 2.0).abs() < 1e-9);
+    assert!((report.ndcg[&1] - (0.5 + 1.0) / 2.0).abs() < 1e-9);
+    assert!((report.mrr[&1] - 1.0).abs() < 1e-9);
+    assert!((report.recall[&1] - (0.5 + 1.0) / 2.0).abs() < 1e-9);
+    assert!((report.recall[&3] - 1.0).abs() < 1e-9);
+    // q1: AP = (1 / 1 + 2 / 3) / 2.
+    assert!((report.map - ((1.0 + 2.0 / 3.0) / 2.0 + 1.0) / 2.0).abs() < 1e-9);
+    Ok(())
+}
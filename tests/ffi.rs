@@ -0,0 +1,71 @@
+#![cfg(feature = "ffi")]
+
+mod common;
+
+use anyhow::{ensure, Result};
+use common::Architecture;
+use std::{env, fs, path::Path, process::Command};
+
+/// Compiles `tests/ffi/test.c` against the shared library and the generated header, then
+/// runs it on a fixture model.
+#[test]
+fn c_api_test() -> Result<()> {
+    let model = common::write_colbert("ffi", Architecture::Bert, false)?;
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    // `cargo test` only builds the rlib, so the shared library is built separately.
+    let lib_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let build = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--features", "ffi", "--target-dir"])
+        .arg(&lib_dir)
+        .current_dir(manifest_dir)
+        .status()?;
+    ensure!(build.success(), "failed to build the shared library");
+    let lib_dir = lib_dir.join("debug");
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+
+    let compile = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(manifest_dir.join("tests/ffi/test.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .args(["-lpylate_rs", "-lm", "-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .status()?;
+    ensure!(compile.success(), "failed to compile the C test program");
+
+    // Cargo points the library path at its own output directories, which take
+    // precedence over the rpath.
+    let output = Command::new(&program)
+        .arg(&model)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()?;
+    ensure!(
+        output.status.success(),
+        "C test program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+    Ok(())
+}
+
+/// Checks that the committed header matches the one generated by the build script.
+///
+/// Setting `UPDATE_HEADER` overwrites the committed header instead, as `make ffi-header`
+/// does.
+#[test]
+fn header_test() -> Result<()> {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/pylate_rs.h"));
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/pylate_rs.h");
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, generated)?;
+    }
+    ensure!(
+        fs::read_to_string(&path)? == generated,
+        "include/pylate_rs.h is out of date, run `make ffi-header`"
+    );
+    Ok(())
+}
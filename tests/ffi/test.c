@@ -0,0 +1,150 @@
+/*
+ * Exercises the C API against the model directory given as the first argument.
+ * Compiled and run by tests/ffi.rs.
+ */
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "pylate_rs.h"
+
+#define CHECK(condition)                                                        \
+    do {                                                                        \
+        if (!(condition)) {                                                     \
+            const char *error = colbert_last_error();                           \
+            fprintf(stderr, "%s:%d: check failed: %s (last error: %s)\n",       \
+                    __FILE__, __LINE__, #condition, error ? error : "none");    \
+            return 1;                                                           \
+        }                                                                       \
+    } while (0)
+
+static size_t total_rows(const size_t *lengths, size_t count) {
+    size_t rows = 0;
+    for (size_t i = 0; i < count; i++) {
+        rows += lengths[i];
+    }
+    return rows;
+}
+
+/* MaxSim computed without the library. */
+static float naive_maxsim(const float *query, size_t query_length, const float *document,
+                          size_t document_length, size_t dim) {
+    float score = 0.0f;
+    for (size_t i = 0; i < query_length; i++) {
+        float best = -INFINITY;
+        for (size_t j = 0; j < document_length; j++) {
+            float dot = 0.0f;
+            for (size_t k = 0; k < dim; k++) {
+                dot += query[i * dim + k] * document[j * dim + k];
+            }
+            if (dot > best) {
+                best = dot;
+            }
+        }
+        score += best;
+    }
+    return score;
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 2);
+
+    /* Errors are reported through colbert_last_error. */
+    CHECK(colbert_model_load("/nonexistent/model", NULL) == NULL);
+    CHECK(colbert_last_error() != NULL && strlen(colbert_last_error()) > 0);
+    CHECK(colbert_model_load(argv[1], "{\"unknown\": 1}") == NULL);
+    CHECK(colbert_encode(NULL, NULL, 0, true, NULL) == COLBERT_ERROR);
+
+    ColbertModel *model = colbert_model_load(argv[1], "{\"query_length\": 12}");
+    CHECK(model != NULL);
+    size_t dim = colbert_model_embedding_dim(model);
+    CHECK(dim > 0);
+
+    const char *queries[] = {"what is the capital of france?", "how big is the sun?"};
+    const char *documents[] = {
+        "paris is the capital of france.",
+        "a star",
+        "berlin is the capital of germany, this is a test of how big the sun is!",
+    };
+
+    ColbertEmbeddings query_embeddings;
+    CHECK(colbert_encode(model, queries, 2, true, &query_embeddings) == COLBERT_OK);
+    CHECK(query_embeddings.num_sentences == 2 && query_embeddings.dim == dim);
+    CHECK(query_embeddings.lengths[0] == 12 && query_embeddings.lengths[1] == 12);
+
+    /* Without query expansion, queries lose their padding rows like documents. */
+    ColbertModel *unexpanded =
+        colbert_model_load(argv[1], "{\"query_length\": 12, \"do_query_expansion\": false}");
+    CHECK(unexpanded != NULL);
+    ColbertEmbeddings unexpanded_embeddings;
+    CHECK(colbert_encode(unexpanded, queries, 2, true, &unexpanded_embeddings) == COLBERT_OK);
+    CHECK(unexpanded_embeddings.lengths[0] < 12 && unexpanded_embeddings.lengths[1] < 12);
+    CHECK(unexpanded_embeddings.lengths[1] < unexpanded_embeddings.lengths[0]);
+    colbert_embeddings_free(&unexpanded_embeddings);
+    colbert_model_free(unexpanded);
+
+    ColbertEmbeddings document_embeddings;
+    CHECK(colbert_encode(model, documents, 3, false, &document_embeddings) == COLBERT_OK);
+    CHECK(document_embeddings.num_sentences == 3);
+    CHECK(document_embeddings.lengths[1] < document_embeddings.lengths[2]);
+
+    /* Caller-provided buffers report the required size, then match the library buffers. */
+    size_t lengths[3];
+    size_t required = 0;
+    CHECK(colbert_encode_into(model, documents, 3, false, NULL, 0, lengths, &required) ==
+          COLBERT_BUFFER_TOO_SMALL);
+    size_t rows = total_rows(document_embeddings.lengths, 3);
+    CHECK(required == rows * dim);
+    float *data = malloc(required * sizeof(float));
+    CHECK(colbert_encode_into(model, documents, 3, false, data, required, lengths, NULL) ==
+          COLBERT_OK);
+    for (size_t i = 0; i < 3; i++) {
+        CHECK(lengths[i] == document_embeddings.lengths[i]);
+    }
+    for (size_t i = 0; i < required; i++) {
+        CHECK(fabsf(data[i] - document_embeddings.data[i]) < 1e-5f);
+    }
+    free(data);
+
+    /* MaxSim agrees with a naive implementation. */
+    float scores[6];
+    CHECK(colbert_maxsim(query_embeddings.data, query_embeddings.lengths, 2,
+                         document_embeddings.data, document_embeddings.lengths, 3, dim,
+                         scores) == COLBERT_OK);
+    const float *query = query_embeddings.data;
+    for (size_t q = 0; q < 2; q++) {
+        const float *document = document_embeddings.data;
+        for (size_t d = 0; d < 3; d++) {
+            float expected = naive_maxsim(query, query_embeddings.lengths[q], document,
+                                          document_embeddings.lengths[d], dim);
+            CHECK(fabsf(scores[q * 3 + d] - expected) < 1e-4f);
+            document += document_embeddings.lengths[d] * dim;
+        }
+        query += query_embeddings.lengths[q] * dim;
+    }
+    CHECK(colbert_maxsim(NULL, query_embeddings.lengths, 2, document_embeddings.data,
+                         document_embeddings.lengths, 3, dim, scores) == COLBERT_ERROR);
+
+    /* Pooling reduces the number of rows of each document. */
+    ColbertEmbeddings pooled;
+    CHECK(colbert_hierarchical_pooling(document_embeddings.data, document_embeddings.lengths,
+                                       3, dim, 2, &pooled) == COLBERT_OK);
+    CHECK(pooled.num_sentences == 3 && pooled.dim == dim);
+    for (size_t i = 0; i < 3; i++) {
+        CHECK(pooled.lengths[i] >= 1);
+        CHECK(pooled.lengths[i] <= (document_embeddings.lengths[i] + 1) / 2);
+    }
+    CHECK(pooled.lengths[2] < document_embeddings.lengths[2]);
+
+    colbert_embeddings_free(&pooled);
+    CHECK(pooled.data == NULL && pooled.lengths == NULL);
+    colbert_embeddings_free(&query_embeddings);
+    colbert_embeddings_free(&document_embeddings);
+    colbert_embeddings_free(NULL);
+    colbert_model_free(model);
+    colbert_model_free(NULL);
+
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use anyhow::Result;
+use common::{sentences, Architecture, QUERY_LENGTH};
+use pylate_rs::{ColBERT, ColbertError, MaskedLM};
+
+/// Checks fill-mask predictions and query-expansion inspection.
+#[test]
+fn local_masked_lm_test() -> Result<()> {
+    let colbert_path = common::write_colbert("expansion-colbert", Architecture::ModernBert, false)?;
+    let masked_lm_path = common::write_masked_lm("expansion-mlm", Architecture::ModernBert)?;
+
+    let mut model: ColBERT = ColBERT::from(colbert_path.to_str().unwrap()).try_into()?;
+    let mut masked_lm: MaskedLM = MaskedLM::from(masked_lm_path.to_str().unwrap()).try_into()?;
+
+    let predictions = masked_lm.fill_mask("paris is the [MASK] of france", 3)?;
+    assert_eq!(predictions.len(), 1);
+    assert_eq!(predictions[0].position, 4);
+    assert_eq!(predictions[0].tokens.len(), 3);
+    assert!(matches!(
+        masked_lm.fill_mask("paris is the capital of france", 3),
+        Err(ColbertError::MissingMaskToken)
+    ));
+
+    // [CLS] [Q] + six words + [SEP] leaves three expansion tokens.
+    let expansions = model.query_expansion_tokens(
+        &masked_lm,
+        &sentences(&["what is the capital of france"]),
+        2,
+    )?;
+    let positions: Vec<usize> = expansions[0].iter().map(|p| p.position).collect();
+    assert_eq!(positions, (9..QUERY_LENGTH).collect::<Vec<_>>());
+    assert!(expansions[0].iter().all(|p| p.tokens.len() == 2));
+
+    // Expansion positions are found with the mask token of the ColBERT model but fed
+    // to the masked LM as its own mask token.
+    let mut unk_model: ColBERT = ColBERT::from(colbert_path.to_str().unwrap())
+        .with_mask_token("[UNK]".to_string())
+        .try_into()?;
+    let unk_expansions = unk_model.query_expansion_tokens(
+        &masked_lm,
+        &sentences(&["what is the capital of france"]),
+        2,
+    )?;
+    assert_eq!(unk_expansions, expansions);
+    Ok(())
+}
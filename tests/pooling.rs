@@ -0,0 +1,66 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use common::{sentences, Architecture, EMBEDDING_DIM};
+use pylate_rs::{hierarchical_pooling, ColBERT, ColbertError};
+
+/// Checks hierarchical pooling on encoded documents.
+#[test]
+fn local_pooling_test() -> Result<()> {
+    let path = common::write_colbert("pooling", Architecture::ModernBert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+
+    let documents = sentences(&["berlin is the capital of germany this is a test"]);
+    let embeddings = model.encode(&documents, false)?;
+    assert_eq!(embeddings.dim(1)?, 13);
+
+    let unpooled = hierarchical_pooling(&embeddings, 1)?;
+    assert_eq!(unpooled.dims(), embeddings.dims());
+
+    // The first token is protected, the twelve others are merged into six clusters.
+    let pooled = hierarchical_pooling(&embeddings, 2)?;
+    assert_eq!(pooled.dims(), &[1, 7, EMBEDDING_DIM]);
+
+    // Pooling on encode pools each document without its padding rows, and never queries.
+    let mut pooling_model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_pool_factor(2)
+        .try_into()?;
+    assert_eq!(pooling_model.info().pool_factor, 2);
+    let batch = sentences(&[
+        "berlin is the capital of germany this is a test",
+        "paris is the capital of france",
+    ]);
+    let pooled_batch = pooling_model.encode(&batch, false)?;
+    assert_eq!(pooled_batch.dims(), &[2, 7, EMBEDDING_DIM]);
+
+    // Each document matches the pooling of its own encoding, up to batching noise.
+    let max_difference = |a: Tensor, b: Tensor| -> Result<f32> {
+        Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+    };
+    assert!(max_difference(pooled_batch.get(0)?, pooled.get(0)?)? < 1e-4);
+    let short = hierarchical_pooling(&model.encode(&batch[1..], false)?, 2)?.get(0)?;
+    let short_length = short.dim(0)?;
+    assert!(max_difference(pooled_batch.get(1)?.narrow(0, 0, short_length)?, short)? < 1e-4);
+    assert_eq!(
+        model
+            .encode_with_pool_factor(&batch, false, 2)?
+            .to_vec3::<f32>()?,
+        pooled_batch.to_vec3::<f32>()?
+    );
+    assert_eq!(
+        pooling_model.encode(&batch, true)?.to_vec3::<f32>()?,
+        model.encode(&batch, true)?.to_vec3::<f32>()?
+    );
+
+    let invalid = Tensor::zeros((4, EMBEDDING_DIM), candle_core::DType::F32, &Device::Cpu)?;
+    assert!(matches!(
+        hierarchical_pooling(&invalid, 2),
+        Err(ColbertError::DimensionMismatch {
+            expected: 3,
+            found: 2,
+            ..
+        })
+    ));
+    Ok(())
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use anyhow::Result;
+use common::{sentences, Architecture, DOCUMENT_LENGTH, EMBEDDING_DIM, QUERY_LENGTH};
+use pylate_rs::{CancellationToken, ColBERT, ColbertError, EncodeProgress};
+use std::sync::{Arc, Mutex};
+
+/// Checks the progress reported after each batch and that cancelled tokens stop `encode`.
+#[test]
+fn local_progress_test() -> Result<()> {
+    let path = common::write_colbert("progress", Architecture::Bert, false)?;
+    let reports = Arc::new(Mutex::new(Vec::<EncodeProgress>::new()));
+    let recorded = reports.clone();
+    let cancellation = CancellationToken::new();
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(2)
+        .with_progress_callback(Arc::new(move |progress: &EncodeProgress| {
+            recorded.lock().unwrap().push(progress.clone())
+        }))
+        .with_cancellation_token(cancellation.clone())
+        .try_into()?;
+
+    let documents = sentences(&[
+        "paris is the capital of france",
+        "berlin is the capital of germany",
+        "the sun is a star",
+        "how big is the sun",
+        "a star",
+    ]);
+    model.encode(&documents, false)?;
+    let reports = std::mem::take(&mut *reports.lock().unwrap());
+    assert_eq!(reports.len(), 3);
+    for (i, report) in reports.iter().enumerate() {
+        assert_eq!(report.batches_done, i + 1);
+        assert_eq!((report.total_batches, report.total_sentences), (3, 5));
+    }
+    let last = reports.last().unwrap();
+    assert_eq!(last.sentences_done, 5);
+    assert!(last.tokens_processed > 5 && last.tokens_processed <= 5 * DOCUMENT_LENGTH);
+
+    cancellation.cancel();
+    assert!(matches!(
+        model.encode(&documents, true),
+        Err(ColbertError::Cancelled)
+    ));
+    model.set_cancellation_token(None);
+    assert_eq!(
+        model.encode(&documents, true)?.dims(),
+        &[5, QUERY_LENGTH, EMBEDDING_DIM]
+    );
+    Ok(())
+}
+
+/// Checks that cancelling from the progress callback stops `encode` and that no batch
+/// is reported afterwards, even though other batches run in parallel.
+#[test]
+fn local_progress_cancel_test() -> Result<()> {
+    let path = common::write_colbert("progress-cancel", Architecture::Bert, false)?;
+    let reports = Arc::new(Mutex::new(Vec::<EncodeProgress>::new()));
+    let recorded = reports.clone();
+    let cancellation = CancellationToken::new();
+    let token = cancellation.clone();
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(1)
+        .with_progress_callback(Arc::new(move |progress: &EncodeProgress| {
+            recorded.lock().unwrap().push(progress.clone());
+            token.cancel();
+        }))
+        .with_cancellation_token(cancellation)
+        .try_into()?;
+
+    let documents: Vec<String> = (0..64)
+        .map(|i| format!("document {} is about the sun", i))
+        .collect();
+    assert!(matches!(
+        model.encode(&documents, false),
+        Err(ColbertError::Cancelled)
+    ));
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!((reports[0].batches_done, reports[0].sentences_done), (1, 1));
+    assert_eq!(reports[0].total_batches, 64);
+    Ok(())
+}
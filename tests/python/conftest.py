@@ -0,0 +1,40 @@
+"""Fixtures for the Python tests.
+
+The tests load the tiny, randomly initialized ColBERT model written by the Rust
+fixture generator, so that they run without network access. Build the extension
+first, e.g. with `maturin develop --features python`.
+"""
+
+from __future__ import annotations
+
+import subprocess
+from pathlib import Path
+
+import pytest
+from pylate_rs import models
+
+ROOT = Path(__file__).resolve().parents[2]
+
+
+@pytest.fixture(scope="session")
+def model_path() -> str:
+    """Write the fixture model once and return its directory."""
+    output = subprocess.run(
+        ["cargo", "run", "--quiet", "--example", "write_fixture"],  # noqa: S607
+        cwd=ROOT,
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    return output.stdout.strip()
+
+
+@pytest.fixture
+def model(model_path: str) -> models.ColBERT:
+    """Load the fixture model with the lengths of its configuration."""
+    return models.ColBERT(
+        model_name_or_path=model_path,
+        query_length=12,
+        document_length=24,
+        batch_size=2,
+    )
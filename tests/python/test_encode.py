@@ -0,0 +1,117 @@
+"""Tests for `ColBERT.encode` and `ColBERT.similarity`."""
+
+from __future__ import annotations
+
+import threading
+import time
+
+import numpy as np
+from pylate_rs import models
+
+DOCUMENTS = [
+    "paris is the capital of france",
+    "berlin is the capital of germany",
+    "the sun is a star",
+]
+QUERIES = ["what is the capital of france?", "how big is the sun?"]
+
+
+def test_per_call_batch_size(model: models.ColBERT) -> None:
+    """A per-call batch size changes neither the embeddings nor later calls."""
+    expected = model.encode(DOCUMENTS, is_query=False)
+    for batch_size in [1, 3, 0]:
+        embeddings = model.encode(DOCUMENTS, is_query=False, batch_size=batch_size)
+        np.testing.assert_allclose(embeddings, expected, atol=1e-5)
+    np.testing.assert_allclose(
+        model.encode(DOCUMENTS, is_query=False), expected, atol=1e-5
+    )
+
+
+def test_encode_releases_the_gil(model: models.ColBERT) -> None:
+    """Python threads keep running while another thread encodes."""
+    bounds = []
+
+    def encode() -> None:
+        start = time.perf_counter()
+        model.encode(DOCUMENTS * 500, is_query=False)
+        bounds.extend([start, time.perf_counter()])
+
+    thread = threading.Thread(target=encode)
+    thread.start()
+    ticks = []
+    while thread.is_alive():
+        ticks.append(time.perf_counter())
+    thread.join()
+
+    # Leave a margin for the thread switches around the call itself.
+    start, end = bounds
+    margin = (end - start) / 4
+    assert any(start + margin < tick < end - margin for tick in ticks)
+
+
+def test_similarity_while_encoding(model: models.ColBERT) -> None:
+    """Similarity waits for a concurrent encode without holding the GIL."""
+    queries = model.encode(QUERIES, is_query=True)
+    documents = model.encode(DOCUMENTS, is_query=False)
+    expected = model.similarity(queries, documents)
+
+    thread = threading.Thread(
+        target=model.encode, args=(DOCUMENTS * 500,), kwargs={"is_query": False}
+    )
+    thread.start()
+    scores = model.similarity(queries, documents)
+    thread.join()
+    np.testing.assert_allclose(scores, expected, atol=1e-5)
+
+
+def test_ragged_round_trip(model: models.ColBERT) -> None:
+    """Ragged embeddings drop the padding rows and score like padded ones."""
+    for sentences, is_query in [(QUERIES, True), (DOCUMENTS, False)]:
+        padded = model.encode(sentences, is_query=is_query)
+        ragged = model.encode(sentences, is_query=is_query, ragged=True)
+        tokens = model.tokenize(sentences, is_query=is_query)
+        assert len(ragged) == len(sentences)
+        for array, row, sentence_tokens in zip(ragged, padded, tokens):
+            assert array.shape == (len(sentence_tokens), padded.shape[2])
+            np.testing.assert_allclose(array, row[: len(array)], atol=1e-5)
+            assert not row[len(array) :].any()
+
+    queries = model.encode(QUERIES, is_query=True, ragged=True)
+    documents = model.encode(DOCUMENTS, is_query=False, ragged=True)
+    np.testing.assert_allclose(
+        model.similarity(queries, documents),
+        model.similarity(np.stack(queries), model.encode(DOCUMENTS, is_query=False)),
+        atol=1e-5,
+    )
+
+
+def test_pool_factor(model_path: str, model: models.ColBERT) -> None:
+    """Documents are pooled per call or by default, like `hierarchical_pooling`."""
+    documents = model.encode(DOCUMENTS, is_query=False, ragged=True)
+    pooled = model.encode(DOCUMENTS, is_query=False, pool_factor=2, ragged=True)
+    for document, array in zip(documents, pooled):
+        expected = models.hierarchical_pooling(document, pool_factor=2)
+        np.testing.assert_allclose(array, expected, atol=1e-5)
+
+    pooling_model = models.ColBERT(
+        model_name_or_path=model_path,
+        query_length=12,
+        document_length=24,
+        pool_factor=2,
+    )
+    for document, array in zip(
+        pooled, pooling_model.encode(DOCUMENTS, is_query=False, ragged=True)
+    ):
+        np.testing.assert_allclose(array, document, atol=1e-5)
+    for document, array in zip(
+        documents,
+        pooling_model.encode(DOCUMENTS, is_query=False, pool_factor=1, ragged=True),
+    ):
+        np.testing.assert_allclose(array, document, atol=1e-5)
+
+    # Queries are never pooled.
+    np.testing.assert_allclose(
+        pooling_model.encode(QUERIES, is_query=True),
+        model.encode(QUERIES, is_query=True),
+        atol=1e-5,
+    )
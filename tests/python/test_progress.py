@@ -0,0 +1,111 @@
+"""Tests for the progress callbacks and cancellation tokens of `ColBERT.encode`."""
+
+from __future__ import annotations
+
+import threading
+import time
+
+import pytest
+from pylate_rs import models
+
+DOCUMENTS = [
+    "paris is the capital of france",
+    "berlin is the capital of germany",
+    "the sun is a star",
+    "how big is the sun?",
+    "a star",
+]
+# Enough single-sentence batches to outnumber the threads encoding them in parallel.
+MANY_DOCUMENTS = DOCUMENTS * 40
+
+
+def test_progress(model: models.ColBERT) -> None:
+    """Progress is reported once per batch, with the totals of the call."""
+    reports = []
+    model.encode(DOCUMENTS, is_query=False, progress=reports.append)
+    assert len(reports) == 3
+    assert sorted(report["batches_done"] for report in reports) == [1, 2, 3]
+    for report in reports:
+        assert report["total_batches"] == 3
+        assert report["total_sentences"] == len(DOCUMENTS)
+    assert max(report["sentences_done"] for report in reports) == len(DOCUMENTS)
+    assert max(report["tokens_processed"] for report in reports) > len(DOCUMENTS)
+
+    # A per-call batch size changes the number of batches of that call only.
+    reports.clear()
+    model.encode(DOCUMENTS, is_query=False, batch_size=1, progress=reports.append)
+    assert len(reports) == len(DOCUMENTS)
+    reports.clear()
+    model.encode(DOCUMENTS, is_query=False, progress=reports.append)
+    assert len(reports) == 3
+
+
+def test_raising_progress_callback(model: models.ColBERT) -> None:
+    """An exception raised by the callback stops encoding and is raised by encode."""
+    calls = []
+
+    def progress(report: dict[str, int]) -> None:
+        calls.append(report)
+        message = "stop"
+        raise ValueError(message)
+
+    with pytest.raises(ValueError, match="stop"):
+        model.encode(MANY_DOCUMENTS, is_query=False, batch_size=1, progress=progress)
+    assert 1 <= len(calls) < len(MANY_DOCUMENTS)
+
+    # The callback does not outlive its call.
+    calls.clear()
+    model.encode(DOCUMENTS, is_query=False)
+    assert not calls
+
+
+def test_cancellation(model: models.ColBERT) -> None:
+    """A cancelled token stops encoding, from the caller or from the callback."""
+    cancel = models.CancellationToken()
+    assert not cancel.cancelled
+    cancel.cancel()
+    assert cancel.cancelled
+    with pytest.raises(Exception, match="cancelled"):
+        model.encode(DOCUMENTS, is_query=False, cancel=cancel)
+
+    cancel = models.CancellationToken()
+    reports = []
+
+    def progress(report: dict[str, int]) -> None:
+        reports.append(report)
+        cancel.cancel()
+
+    with pytest.raises(Exception, match="cancelled"):
+        model.encode(
+            MANY_DOCUMENTS,
+            is_query=False,
+            batch_size=1,
+            progress=progress,
+            cancel=cancel,
+        )
+    assert len(reports) < len(MANY_DOCUMENTS)
+
+    # The token does not outlive its call.
+    assert model.encode(DOCUMENTS, is_query=False).shape[0] == len(DOCUMENTS)
+
+
+def test_concurrent_calls_with_progress(model: models.ColBERT) -> None:
+    """Other methods wait for an encode whose callback takes the GIL back."""
+    queries = model.encode(["what is the capital of france?"], is_query=True)
+    documents = model.encode(DOCUMENTS, is_query=False)
+
+    def progress(_: dict[str, int]) -> None:
+        time.sleep(0.001)
+
+    thread = threading.Thread(
+        target=model.encode,
+        args=(MANY_DOCUMENTS,),
+        kwargs={"is_query": False, "batch_size": 1, "progress": progress},
+    )
+    thread.start()
+    while thread.is_alive():
+        assert model.info()["embedding_dim"] == documents.shape[2]
+        assert len(model.similarity(queries, documents)[0]) == len(DOCUMENTS)
+        model.tokenize(DOCUMENTS, is_query=False)
+    thread.join(timeout=60)
+    assert not thread.is_alive()
@@ -0,0 +1,57 @@
+"""Tests for `ColBERT.raw_similarity`, `ColBERT.tokenize` and `hierarchical_pooling`."""
+
+from __future__ import annotations
+
+import numpy as np
+from pylate_rs import models
+
+DOCUMENTS = [
+    "paris is the capital of france",
+    "berlin is the capital of germany",
+    "the sun is a star",
+]
+QUERIES = ["what is the capital of france?", "how big is the sun?"]
+
+
+def test_raw_similarity(model: models.ColBERT) -> None:
+    """The token-level matrix is aligned with the tokens and reduces to MaxSim."""
+    raw = model.raw_similarity(QUERIES, DOCUMENTS)
+    matrix = raw["similarity_matrix"]
+    assert raw["query_tokens"] == model.tokenize(QUERIES, is_query=True)
+    assert raw["document_tokens"] == model.tokenize(DOCUMENTS, is_query=False)
+    assert matrix.shape == (
+        len(QUERIES),
+        len(DOCUMENTS),
+        max(len(tokens) for tokens in raw["query_tokens"]),
+        max(len(tokens) for tokens in raw["document_tokens"]),
+    )
+
+    scores = model.similarity(
+        model.encode(QUERIES, is_query=True),
+        model.encode(DOCUMENTS, is_query=False),
+    )
+    np.testing.assert_allclose(matrix.max(axis=3).sum(axis=2), scores, atol=1e-4)
+
+
+def test_tokenize(model: models.ColBERT) -> None:
+    """Tokens drop padding, and the skiplist words of documents only."""
+    [query_tokens] = model.tokenize(["what is the sun?"], is_query=True)
+    [document_tokens] = model.tokenize(["what is the sun?"], is_query=False)
+    assert "[PAD]" not in query_tokens
+    assert "?" in query_tokens
+    assert "?" not in document_tokens
+    assert {"sun", "[Q]"} <= set(query_tokens)
+    assert {"sun", "[D]"} <= set(document_tokens)
+
+
+def test_hierarchical_pooling(model: models.ColBERT) -> None:
+    """Pooling divides the number of rows and keeps the rank of its input."""
+    embeddings = model.encode(DOCUMENTS, is_query=False)
+    pooled = models.hierarchical_pooling(embeddings, pool_factor=2)
+    assert pooled.shape[0] == embeddings.shape[0]
+    assert pooled.shape[1] < embeddings.shape[1]
+    assert pooled.shape[2] == embeddings.shape[2]
+
+    single = models.hierarchical_pooling(embeddings[0], pool_factor=2)
+    assert single.ndim == 2
+    assert single.shape[1] == embeddings.shape[2]
@@ -0,0 +1,33 @@
+mod common;
+
+use anyhow::Result;
+use common::{sentences, Architecture, EMBEDDING_DIM_2};
+use pylate_rs::ColBERT;
+
+/// Checks that a saved model reloads with its weights and overridden settings.
+#[test]
+fn local_save_pretrained_test() -> Result<()> {
+    let path = common::write_colbert("save-pretrained", Architecture::Bert, true)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_query_length(6)
+        .with_document_length(5)
+        .with_skiplist_words(vec![])
+        .try_into()?;
+    let saved = path.join("saved");
+    model.save_pretrained(&saved)?;
+    assert!(saved.join("modules.json").exists());
+
+    // The reloaded model uses the saved settings without any override.
+    let mut reloaded: ColBERT = ColBERT::from(saved.to_str().unwrap()).try_into()?;
+    let documents = sentences(&["paris is the capital of france.", "the sun"]);
+    let queries = sentences(&["what is the capital of france"]);
+    for (texts, is_query) in [(&documents, false), (&queries, true)] {
+        let expected = model.encode(texts, is_query)?;
+        let embeddings = reloaded.encode(texts, is_query)?;
+        assert_eq!(embeddings.dims(), expected.dims());
+        assert_eq!(embeddings.to_vec3::<f32>()?, expected.to_vec3::<f32>()?);
+    }
+    assert_eq!(reloaded.encode(&queries, true)?.dim(1)?, 6);
+    assert_eq!(reloaded.encode(&documents, false)?.dim(2)?, EMBEDDING_DIM_2);
+    Ok(())
+}
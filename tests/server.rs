@@ -0,0 +1,177 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use anyhow::Result;
+use common::{Architecture, EMBEDDING_DIM, QUERY_LENGTH};
+use pylate_rs::{
+    ColBERT, EmbeddingServer, EncodeOutput, ModelInfo, RankedDocument, ServerConfig, Similarities,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+    time::Duration,
+};
+
+fn start_server(name: &str, do_query_expansion: bool) -> Result<(EmbeddingServer, String)> {
+    let path = common::write_colbert(name, Architecture::Bert, false)?;
+    let model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_do_query_expansion(do_query_expansion)
+        .try_into()?;
+    let config = ServerConfig {
+        model_id: Some(name.to_string()),
+        batch_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let server = EmbeddingServer::start(model, "127.0.0.1:0", config)?;
+    let url = format!("http://{}", server.addr().unwrap());
+    Ok((server, url))
+}
+
+/// Sends a JSON body and returns the status code and body of the response.
+fn post(url: &str, body: Value) -> Result<(u16, String)> {
+    let request = ureq::post(url).set("Content-Type", "application/json");
+    match request.send_string(&body.to_string()) {
+        Ok(response) => Ok((response.status(), response.into_string()?)),
+        Err(ureq::Error::Status(status, response)) => Ok((status, response.into_string()?)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Sends a JSON body and parses the response of a successful request.
+fn post_json<T: DeserializeOwned>(url: &str, body: Value) -> Result<T> {
+    let (status, body) = post(url, body)?;
+    assert_eq!(status, 200, "{}", body);
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Checks the health, info, encode, similarity and rerank endpoints on localhost.
+#[test]
+fn local_server_endpoints_test() -> Result<()> {
+    let (server, url) = start_server("server-endpoints", true)?;
+
+    let health: Value =
+        serde_json::from_str(&ureq::get(&format!("{url}/health")).call()?.into_string()?)?;
+    assert_eq!(health, json!({ "status": "ok" }));
+
+    let info: ModelInfo =
+        serde_json::from_str(&ureq::get(&format!("{url}/info")).call()?.into_string()?)?;
+    assert_eq!(info.model_id.as_deref(), Some("server-endpoints"));
+    assert_eq!(info.embedding_dim, EMBEDDING_DIM);
+    assert_eq!(info.query_length, QUERY_LENGTH);
+
+    // Documents are returned without their padding rows.
+    let output: EncodeOutput = post_json(
+        &format!("{url}/encode"),
+        json!({ "sentences": ["paris is the capital of france", "the sun"] }),
+    )?;
+    assert_eq!(output.embeddings[0].len(), 9);
+    assert_eq!(output.embeddings[1].len(), 5);
+    assert_eq!(output.embeddings[0][0].len(), EMBEDDING_DIM);
+
+    let output: EncodeOutput = post_json(
+        &format!("{url}/encode"),
+        json!({ "sentences": ["what is the sun"], "is_query": true }),
+    )?;
+    assert_eq!(output.embeddings[0].len(), QUERY_LENGTH);
+
+    let output: EncodeOutput = post_json(
+        &format!("{url}/encode"),
+        json!({ "sentences": ["paris is the capital of france"], "pool_factor": 2 }),
+    )?;
+    assert!(output.embeddings[0].len() < 9);
+
+    let documents = ["the sun is a star", "paris is the capital of france"];
+    let similarities: Similarities = post_json(
+        &format!("{url}/similarity"),
+        json!({ "queries": ["capital of france"], "documents": documents }),
+    )?;
+    assert_eq!(similarities.data.len(), 1);
+    assert_eq!(similarities.data[0].len(), 2);
+
+    let ranked: Vec<RankedDocument> = post_json(
+        &format!("{url}/rerank"),
+        json!({ "query": "capital of france", "documents": documents, "top_k": 1 }),
+    )?;
+    assert_eq!(ranked.len(), 1);
+    let best = similarities.data[0]
+        .iter()
+        .cloned()
+        .fold(f32::MIN, f32::max);
+    assert!((ranked[0].score - best).abs() < 1e-4);
+
+    // Invalid requests and unknown endpoints are reported with their status code.
+    let (status, _) = post(&format!("{url}/encode"), json!({ "sentences": [] }))?;
+    assert_eq!(status, 400);
+    match ureq::get(&format!("{url}/missing")).call() {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+        other => panic!("expected a 404 response, got {:?}", other.map(|_| ())),
+    }
+
+    server.shutdown();
+
+    // Without query expansion, queries are padded with zero rows, which are dropped.
+    let (server, url) = start_server("server-no-expansion", false)?;
+    let output: EncodeOutput = post_json(
+        &format!("{url}/encode"),
+        json!({ "sentences": ["what is the sun"], "is_query": true }),
+    )?;
+    assert_eq!(output.embeddings[0].len(), 7);
+    server.shutdown();
+    Ok(())
+}
+
+/// Checks that concurrent callers are encoded in shared batches and get their own
+/// embeddings back.
+#[test]
+fn local_server_concurrent_batching_test() -> Result<()> {
+    let (server, url) = start_server("server-batching", true)?;
+    let texts = [
+        "the sun",
+        "paris is the capital of france",
+        "a star",
+        "the capital",
+    ];
+
+    let expected: Vec<EncodeOutput> = texts
+        .iter()
+        .map(|text| post_json(&format!("{url}/encode"), json!({ "sentences": [text] })))
+        .collect::<Result<_>>()?;
+
+    let before = server.stats();
+    assert_eq!(before.jobs, texts.len() as u64);
+
+    let barrier = Arc::new(Barrier::new(texts.len()));
+    let handles: Vec<_> = texts
+        .iter()
+        .map(|text| {
+            let url = url.clone();
+            let text = text.to_string();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || -> Result<EncodeOutput> {
+                barrier.wait();
+                post_json(&format!("{url}/encode"), json!({ "sentences": [text] }))
+            })
+        })
+        .collect();
+
+    for (handle, expected) in handles.into_iter().zip(expected) {
+        let output = handle.join().unwrap()?;
+        let embeddings = &output.embeddings[0];
+        assert_eq!(embeddings.len(), expected.embeddings[0].len());
+        for (row, expected_row) in embeddings.iter().zip(&expected.embeddings[0]) {
+            for (value, expected_value) in row.iter().zip(expected_row) {
+                assert!((value - expected_value).abs() < 1e-4);
+            }
+        }
+    }
+
+    let after = server.stats();
+    assert_eq!(after.jobs - before.jobs, texts.len() as u64);
+    assert!(after.model_calls - before.model_calls < texts.len() as u64);
+
+    server.shutdown();
+    Ok(())
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use common::{sentences, Architecture, EMBEDDING_DIM};
+use pylate_rs::{ColBERT, ColbertError, EmbeddingCollection, EmbeddingStore};
+use std::collections::HashMap;
+
+/// Checks that a collection written to disk is read back identically through the store.
+#[test]
+fn local_storage_roundtrip_test() -> Result<()> {
+    let path = common::write_colbert("storage", Architecture::Bert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap()).try_into()?;
+
+    let documents = sentences(&["paris is the capital of france", "the sun is a star"]);
+    let embeddings = model.encode(&documents, false)?;
+
+    let mut collection = EmbeddingCollection::new();
+    collection.extend_from_batch(
+        &sentences(&["doc-0", "doc-1"]),
+        &embeddings,
+        &[
+            serde_json::json!({ "title": "Paris" }),
+            serde_json::Value::Null,
+        ],
+    )?;
+    let file = path.join("collection.safetensors");
+    collection.save(&file)?;
+
+    let store = EmbeddingStore::open(&file)?;
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.embedding_dim(), EMBEDDING_DIM);
+    assert_eq!(store.ids(), &["doc-0", "doc-1"]);
+    assert_eq!(
+        store.metadata(0),
+        Some(&serde_json::json!({ "title": "Paris" }))
+    );
+
+    // The second document is shorter and loses its padding row.
+    let first = store.embeddings(0, &Device::Cpu)?;
+    let second = store.embeddings(1, &Device::Cpu)?;
+    assert_eq!(first.dims(), &[9, EMBEDDING_DIM]);
+    assert_eq!(second.dims(), &[8, EMBEDDING_DIM]);
+    assert_eq!(
+        second.to_vec2::<f32>()?,
+        embeddings.get(1)?.narrow(0, 0, 8)?.to_vec2::<f32>()?
+    );
+    assert!(matches!(
+        store.embeddings(2, &Device::Cpu),
+        Err(ColbertError::IndexOutOfRange { index: 2, len: 2 })
+    ));
+
+    // Searching the store matches the scores of each document without its padding
+    // rows, which would win the maximum of query tokens with only negative similarities.
+    let queries = sentences(&["capital of france", "a star"]);
+    let query_embeddings = model.encode(&queries, true)?;
+    let mut expected = vec![Vec::new(); queries.len()];
+    for document in [&first, &second] {
+        let scores = model.similarity(&query_embeddings, &document.unsqueeze(0)?)?;
+        for (query_scores, score) in expected.iter_mut().zip(scores.data) {
+            query_scores.push(score[0]);
+        }
+    }
+    let results = store.search(&query_embeddings, 1)?;
+    assert_eq!(results.len(), 2);
+    for (ranked, scores) in results.iter().zip(&expected) {
+        assert_eq!(ranked.len(), 1);
+        let best = scores.iter().cloned().fold(f32::MIN, f32::max);
+        assert!((ranked[0].score - best).abs() < 1e-4);
+        assert!((scores[ranked[0].index] - best).abs() < 1e-4);
+    }
+
+    // Loading the file in memory, as the WASM build does, gives the same collection.
+    let loaded = EmbeddingCollection::from_bytes(&std::fs::read(&file)?)?;
+    assert_eq!(loaded.ids(), store.ids());
+    assert_eq!(loaded.metadata(0), store.metadata(0));
+    assert_eq!(loaded.search(&query_embeddings, 1)?, results);
+    assert!(matches!(
+        EmbeddingCollection::from_bytes(&std::fs::read(path.join("model.safetensors"))?),
+        Err(ColbertError::MalformedFile { field, .. }) if field == "format"
+    ));
+
+    // A loaded collection can be appended to and written again.
+    let mut appended = loaded;
+    appended.push(
+        "doc-2".to_string(),
+        first.clone(),
+        serde_json::json!({ "title": "Copy" }),
+    )?;
+    let file = path.join("appended.safetensors");
+    appended.save(&file)?;
+    let store = EmbeddingStore::open(&file)?;
+    assert_eq!(store.ids(), &["doc-0", "doc-1", "doc-2"]);
+    assert_eq!(
+        store.metadata(2),
+        Some(&serde_json::json!({ "title": "Copy" }))
+    );
+    assert_eq!(
+        store.embeddings(2, &Device::Cpu)?.to_vec2::<f32>()?,
+        first.to_vec2::<f32>()?
+    );
+    Ok(())
+}
+
+/// Checks that ids and metadata are stored as tensors, keeping the header small.
+#[test]
+fn local_storage_header_test() -> Result<()> {
+    let device = Device::Cpu;
+    let mut collection = EmbeddingCollection::new();
+    for index in 0..1000 {
+        collection.push(
+            format!("document-{index}-é"),
+            Tensor::ones((2, EMBEDDING_DIM), DType::F32, &device)?,
+            serde_json::json!({ "text": "a long text ".repeat(10), "index": index }),
+        )?;
+    }
+    let bytes = collection.to_bytes()?;
+
+    let (header_size, header) = safetensors::SafeTensors::read_metadata(&bytes)?;
+    assert!(header_size < 1024, "the header takes {header_size} bytes");
+    assert_eq!(
+        header.metadata().as_ref().map(|metadata| metadata.len()),
+        Some(2)
+    );
+
+    let loaded = EmbeddingCollection::from_bytes(&bytes)?;
+    assert_eq!(loaded.ids(), collection.ids());
+    assert_eq!(loaded.metadata(999), collection.metadata(999));
+    Ok(())
+}
+
+/// Checks that files written with ids and metadata in the header, as by version 1 of
+/// the format, are read and rejected when their tensors disagree with the header.
+#[test]
+fn local_storage_malformed_test() -> Result<()> {
+    let dir = common::fixture_dir("storage-malformed");
+    let write = |name: &str, embeddings: Tensor, lengths: Tensor| -> Result<_> {
+        let header = HashMap::from([
+            ("format".to_string(), "pylate-rs-embeddings".to_string()),
+            ("version".to_string(), "1".to_string()),
+            ("ids".to_string(), r#"["doc-0","doc-1"]"#.to_string()),
+            ("metadata".to_string(), "[null,null]".to_string()),
+        ]);
+        let bytes = safetensors::serialize(
+            [("embeddings", &embeddings), ("lengths", &lengths)],
+            Some(header),
+        )?;
+        let file = dir.join(name);
+        std::fs::write(&file, &bytes)?;
+        Ok((file, bytes))
+    };
+    let device = Device::Cpu;
+    let rows = |n: usize| Tensor::zeros((n, EMBEDDING_DIM), DType::F32, &device);
+    let lengths = Tensor::new(&[2u32, 3], &device)?;
+
+    let (valid, valid_bytes) = write("valid", rows(5)?, lengths.clone())?;
+    assert_eq!(EmbeddingStore::open(&valid)?.len(), 2);
+    assert_eq!(EmbeddingCollection::from_bytes(&valid_bytes)?.len(), 2);
+
+    let files = [
+        (write("truncated", rows(4)?, lengths.clone())?, "embeddings"),
+        (
+            write("f16", rows(5)?.to_dtype(DType::F16)?, lengths.clone())?,
+            "embeddings",
+        ),
+        (
+            write("rank3", rows(5)?.unsqueeze(0)?, lengths.clone())?,
+            "embeddings",
+        ),
+        (
+            write("f32-lengths", rows(5)?, lengths.to_dtype(DType::F32)?)?,
+            "lengths",
+        ),
+    ];
+    for ((file, bytes), expected) in &files {
+        assert!(matches!(
+            EmbeddingStore::open(file),
+            Err(ColbertError::MalformedFile { field, .. }) if field == *expected
+        ));
+        assert!(matches!(
+            EmbeddingCollection::from_bytes(bytes),
+            Err(ColbertError::MalformedFile { field, .. }) if field == *expected
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that files whose id or metadata tensors are inconsistent are rejected.
+#[test]
+fn local_storage_malformed_entries_test() -> Result<()> {
+    let device = Device::Cpu;
+    let write = |ids: &[u8], id_lengths: &[u32], metadata: &[u8]| -> Result<Vec<u8>> {
+        let header = HashMap::from([
+            ("format".to_string(), "pylate-rs-embeddings".to_string()),
+            ("version".to_string(), "2".to_string()),
+        ]);
+        Ok(safetensors::serialize(
+            [
+                (
+                    "embeddings",
+                    &Tensor::zeros((5, EMBEDDING_DIM), DType::F32, &device)?,
+                ),
+                ("lengths", &Tensor::new(&[2u32, 3], &device)?),
+                ("ids", &Tensor::new(ids, &device)?),
+                ("id_lengths", &Tensor::new(id_lengths, &device)?),
+                ("metadata", &Tensor::new(metadata, &device)?),
+                ("metadata_lengths", &Tensor::new(&[4u32, 2], &device)?),
+            ],
+            Some(header),
+        )?)
+    };
+
+    let loaded = EmbeddingCollection::from_bytes(&write(b"ab", &[1, 1], b"null{}")?)?;
+    assert_eq!(loaded.ids(), &["a", "b"]);
+    assert_eq!(loaded.metadata(1), Some(&serde_json::json!({})));
+
+    for (bytes, expected) in [
+        (write(b"abc", &[1, 1], b"null{}")?, "ids"),
+        (write(b"a\xff", &[1, 1], b"null{}")?, "ids"),
+        (write(b"ab", &[1, 1], b"nul{}")?, "metadata"),
+    ] {
+        assert!(matches!(
+            EmbeddingCollection::from_bytes(&bytes),
+            Err(ColbertError::MalformedFile { field, .. }) if field == expected
+        ));
+    }
+    // Ids and lengths disagree on the number of documents.
+    assert!(matches!(
+        EmbeddingCollection::from_bytes(&write(b"abc", &[1, 1, 1], b"null{}")?),
+        Err(ColbertError::MalformedFile { field, .. }) if field == "lengths"
+    ));
+    Ok(())
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::Tensor;
+use common::{sentences, Architecture, EMBEDDING_DIM, QUERY_LENGTH};
+use pylate_rs::{CancellationToken, ColBERT, ColbertError, EmbeddingCollection, EncodeProgress};
+use std::sync::{Arc, Mutex};
+
+/// Checks that streamed batches match `encode` on the same sentences.
+#[test]
+fn local_encode_stream_test() -> Result<()> {
+    let path = common::write_colbert("stream", Architecture::ModernBert, false)?;
+    let mut model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(2)
+        .with_pool_factor(2)
+        .try_into()?;
+    let max_difference = |a: &Tensor, b: &Tensor| -> Result<f32> {
+        Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+    };
+
+    let documents = sentences(&[
+        "paris is the capital of france",
+        "berlin is the capital of germany this is a test",
+        "the sun is a star",
+        "how big is the sun",
+        "a star",
+    ]);
+    let items: Vec<(String, String)> = documents
+        .iter()
+        .enumerate()
+        .map(|(i, text)| (format!("doc-{i}"), text.clone()))
+        .collect();
+
+    let batches = model
+        .encode_stream(items.clone(), false, 1)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(batches.len(), 3);
+    let mut collection = EmbeddingCollection::new();
+    for (batch, chunk) in batches.iter().zip(documents.chunks(2)) {
+        let expected = model.encode(chunk, false)?;
+        assert_eq!(batch.embeddings.dims(), expected.dims());
+        assert!(max_difference(&batch.embeddings, &expected)? < 1e-4);
+        collection.extend_from_batch(&batch.ids, &batch.embeddings, &[])?;
+    }
+    assert_eq!(collection.len(), 5);
+    assert_eq!(
+        collection.ids(),
+        &["doc-0", "doc-1", "doc-2", "doc-3", "doc-4"]
+    );
+
+    let queries = model
+        .encode_stream(items.into_iter().take(3), true, 4)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(queries[1].ids, &["doc-2"]);
+    assert_eq!(
+        queries[0].embeddings.dims(),
+        &[2, QUERY_LENGTH, EMBEDDING_DIM]
+    );
+
+    assert!(model.encode_stream(Vec::new(), false, 1).next().is_none());
+    Ok(())
+}
+
+/// Checks that streams report their progress and stop once cancelled.
+#[test]
+fn local_encode_stream_progress_test() -> Result<()> {
+    let path = common::write_colbert("stream-progress", Architecture::Bert, false)?;
+    let reports = Arc::new(Mutex::new(Vec::<EncodeProgress>::new()));
+    let recorded = reports.clone();
+    let cancellation = CancellationToken::new();
+    let model: ColBERT = ColBERT::from(path.to_str().unwrap())
+        .with_batch_size(2)
+        .with_progress_callback(Arc::new(move |progress: &EncodeProgress| {
+            recorded.lock().unwrap().push(progress.clone())
+        }))
+        .with_cancellation_token(cancellation.clone())
+        .try_into()?;
+
+    let items: Vec<(String, String)> = (0..5)
+        .map(|i| (format!("doc-{i}"), "the sun is a star".to_string()))
+        .collect();
+    model
+        .encode_stream(items.clone(), false, 1)
+        .collect::<Result<Vec<_>, _>>()?;
+    let streamed = std::mem::take(&mut *reports.lock().unwrap());
+    assert_eq!(streamed.len(), 3);
+    for (i, report) in streamed.iter().enumerate() {
+        assert_eq!(report.batches_done, i + 1);
+        assert_eq!((report.total_batches, report.total_sentences), (3, 5));
+    }
+    assert_eq!(streamed[2].sentences_done, 5);
+
+    // Inputs of unknown length report totals of zero.
+    model
+        .encode_stream(items.clone().into_iter().filter(|_| true), false, 1)
+        .collect::<Result<Vec<_>, _>>()?;
+    let streamed = std::mem::take(&mut *reports.lock().unwrap());
+    assert_eq!(streamed.len(), 3);
+    assert_eq!(
+        (streamed[2].total_batches, streamed[2].total_sentences),
+        (0, 0)
+    );
+
+    let mut stream = model.encode_stream(items, false, 1);
+    assert!(stream.next().unwrap().is_ok());
+    cancellation.cancel();
+    assert!(matches!(stream.next(), Some(Err(ColbertError::Cancelled))));
+    assert!(stream.next().is_none());
+    Ok(())
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use anyhow::Result;
+use candle_core::Device;
+use common::{sentences, Architecture};
+use pylate_rs::{
+    ColBERT, ColbertError, ColbertTrainer, ContrastiveExample, DistillationExample, TrainingConfig,
+};
+
+/// Fine-tunes a ModernBERT fixture with both losses and reloads the saved checkpoint.
+#[test]
+fn local_training_test() -> Result<()> {
+    let path = common::write_colbert("training", Architecture::ModernBert, true)?;
+    let config = TrainingConfig {
+        learning_rate: 1e-2,
+        ..Default::default()
+    };
+    let mut trainer = ColbertTrainer::new(ColBERT::from(path.to_str().unwrap()), config)?;
+
+    let contrastive = [
+        ContrastiveExample {
+            query: "capital of france".to_string(),
+            positive: "paris is the capital of france".to_string(),
+            negatives: vec!["the sun is a star".to_string()],
+        },
+        ContrastiveExample {
+            query: "how big is the sun".to_string(),
+            positive: "the sun is a star".to_string(),
+            negatives: vec![],
+        },
+    ];
+    let first = trainer.contrastive_step(&contrastive)?;
+    let mut last = first;
+    for _ in 0..10 {
+        last = trainer.contrastive_step(&contrastive)?;
+    }
+    assert!(last < first, "contrastive loss went from {first} to {last}");
+
+    let distillation = [DistillationExample {
+        query: "capital of france".to_string(),
+        documents: sentences(&["paris is the capital of france", "the sun is a star"]),
+        scores: vec![0.0, 5.0],
+    }];
+    let first = trainer.distillation_step(&distillation)?;
+    let mut last = first;
+    for _ in 0..10 {
+        last = trainer.distillation_step(&distillation)?;
+    }
+    assert!(
+        last < first,
+        "distillation loss went from {first} to {last}"
+    );
+
+    let uneven = [DistillationExample {
+        scores: vec![1.0],
+        ..distillation[0].clone()
+    }];
+    assert!(matches!(
+        trainer.distillation_step(&uneven),
+        Err(ColbertError::DimensionMismatch { .. })
+    ));
+
+    // The checkpoint loads through the builder and encodes like the trained model.
+    let checkpoint = path.join("checkpoint");
+    trainer.save(&checkpoint)?;
+    let mut reloaded: ColBERT = ColBERT::from(checkpoint.to_str().unwrap()).try_into()?;
+    let documents = sentences(&["paris is the capital of france", "the sun is a star"]);
+    let queries = sentences(&["capital of france"]);
+    for (texts, is_query) in [(&documents, false), (&queries, true)] {
+        let expected = trainer.model().encode(texts, is_query)?;
+        let embeddings = reloaded.encode(texts, is_query)?;
+        assert_eq!(embeddings.dims(), expected.dims());
+        let diff = (embeddings - expected)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+    }
+
+    Ok(())
+}
+
+/// Fine-tunes a BERT fixture, whose layer normalizations must let gradients through.
+#[test]
+fn bert_training_test() -> Result<()> {
+    let path = common::write_colbert("training-bert", Architecture::Bert, false)?;
+    let config = TrainingConfig {
+        learning_rate: 1e-2,
+        ..Default::default()
+    };
+    let mut trainer = ColbertTrainer::new(ColBERT::from(path.to_str().unwrap()), config)?;
+
+    let contrastive = [
+        ContrastiveExample {
+            query: "capital of france".to_string(),
+            positive: "paris is the capital of france".to_string(),
+            negatives: vec!["the sun is a star".to_string()],
+        },
+        ContrastiveExample {
+            query: "how big is the sun".to_string(),
+            positive: "the sun is a star".to_string(),
+            negatives: vec![],
+        },
+    ];
+    let first = trainer.contrastive_step(&contrastive)?;
+    let mut last = first;
+    for _ in 0..10 {
+        last = trainer.contrastive_step(&contrastive)?;
+    }
+    assert!(last < first, "contrastive loss went from {first} to {last}");
+
+    // The word embeddings sit below every layer normalization of the backbone.
+    let checkpoint = path.join("checkpoint");
+    trainer.save(&checkpoint)?;
+    let name = "embeddings.word_embeddings.weight";
+    let before =
+        &candle_core::safetensors::load(path.join("model.safetensors"), &Device::Cpu)?[name];
+    let after =
+        &candle_core::safetensors::load(checkpoint.join("model.safetensors"), &Device::Cpu)?[name];
+    let diff = (after - before)?.abs()?.max_all()?.to_scalar::<f32>()?;
+    assert!(diff > 0.0, "the word embeddings were not updated");
+    Ok(())
+}